@@ -1,20 +1,12 @@
 // main.rs: This is the server (should've probably called it server.rs lmao)
 
 // server stuff
-use actix_web::{post, get, web, http, App, HttpResponse, HttpServer, Responder, Error};
+use actix_web::{post, get, patch, delete, route, web, http, App, HttpRequest, HttpResponse, HttpServer, Responder, Error};
 use actix_cors::Cors;
 use serde::{Serialize, Deserialize};
-use reqwest::Client;
-use serde_json::Value;
-use rusqlite::Connection;
-use std::collections::HashSet;
-use std::path::Path;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use std::sync::Arc;
 
 
-use uuid::Uuid;
-use sanitize_filename::sanitize;
 use actix_multipart::Multipart;
 use futures_util::StreamExt;
 
@@ -22,16 +14,30 @@ use futures_util::StreamExt;
 
 // structs
 use ai_engine::{ExtractedMetaData, FileRecord};
+use ai_engine::Error as ArchiveError;
 
 // functionality
-use ai_engine::get_meta_data_response;
-use ai_engine::package_hash_and_cid;
+use ai_engine::{build_answerer, build_extractor, Answerer, ChunkConfig, LlmConfig, MetadataExtractor};
+use ai_engine::{JobConfig, JobQueue};
+use ai_engine::{admit_batch, discard_received, receive_file, FileResult, Received, UploadConfig, UPLOAD_DIR};
+use ai_engine::UploadSessions;
 
 // the database
-use ai_engine::add_to_or_create_database;
+use ai_engine::{ArchiveRecord, ArchiveRepository, MetadataPatch, RecordQuery, RecordSearch, RecordSort};
 
 // the solana
-use ai_engine::send_memo;
+use ai_engine::{MemoSender, SolanaConfig, parse_memo};
+
+// where the archived files live
+use ai_engine::{build_content_store, ContentStore, ContentStoreConfig};
+use ai_engine::{content_format, content_type, download_filename, etag, etag_matches, parse_range, stored_sha256, DownloadCache};
+use ai_engine::{ArchivedFile, RangeRequest};
+use ai_engine::hash::Sha256Stream;
+
+// the vector search
+use ai_engine::{build_embedder, EmbeddingConfig, IndexSync, PassageIndex, SemanticIndex};
+use ai_engine::{hybrid_search, HybridQuery, passage_search, PassageQuery};
+use ai_engine::{ask, AskQuery};
 
 
 // Request and Response Schema
@@ -55,27 +61,85 @@ struct VectorSearchRequest{
     k: Option<usize>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct VectorSearchResult{
-    id: Vec<Vec<String>>,
-    documents: Vec<Vec<String>>,
-    metadatas: Vec<Vec<Value>>,
-    distances: Vec<Vec<f32>>
-}
 
 
 
-// TODO: should we find a better work around this?
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ArchiveRecord{
+// an archived copy of a file, as returned by the verify-by-hash endpoints
+#[derive(Debug, Serialize)]
+struct HashMatch {
     id: i64,
-    genre: String,
     title: String,
-    difficulty: String,
-    summary: String,
     file_hash: String,
     file_cid: String,
+    solana_signature: Option<String>,
+    solana_slot: Option<i64>,
+    solana_block_time: Option<i64>,
+}
+
+// query string of /metadata
+#[derive(Debug, Deserialize)]
+struct ListParams {
+    genre: Option<String>,
+    difficulty: Option<String>,
+    sort: Option<String>,  // id | title | created
+    order: Option<String>, // asc | desc
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+// query string of /search/hybrid
+#[derive(Debug, Deserialize)]
+struct HybridSearchParams {
+    q: String,
+    genre: Option<String>,
+    difficulty: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
 }
+
+// query string of /search/passages
+#[derive(Debug, Deserialize)]
+struct PassageSearchParams {
+    q: String,
+    record_id: Option<i64>,
+    limit: Option<usize>,
+}
+
+// body of /ask
+#[derive(Debug, Deserialize)]
+struct AskRequest {
+    question: String,
+    record_id: Option<i64>,
+    limit: Option<usize>,
+}
+
+// query options for /api/upload
+#[derive(Debug, Deserialize)]
+struct UploadOptions {
+    // re-run metadata extraction for a file we already hold (no new ipfs add or memo)
+    #[serde(default)]
+    force: bool,
+}
+
+// body of POST /api/uploads; size is optional, but a session opened with one can't complete short of it
+#[derive(Debug, Deserialize)]
+struct OpenUploadRequest {
+    filename: Option<String>,
+    content_type: Option<String>,
+    size: Option<u64>,
+}
+
+// query options for DELETE /metadata/{id}
+#[derive(Debug, Deserialize)]
+struct DeleteOptions {
+    // stop pinning the file in the content store
+    #[serde(default)]
+    unpin: bool,
+    // post a memo revoking the record's anchor
+    #[serde(default)]
+    revoke: bool,
+}
+
 // TODO: change this dir to something better
 const DB_NAME: &str = "archive.db";
 
@@ -87,360 +151,912 @@ async fn hello() -> impl Responder {
 
 // get the metadata from the database
 #[get("/metadata")]
-// list all metadata we have
-async fn list_all() -> impl Responder {
-    let db_name = DB_NAME.to_string();
-    let res = web::block(move || -> Result<Vec<(String, String, String, String, String, String)>, anyhow::Error> {
-        let conn = Connection::open(db_name)?;
-        let mut stmt = conn.prepare("SELECT id, genre, title, difficulty, summary, file_hash, file_cid FROM archive").ok();
-        if stmt.is_none() {
-            return Ok(vec![]);
-        }
-        let mut stmt = stmt.unwrap();
-        let mut rows = stmt.query([])?;
-        let mut out = Vec::new();
-        while let Some(row) = rows.next()? {
-            let id: i64 = row.get(0)?;
-            let genre_val: String = row.get(1)?;
-            let title: String = row.get(2)?;
-            let difficulty_val: String = row.get(3)?;
-            let summary: String = row.get(4)?;
-            let file_hash: String = row.get(5)?;
-            let file_cid: String = row.get(6)?;
-            out.push((id.to_string(), genre_val, title, difficulty_val, summary, format!("{}|{}", file_hash, file_cid)));
+// list the records a page at a time, same shape as /metadata/{id}
+async fn list_all(params: web::Query<ListParams>, repo: web::Data<ArchiveRepository>) -> Result<HttpResponse, Error> {
+    let params = params.into_inner();
+    let descending = match params.order.as_deref().map(str::to_ascii_lowercase).as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(other) => return Err(ArchiveError::InvalidInput(format!("order '{}' must be asc or desc", other)).into()),
+    };
+
+    let query = RecordQuery {
+        genre: params.genre.filter(|g| !g.is_empty()),
+        difficulty: params.difficulty.filter(|d| !d.is_empty()),
+        sort: params.sort.as_deref().map(RecordSort::from_name).transpose()?.unwrap_or_default(),
+        descending,
+        offset: params.offset.unwrap_or(0),
+        limit: params.limit.unwrap_or(50),
+    };
+    let page = web::block(move || repo.list(&query)).await??;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+#[get("/metadata/{id}")]
+async fn get_entry_by_id(path: web::Path<i64>, repo: web::Data<ArchiveRepository>) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+
+    match web::block(move || repo.get(id)).await?? {
+        Some(record) => Ok(HttpResponse::Ok().json(record)),
+        None => Err(record_missing(id)),
+    }
+}
+
+// hand edits to a record's metadata; only the fields given change, and each change is logged with
+// the value it replaced
+#[patch("/metadata/{id}")]
+async fn edit_entry(
+    path: web::Path<i64>,
+    payload: web::Json<MetadataPatch>,
+    index_sync: web::Data<IndexSync>,
+    repo: web::Data<ArchiveRepository>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let patch = payload.into_inner();
+
+    let writer = repo.clone();
+    let edits = match web::block(move || writer.edit(id, &patch)).await? {
+        Ok(edits) => edits,
+        Err(ArchiveError::Database(rusqlite::Error::QueryReturnedNoRows)) => {
+            return Err(record_missing(id));
         }
-        Ok(out)
-    })
-        .await;
+        Err(e) => return Err(e.into()),
+    };
+    // the edit queued the record for re-embedding
+    if !edits.is_empty() {
+        index_sync.notify();
+    }
+
+    let Some(record) = web::block(move || repo.get(id)).await?? else {
+        return Err(record_missing(id));
+    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "record": record,
+        "edits": edits,
+    })))
+}
+
+// a record's hand edits, oldest first, plus what each edited field held before the first of them
+// (the model's output)
+#[get("/metadata/{id}/history")]
+async fn entry_history(path: web::Path<i64>, repo: web::Data<ArchiveRepository>) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
 
-    match res {
-        Ok(Ok(rows)) => HttpResponse::Ok().json(rows),
-        Ok(Err(e)) => HttpResponse::InternalServerError().body(format!("db error: {}", e)),
-        Err(e) => HttpResponse::InternalServerError().body(format!("blocking error: {}", e)),
+    let reader = repo.clone();
+    let Some(record) = web::block(move || reader.get(id)).await?? else {
+        return Err(record_missing(id));
+    };
+    let edits = web::block(move || repo.history(id)).await??;
+
+    let mut original = serde_json::Map::new();
+    for edit in &edits {
+        original.entry(edit.field.clone()).or_insert_with(|| edit.old_value.clone());
     }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "record_id": id,
+        "current": record,
+        "original": original,
+        "edits": edits,
+    })))
 }
 
-#[get("/metadata/{id}")]
-async fn get_entry_by_id(path: web::Path<i64>) -> impl Responder {
+// withdraws a record: it's gone from listings, search and analytics straight away, the row itself
+// stays for the audit trail. unpinning and revoking happen after the delete; if either fails that's
+// reported, the delete still stands
+#[delete("/metadata/{id}")]
+async fn delete_entry(
+    path: web::Path<i64>,
+    options: web::Query<DeleteOptions>,
+    memo_sender: web::Data<MemoSender>,
+    store: web::Data<Arc<dyn ContentStore>>,
+    index_sync: web::Data<IndexSync>,
+    repo: web::Data<ArchiveRepository>,
+) -> Result<HttpResponse, Error> {
     let id = path.into_inner();
 
-    let res = web::block(move || -> Result<ArchiveRecord, rusqlite::Error> {
-        let conn = Connection::open(DB_NAME)?;
-        conn.query_row(
-            "SELECT id, genre, title, difficulty, summary, file_hash, file_cid FROM archive WHERE id = ?1",
-            [id],
-            |row| {
-                Ok(ArchiveRecord {
-                    id: row.get(0)?,
-                    genre: row.get(1)?,
-                    title: row.get(2)?,
-                    difficulty: row.get(3)?,
-                    summary: row.get(4)?,
-                    file_hash: row.get(5)?,
-                    file_cid: row.get(6)?,
-                })
-            },
-        )
-    })
-        .await;
+    let reader = repo.clone();
+    let Some(record) = web::block(move || reader.get(id)).await?? else {
+        return Err(record_missing(id));
+    };
+    let writer = repo.clone();
+    if !web::block(move || writer.delete(id)).await?? {
+        // deleted by someone else in the meantime
+        return Err(record_missing(id));
+    }
+    index_sync.notify();
+
+    let ArchiveRecord { file_hash, file_cid, solana_signature, .. } = record;
 
-    match res {
-        Ok(Ok(record)) => HttpResponse::Ok().json(record),
-        Ok(Err(rusqlite::Error::QueryReturnedNoRows)) => HttpResponse::NotFound().body("Record not found"),
-        Ok(Err(e)) => HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Blocking error: {}", e)),
+    let (mut unpinned, mut unpin_error) = (false, None);
+    if options.unpin {
+        let (reader, cid) = (repo.clone(), file_cid.clone());
+        if web::block(move || reader.cid_in_use(&cid)).await?? {
+            unpin_error = Some("another record still uses this cid, it stays pinned".to_string());
+        } else {
+            match store.unpin(&file_cid).await {
+                Ok(()) => unpinned = true,
+                Err(e) => unpin_error = Some(e.to_string()),
+            }
+        }
     }
+
+    let (mut revocation_signature, mut revocation_error) = (None, None);
+    if options.revoke {
+        match solana_signature.clone() {
+            None => revocation_error = Some("record was archived before signatures were stored, nothing to revoke".to_string()),
+            Some(original) => {
+                // the rpc client is blocking
+                let sender = memo_sender.clone().into_inner();
+                let hash = file_hash.clone();
+                let sent = tokio::task::spawn_blocking(move || sender.send_revocation(&hash, &original))
+                    .await
+                    .map_err(|e| ArchiveError::Io(std::io::Error::other(format!("memo task failed: {}", e))))?;
+                match sent {
+                    Ok(receipt) => {
+                        let (writer, signature) = (repo.clone(), receipt.signature.clone());
+                        web::block(move || writer.set_revocation(id, &signature)).await??;
+                        revocation_signature = Some(receipt.signature);
+                    }
+                    Err(e) => revocation_error = Some(e.to_string()),
+                }
+            }
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "record_id": id,
+        "deleted": true,
+        "file_hash": file_hash,
+        "file_cid": file_cid,
+        "unpinned": unpinned,
+        "unpin_error": unpin_error,
+        "solana_signature": solana_signature,
+        "revocation_signature": revocation_signature,
+        "revocation_error": revocation_error,
+    })))
 }
 
-#[get("/search")]
-async fn search_by_field(query: web::Query<std::collections::HashMap<String, String>>) -> impl Responder {
-    // required params: field, q
-    let field = match query.get("field") {
-        Some(f) => f.as_str(),
-        None => return HttpResponse::BadRequest().body("missing 'field' query param"),
+// checks a record's anchor end to end: the memo on chain, who signed it, and what the content store
+// serves for the cid (reported as ipfs_*, whichever store is configured)
+#[get("/verify/{id}")]
+async fn verify_entry(
+    path: web::Path<i64>,
+    memo_sender: web::Data<MemoSender>,
+    store: web::Data<Arc<dyn ContentStore>>,
+    repo: web::Data<ArchiveRepository>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+
+    let Some(record) = web::block(move || repo.get(id)).await?? else {
+        return Err(record_missing(id));
+    };
+    let ArchiveRecord { file_hash, file_cid, solana_signature, .. } = record;
+    let Some(signature) = solana_signature else {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "id": id,
+            "verified": false,
+            "message": "record was archived before signatures were stored, nothing to verify against",
+        })));
+    };
+
+    // the rpc client is blocking
+    let sender = memo_sender.clone().into_inner();
+    let sig = signature.clone();
+    let onchain = tokio::task::spawn_blocking(move || sender.fetch_memo(&sig))
+        .await
+        .map_err(|e| ArchiveError::Io(std::io::Error::other(format!("memo task failed: {}", e))))??;
+
+    let memo_fields = onchain.memo.as_deref().and_then(parse_memo);
+    let hash_matches_memo = memo_fields.as_ref().is_some_and(|(hash, _)| *hash == file_hash);
+    let cid_matches_memo = memo_fields.as_ref().is_some_and(|(_, cid)| *cid == file_cid);
+    let signed_by_archive = onchain.signers.contains(&memo_sender.pubkey().to_string());
+
+    // re-hash what the store actually hands out for the cid; an unreachable daemon is reported, not fatal
+    let (ipfs_hash, ipfs_error) = match stored_sha256(store.as_ref().as_ref(), &file_cid).await {
+        Ok(hash) => (Some(hash), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let ipfs_content_matches = ipfs_hash.as_deref() == Some(file_hash.as_str());
+
+    let verified = onchain.succeeded && hash_matches_memo && cid_matches_memo && signed_by_archive && ipfs_content_matches;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": id,
+        "verified": verified,
+        "file_hash": file_hash,
+        "file_cid": file_cid,
+        "transaction": onchain,
+        "checks": {
+            "transaction_succeeded": onchain.succeeded,
+            "hash_matches_memo": hash_matches_memo,
+            "cid_matches_memo": cid_matches_memo,
+            "signed_by_archive": signed_by_archive,
+            "ipfs_content_matches": ipfs_content_matches,
+        },
+        "ipfs_sha256": ipfs_hash,
+        "ipfs_error": ipfs_error,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentParams {
+    download: Option<bool>, // attachment instead of inline
+}
+
+// the archived file itself, from the content store. it's checked against the record's hash before
+// any of it goes out, and Range requests get the part they ask for
+#[route("/documents/{id}/content", method = "GET", method = "HEAD")]
+async fn document_content(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    params: web::Query<ContentParams>,
+    store: web::Data<Arc<dyn ContentStore>>,
+    downloads: web::Data<DownloadCache>,
+    repo: web::Data<ArchiveRepository>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+
+    let Some(file) = web::block(move || repo.archived_file(id)).await?? else {
+        return Err(record_missing(id));
     };
-    let q = match query.get("q") {
-        Some(q) => q.clone(),
-        None => return HttpResponse::BadRequest().body("missing 'q' query param"),
+    serve_archived_file(&req, file, params.download.unwrap_or(false), store.get_ref().as_ref(), &downloads).await
+}
+
+// the same by cid, for links that only know the cid. only files a live record holds are served,
+// this isn't a gateway to the rest of ipfs
+#[route("/ipfs/{cid}", method = "GET", method = "HEAD")]
+async fn ipfs_content(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Query<ContentParams>,
+    store: web::Data<Arc<dyn ContentStore>>,
+    downloads: web::Data<DownloadCache>,
+    repo: web::Data<ArchiveRepository>,
+) -> Result<HttpResponse, Error> {
+    let cid = path.into_inner();
+
+    let wanted = cid.clone();
+    let Some(file) = web::block(move || repo.archived_file_by_cid(&wanted)).await?? else {
+        return Err(ArchiveError::NotFound(format!("no archived file has the cid {}", cid)).into());
+    };
+    serve_archived_file(&req, file, params.download.unwrap_or(false), store.get_ref().as_ref(), &downloads).await
+}
+
+// is this exact file already anchored? upload it (multipart) and we hash it without extraction,
+// ipfs or a memo
+#[post("/verify/file")]
+async fn verify_file(
+    mut payload: Multipart,
+    upload_config: web::Data<UploadConfig>,
+    repo: web::Data<ArchiveRepository>,
+) -> Result<HttpResponse, Error> {
+    let mut field = match payload.next().await {
+        Some(field_res) => field_res.map_err(|e| ArchiveError::InvalidInput(format!("Multipart field error: {}", e)))?,
+        None => return Err(ArchiveError::InvalidInput("No file uploaded".to_string()).into()),
     };
 
-    // whitelist allowed searchable fields to avoid SQL injection
-    let allowed: HashSet<&str> = [
-        "genre",
-        "title",
-        "difficulty",
-        "summary",
-        "file_hash",
-        "file_cid",
-    ]
-        .iter()
-        .copied()
+    // hashed as it arrives, nothing is kept; the same size limit as an upload
+    let mut hasher = Sha256Stream::default();
+    let mut size = 0u64;
+    while let Some(chunk_res) = field.next().await {
+        let chunk = chunk_res.map_err(|e| ArchiveError::InvalidInput(format!("Chunk read error: {}", e)))?;
+        size += chunk.len() as u64;
+        if size > upload_config.max_file_bytes {
+            let limit = upload_config.max_file_bytes / (1024 * 1024);
+            return Err(ArchiveError::TooLarge(format!("the file is over the {} MB limit for one file", limit)).into());
+        }
+        hasher.update(&chunk);
+    }
+
+    lookup_hash(hasher.finish(), repo).await
+}
+
+// same as /verify/file for callers that already have the SHA-256
+#[get("/verify/hash/{sha256}")]
+async fn verify_hash(path: web::Path<String>, repo: web::Data<ArchiveRepository>) -> Result<HttpResponse, Error> {
+    let file_hash = path.into_inner().trim().to_ascii_lowercase();
+    if file_hash.len() != 64 || !file_hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ArchiveError::InvalidInput(format!("'{}' is not a hex encoded SHA-256", file_hash)).into());
+    }
+    lookup_hash(file_hash, repo).await
+}
+
+async fn lookup_hash(file_hash: String, repo: web::Data<ArchiveRepository>) -> Result<HttpResponse, Error> {
+    let hash = file_hash.clone();
+    let records = web::block(move || repo.all_by_hash(&hash)).await??;
+    let matches: Vec<HashMatch> = records
+        .into_iter()
+        .map(|r| HashMatch {
+            id: r.id,
+            title: r.title,
+            file_hash: r.file_hash,
+            file_cid: r.file_cid,
+            solana_signature: r.solana_signature,
+            solana_slot: r.solana_slot,
+            solana_block_time: r.solana_block_time,
+        })
         .collect();
 
-    if !allowed.contains(field) {
-        return HttpResponse::BadRequest()
-            .body(format!("field '{}' is not searchable", field));
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "sha256": file_hash,
+        "anchored": matches.iter().any(|m| m.solana_signature.is_some()),
+        "matches": matches,
+    })))
+}
+
+// ranked search over everything: exact title/keyword hits and semantically close records,
+// fused into one list
+#[get("/search/hybrid")]
+async fn search_hybrid(
+    params: web::Query<HybridSearchParams>,
+    index_sync: web::Data<IndexSync>,
+    repo: web::Data<ArchiveRepository>,
+) -> Result<HttpResponse, Error> {
+    let params = params.into_inner();
+    if params.q.trim().is_empty() {
+        return Err(ArchiveError::InvalidInput("q must not be empty".to_string()).into());
     }
 
-    // Build pattern for LIKE
-    let pattern = format!("%{}%", q);
+    let query = HybridQuery {
+        query: params.q,
+        genre: params.genre.filter(|g| !g.is_empty()),
+        difficulty: params.difficulty.filter(|d| !d.is_empty()),
+        page: params.page.unwrap_or(1),
+        per_page: params.per_page.unwrap_or(10),
+    };
+    let page = hybrid_search(&repo, index_sync.index(), query).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
 
-    // We cannot parametrize column name, so we inject the validated field name into SQL.
-    // The value itself is bound as a parameter to avoid injection on content.
-    let sql = format!(
-        "SELECT id, genre, title, difficulty, summary, file_hash, file_cid FROM archive WHERE {} LIKE ?1",
-        field
-    );
+// search inside the documents themselves: the best matching passages, with their page
+#[get("/search/passages")]
+async fn search_passages(
+    params: web::Query<PassageSearchParams>,
+    index_sync: web::Data<IndexSync>,
+    repo: web::Data<ArchiveRepository>,
+) -> Result<HttpResponse, Error> {
+    let params = params.into_inner();
+    if params.q.trim().is_empty() {
+        return Err(ArchiveError::InvalidInput("q must not be empty".to_string()).into());
+    }
 
-    let res = web::block(move || -> Result<Vec<ArchiveRecord>, rusqlite::Error> {
-        let conn = Connection::open(DB_NAME)?;
-        let mut stmt = conn.prepare(&sql)?;
-        let rows = stmt.query_map([pattern], |row| {
-            Ok(ArchiveRecord {
-                id: row.get(0)?,
-                genre: row.get(1)?,
-                title: row.get(2)?,
-                difficulty: row.get(3)?,
-                summary: row.get(4)?,
-                file_hash: row.get(5)?,
-                file_cid: row.get(6)?,
-            })
-        })?;
-
-        let mut out = Vec::new();
-        for r in rows {
-            out.push(r?);
-        }
-        Ok(out)
-    })
-        .await;
+    let query = PassageQuery { query: params.q, record_id: params.record_id, limit: params.limit.unwrap_or(10) };
+    let hits = passage_search(&repo, index_sync.passages(), query).await?;
+    Ok(HttpResponse::Ok().json(hits))
+}
 
-    match res {
-        Ok(Ok(records)) => HttpResponse::Ok().json(records),
-        Ok(Err(e)) => HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Blocking error: {}", e)),
+// answers a question from the archived documents, citing the passages it used
+#[post("/ask")]
+async fn ask_archive(
+    payload: web::Json<AskRequest>,
+    index_sync: web::Data<IndexSync>,
+    answerer: web::Data<Arc<dyn Answerer>>,
+    repo: web::Data<ArchiveRepository>,
+) -> Result<HttpResponse, Error> {
+    let payload = payload.into_inner();
+    if payload.question.trim().is_empty() {
+        return Err(ArchiveError::InvalidInput("question must not be empty".to_string()).into());
     }
+
+    let query = AskQuery { question: payload.question, record_id: payload.record_id, limit: payload.limit.unwrap_or(5) };
+    let answer = ask(&repo, index_sync.passages(), answerer.get_ref().as_ref(), query).await?;
+    Ok(HttpResponse::Ok().json(answer))
+}
+
+#[get("/search")]
+async fn search_by_field(
+    query: web::Query<std::collections::HashMap<String, String>>,
+    repo: web::Data<ArchiveRepository>,
+) -> Result<HttpResponse, Error> {
+    // field + q do a LIKE search; resource_type and language are exact (case-insensitive) filters.
+    // at least one of the two has to be given
+    let filters = RecordSearch {
+        field: query.get("field").cloned(),
+        q: query.get("q").cloned(),
+        resource_type: query.get("resource_type").cloned(),
+        language: query.get("language").cloned(),
+    };
+
+    let records = web::block(move || repo.search(&filters)).await??;
+    Ok(HttpResponse::Ok().json(records))
 }
 
 #[get("/analytics/difficulty")]
-async fn difficulty() -> impl Responder {
-    let client = Client::new();
-    let url = "http://127.0.0.1:8001/analytics/difficulty";
-
-    match client.get(url).send().await {
-        Ok(resp) => match resp.json::<Value>().await {
-            Ok(json) => HttpResponse::Ok().json(json),
-            Err(_) => HttpResponse::InternalServerError().body("Invalid JSON from vector service"),
-        },
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {:?}", e)),
-    }
+async fn difficulty(repo: web::Data<ArchiveRepository>) -> Result<HttpResponse, Error> {
+    let counts = web::block(move || repo.count_by("difficulty")).await??;
+    Ok(HttpResponse::Ok().json(counts))
 }
 
 #[get("/analytics/genre")]
-async fn genre() -> impl Responder {
-    let client = Client::new();
-    let url = "http://127.0.0.1:8001/analytics/genre";
-
-    match client.get(url).send().await {
-        Ok(resp) => match resp.json::<Value>().await {
-            Ok(json) => HttpResponse::Ok().json(json),
-            Err(_) => HttpResponse::InternalServerError().body("Invalid JSON from vector service"),
-        },
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {:?}", e)),
-    }
+async fn genre(repo: web::Data<ArchiveRepository>) -> Result<HttpResponse, Error> {
+    let counts = web::block(move || repo.count_by("genre")).await??;
+    Ok(HttpResponse::Ok().json(counts))
 }
 
+// groups the records by how close their vectors are
 #[get("/analytics/clusters")]
-async fn clusters(query: web::Query<std::collections::HashMap<String, String>>) -> impl Responder {
-    let client = Client::new();
-    let default_n = "3".to_string();
-    let n = query.get("n").unwrap_or(&default_n); // default to 3 clusters
-    let url = format!("http://127.0.0.1:8001/analytics/clusters?n={}", n);
-
-    match client.get(&url).send().await {
-        Ok(resp) => match resp.json::<Value>().await {
-            Ok(json) => HttpResponse::Ok().json(json),
-            Err(_) => HttpResponse::InternalServerError().body("Invalid JSON from vector service"),
-        },
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {:?}", e)),
-    }
+async fn clusters(
+    query: web::Query<std::collections::HashMap<String, String>>,
+    index_sync: web::Data<IndexSync>,
+) -> Result<HttpResponse, Error> {
+    let n = match query.get("n") {
+        Some(n) => n
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| ArchiveError::InvalidInput(format!("n '{}' is not a positive integer", n)))?,
+        None => 3, // default to 3 clusters
+    };
+    Ok(HttpResponse::Ok().json(index_sync.index().clusters(n)))
 }
+
 #[post("/ai-search")]
-async fn search(payload: web::Json<VectorSearchRequest>) -> impl Responder {
-    let client = Client::new();
-    let url = "http://127.0.0.1:8001/search"; // Python FastAPI endpoint
-
-    let body = serde_json::json!({
-        "query": payload.query,
-        "k": payload.k.unwrap_or(3),
-    });
-
-    match client.post(url).json(&body).send().await {
-        Ok(resp) => {
-            if let Ok(json) = resp.json::<VectorSearchResult>().await {
-                HttpResponse::Ok().json(json)
-            } else {
-                HttpResponse::InternalServerError().body("Invalid response from vector service")
-            }
-        }
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {:?}", e)),
+async fn search(payload: web::Json<VectorSearchRequest>, index_sync: web::Data<IndexSync>) -> Result<HttpResponse, Error> {
+    if payload.query.trim().is_empty() {
+        return Err(ArchiveError::InvalidInput("query must not be empty".to_string()).into());
     }
+    let result = index_sync.index().search(&payload.query, payload.k.unwrap_or(3)).await?;
+    Ok(HttpResponse::Ok().json(result))
+}
+
+// where one record stands with the vector index
+#[get("/metadata/{id}/index")]
+async fn index_status(path: web::Path<i64>, index_sync: web::Data<IndexSync>) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    match index_sync.status(id).await? {
+        Some(state) => Ok(HttpResponse::Ok().json(state)),
+        None => Err(ArchiveError::NotFound(format!("record {} has never been queued for indexing", id)).into()),
+    }
+}
+
+// admin: how many records are indexed, pending or failed
+#[get("/admin/index")]
+async fn index_summary(index_sync: web::Data<IndexSync>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(index_sync.summary().await?))
+}
+
+// admin: drop the vector index and rebuild it from the archive in the background
+#[post("/admin/index/rebuild")]
+async fn rebuild_index(index_sync: web::Data<IndexSync>) -> Result<HttpResponse, Error> {
+    let queued = index_sync.rebuild().await?;
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "status": "accepted",
+        "queued": queued,
+    })))
 }
 
 
+// how far an upload has got: the stage it's on, each stage's attempts and errors, and the record
+// once it's written
+#[get("/jobs/{id}")]
+async fn job_status(path: web::Path<String>, jobs: web::Data<JobQueue>) -> Result<HttpResponse, Error> {
+    let job_id = path.into_inner();
+    match jobs.report(job_id.clone()).await? {
+        Some(report) => Ok(HttpResponse::Ok().json(report)),
+        None => Err(ArchiveError::NotFound(format!("job {}", job_id)).into()),
+    }
+}
+
+
+// a record that doesn't exist, or has been deleted
+fn record_missing(id: i64) -> Error {
+    ArchiveError::NotFound(format!("record {}", id)).into()
+}
+
+// an upload session never opened, cancelled, or expired and swept up
+fn session_missing(id: &str) -> Error {
+    ArchiveError::NotFound(format!("upload session {} (it may have expired)", id)).into()
+}
+
 #[post("/api/upload")]
-async fn upload(mut payload: Multipart) -> Result<impl Responder, Error> {
+async fn upload(
+    mut payload: Multipart,
+    options: web::Query<UploadOptions>,
+    upload_config: web::Data<UploadConfig>,
+    jobs: web::Data<JobQueue>,
+    repo: web::Data<ArchiveRepository>,
+) -> Result<impl Responder, Error> {
     // create uploads dir (synchronous ok here)
-    let _ = std::fs::create_dir_all("./uploads");
+    let _ = std::fs::create_dir_all(UPLOAD_DIR);
 
-    // iterate over multipart fields
+    // every multipart field is a file of the batch; a zip counts as the documents inside it. the
+    // size limits hold while the bytes arrive, and each file is hashed as it's written
+    let mut files: Vec<Received> = Vec::new();
+    let mut received = 0u64;
     while let Some(field_res) = payload.next().await {
-        let mut field = field_res.map_err(|e| {
-            actix_web::error::ErrorInternalServerError(format!("Multipart field error: {}", e))
-        })?;
+        let field = match field_res {
+            Ok(field) => field,
+            Err(e) => {
+                discard_received(&files).await;
+                return Err(ArchiveError::InvalidInput(format!("Multipart field error: {}", e)).into());
+            }
+        };
 
         // clone ContentDisposition (field.content_disposition() returns &ContentDisposition)
         let cd = field.content_disposition().clone();
+        let original_filename = cd.get_filename().map(|s| s.to_string());
+        let content_type = field.content_type().map(|mime| mime.essence_str().to_string());
 
-        // extract original filename if present
-        let original_filename_opt: Option<String> = cd
-            .get_filename()
-            .map(|s| s.to_string());
-
-        // choose server filename
-        let uuid = Uuid::new_v4().to_string();
-        let server_filename = if let Some(ref orig) = original_filename_opt {
-            let sanitized = sanitize(orig);
-            if let Some(ext) = Path::new(&sanitized).extension() {
-                format!("{}.{}", uuid, ext.to_string_lossy())
-            } else {
-                uuid.clone()
+        match receive_file(field, original_filename, content_type, &upload_config, &mut received).await {
+            Ok(file) => files.push(file),
+            Err(e) => {
+                // over the request limit or the body broke off: nothing of this request is kept
+                discard_received(&files).await;
+                return Err(e.into());
             }
-        } else {
-            uuid.clone()
-        };
+        }
+    }
+    if files.is_empty() {
+        return Err(ArchiveError::InvalidInput("No file uploaded".to_string()).into());
+    }
 
-        let filepath = format!("./uploads/{}", server_filename);
+    // each file is checked and queued on its own; one that fails (415 unsupported format, 422
+    // unreadable document, ...) carries its error in its result and the rest go ahead. the client
+    // follows accepted files along on /jobs/{id}
+    let results = admit_batch(files, options.force, &upload_config, &jobs, &repo).await;
+    Ok(batch_response(&results))
+}
 
+// resumable uploads, for files too big to trust to one request: open a session here, PATCH the
+// bytes to /api/uploads/{id} with an Upload-Offset header, GET it after a disconnect to see where
+// to carry on, then complete it
+#[post("/api/uploads")]
+async fn open_upload(
+    payload: web::Json<OpenUploadRequest>,
+    sessions: web::Data<UploadSessions>,
+) -> Result<HttpResponse, Error> {
+    let OpenUploadRequest { filename, content_type, size } = payload.into_inner();
+    let session = sessions.open(filename, content_type, size).await?;
+    Ok(HttpResponse::Created()
+        .insert_header((http::header::LOCATION, format!("/api/uploads/{}", session.id)))
+        .insert_header(("Upload-Offset", session.offset.to_string()))
+        .json(session))
+}
 
-        // create file asynchronously
-        let mut f = File::create(&filepath).await.map_err(|e| {
-            actix_web::error::ErrorInternalServerError(format!("File create error: {}", e))
-        })?;
+#[get("/api/uploads/{id}")]
+async fn upload_session_status(path: web::Path<String>, sessions: web::Data<UploadSessions>) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    match sessions.get(&id).await? {
+        Some(session) => Ok(HttpResponse::Ok()
+            .insert_header(("Upload-Offset", session.offset.to_string()))
+            .json(session)),
+        None => Err(session_missing(&id)),
+    }
+}
 
-        // async chunk writes
-        while let Some(chunk_res) = field.next().await {
-            let chunk = chunk_res.map_err(|e| {
-                actix_web::error::ErrorInternalServerError(format!("Chunk read error: {}", e))
-            })?;
-            f.write_all(&chunk).await.map_err(|e| {
-                actix_web::error::ErrorInternalServerError(format!("File write error: {}", e))
-            })?;
-        }
+// the body is the next piece of the file, as raw bytes; Upload-Offset says where it goes
+#[patch("/api/uploads/{id}")]
+async fn append_upload(
+    path: web::Path<String>,
+    req: HttpRequest,
+    body: web::Payload,
+    sessions: web::Data<UploadSessions>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let offset = req
+        .headers()
+        .get("Upload-Offset")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .ok_or_else(|| ArchiveError::InvalidInput("an Upload-Offset header with the byte offset of this piece is required".to_string()))?;
 
-        // Step 1: metadata extraction (async)
-        let metadata = match get_meta_data_response(filepath.clone()).await {
-            Ok(m) => m,
-            Err(e) => {
-                return Ok(HttpResponse::InternalServerError()
-                    .body(format!("Metadata extraction failed: {}", e)));
-            }
-        };
+    match sessions.append(&id, offset, body).await? {
+        Some(session) => Ok(HttpResponse::Ok()
+            .insert_header(("Upload-Offset", session.offset.to_string()))
+            .json(session)),
+        None => Err(session_missing(&id)),
+    }
+}
 
-        // Step 2: hash + CID packaging (async)
-        let file_record = match package_hash_and_cid(filepath.clone()).await {
-            Ok(r) => r,
-            Err(e) => {
-                return Ok(HttpResponse::InternalServerError()
-                    .body(format!("File packaging failed: {}", e)));
-            }
-        };
+// the file is all there: check it and queue it, with the same results as /api/upload
+#[post("/api/uploads/{id}/complete")]
+async fn complete_upload(
+    path: web::Path<String>,
+    options: web::Query<UploadOptions>,
+    sessions: web::Data<UploadSessions>,
+    jobs: web::Data<JobQueue>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    match sessions.complete(&id, options.force, &jobs).await? {
+        Some(session) => Ok(batch_response(&session.results.unwrap_or_default())),
+        None => Err(session_missing(&id)),
+    }
+}
 
-        // Step 3: send memo to Solana (blocking work)
-        // Ensure closure returns types that are Send + 'static by mapping errors to String
-        let file_record_clone = file_record.clone();
-        let memo_sig = match tokio::task::spawn_blocking(move || {
-            send_memo(&file_record_clone.file_hash, &file_record_clone.file_cid)
-                .map_err(|e| e.to_string())
-        })
-            .await
-        {
-            Ok(Ok(sig)) => sig,
-            Ok(Err(e_str)) => {
-                return Ok(HttpResponse::InternalServerError()
-                    .body(format!("Solana memo failed: {}", e_str)));
-            }
-            Err(join_err) => {
-                return Ok(HttpResponse::InternalServerError()
-                    .body(format!("Join error in Solana memo: {}", join_err)));
-            }
-        };
+#[delete("/api/uploads/{id}")]
+async fn cancel_upload(path: web::Path<String>, sessions: web::Data<UploadSessions>) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    if !sessions.cancel(&id).await? {
+        return Err(session_missing(&id));
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "success", "deleted": id })))
+}
 
-        // Step 4: DB insertion (blocking work)
-        let metadata_clone = metadata.clone();
-        let file_record_clone2 = file_record.clone();
-        let db_res = tokio::task::spawn_blocking(move || {
-            add_to_or_create_database(&metadata_clone, &file_record_clone2, "archive.db".to_string())
-                .map_err(|e| e.to_string())
-        })
-            .await;
-
-        // handle spawn_blocking join error
-        let db_inner_res = match db_res {
-            Ok(inner) => inner,
-            Err(join_err) => {
-                return Ok(HttpResponse::InternalServerError()
-                    .body(format!("Database thread join error: {}", join_err)));
-            }
-        };
+// the response to a batch of files: 202 if anything was queued, 200 if everything was already
+// here, otherwise the status a single-file upload would have got for the first failure
+fn batch_response(results: &[FileResult]) -> HttpResponse {
+    let count = |status: &str| results.iter().filter(|r| r.status == status).count();
+    let (accepted, duplicates, failed) = (count("accepted"), count("duplicate"), count("failed"));
 
-        if let Err(db_err_str) = db_inner_res {
-            return Ok(HttpResponse::InternalServerError()
-                .body(format!("Database insertion failed: {}", db_err_str)));
-        }
+    let (status, code) = if accepted > 0 {
+        ("accepted", http::StatusCode::ACCEPTED)
+    } else if duplicates > 0 {
+        ("success", http::StatusCode::OK)
+    } else {
+        let code = results
+            .iter()
+            .find_map(|r| r.error.as_ref())
+            .and_then(|e| http::StatusCode::from_u16(e.http_status).ok())
+            .unwrap_or(http::StatusCode::BAD_REQUEST);
+        ("error", code)
+    };
 
-        // Final JSON response for this file
-        return Ok(HttpResponse::Ok().json(serde_json::json!({
-            "status": "success",
-            "server_filename": server_filename,
-            "original_filename": original_filename_opt,
-            "metadata": metadata,
-            "file_record": file_record,
-            "solana_signature": memo_sig
-        })));
+    HttpResponse::build(code).json(serde_json::json!({
+        "status": status,
+        "accepted": accepted,
+        "duplicates": duplicates,
+        "failed": failed,
+        "results": results,
+    }))
+}
+
+// the response for a download: 304 if the client has the file already (its hash is fixed, so that's
+// known without fetching it), else the verified bytes, whole or the range asked for
+async fn serve_archived_file(
+    req: &HttpRequest,
+    file: ArchivedFile,
+    attachment: bool,
+    store: &dyn ContentStore,
+    downloads: &DownloadCache,
+) -> Result<HttpResponse, Error> {
+    use actix_web::body::SizedStream;
+    use http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+
+    let tag = etag(&file);
+    let request_header = |name: header::HeaderName| req.headers().get(name).and_then(|value| value.to_str().ok());
+    if request_header(header::IF_NONE_MATCH).is_some_and(|tags| etag_matches(tags, &tag)) {
+        return Ok(HttpResponse::NotModified().insert_header((header::ETAG, tag)).finish());
+    }
+
+    // verified on the first request, every one after that (each range of it too) reads the copy
+    let spool = downloads.fetch(store, &file).await?;
+    let total = spool.size();
+    let format = content_format(&file, &spool).await;
+
+    // a plain filename for old clients, the utf-8 one (RFC 6266) when the name needs it
+    let filename = download_filename(&file, format);
+    let mut parameters = vec![DispositionParam::Filename(
+        filename.chars().map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '_' }).collect(),
+    )];
+    if !filename.is_ascii() {
+        parameters.push(DispositionParam::FilenameExt(header::ExtendedValue {
+            charset: header::Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: filename.into_bytes(),
+        }));
     }
+    let disposition = match attachment {
+        true => DispositionType::Attachment,
+        false => DispositionType::Inline,
+    };
+
+    let (mut response, range) =
+        match parse_range(request_header(header::RANGE), request_header(header::IF_RANGE), &tag, total) {
+            RangeRequest::Full => (HttpResponse::Ok(), None),
+            RangeRequest::Partial(range) => {
+                let mut response = HttpResponse::PartialContent();
+                response.insert_header((header::CONTENT_RANGE, range.content_range(total)));
+                (response, Some(range))
+            }
+            RangeRequest::Unsatisfiable => {
+                return Ok(HttpResponse::RangeNotSatisfiable()
+                    .insert_header((header::CONTENT_RANGE, format!("bytes */{}", total)))
+                    .insert_header((header::ETAG, tag))
+                    .insert_header((header::ACCEPT_RANGES, "bytes"))
+                    .finish());
+            }
+        };
 
-    // if we get here, no fields were uploaded
-    Ok(HttpResponse::BadRequest().body("No file uploaded"))
+    // sent straight off the spool file, which stays for as long as the cache or a response needs it
+    let length = range.map(|range| range.end - range.start + 1).unwrap_or(total);
+    let body = spool.into_stream(range).await?.map(|piece| piece.map(web::Bytes::from));
+
+    Ok(response
+        .insert_header((header::CONTENT_TYPE, content_type(format)))
+        .insert_header(ContentDisposition { disposition, parameters })
+        .insert_header((header::ETAG, tag))
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .body(SizedStream::new(length, body)))
 }
 
 // start the actix server
 #[actix_web::main]
 async fn main() -> std::io::Result<()>{
+    // every handler shares one pool; the schema is brought up to date before any of them reads
+    // from it, nothing else creates tables
+    let repo = ArchiveRepository::open(DB_NAME)
+        .map_err(|e| std::io::Error::other(format!("could not open {}: {}", DB_NAME, e)))?;
+    let applied = repo
+        .migrate()
+        .map_err(|e| std::io::Error::other(format!("could not migrate {}: {}", DB_NAME, e)))?;
+    if !applied.is_empty() {
+        println!("{} is now at schema version {}", DB_NAME, applied[applied.len() - 1]);
+    }
+
+    // pick the llm provider + model once, every upload shares it
+    let llm_config = LlmConfig::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+    let extractor: Arc<dyn MetadataExtractor> = build_extractor(&llm_config);
+    println!("Metadata extraction provider: {}", extractor.name());
+    let extractor = web::Data::new(extractor);
+    // /ask goes to the same provider
+    let answerer = web::Data::new(build_answerer(&llm_config));
+
+    // one persistent payer keypair signs every memo, so they're all attributable to this archive
+    let solana_config = SolanaConfig::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+    let memo_sender = MemoSender::new(&solana_config)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+    println!("Posting memos to {} as {}", solana_config.rpc_url, memo_sender.pubkey());
+    let memo_sender = Arc::new(memo_sender);
+
+    // long documents get summarised in chunks sized for the model's context window
+    let chunking = ChunkConfig::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+
+    // the archived files go to kubo, a directory or memory, whichever CONTENT_STORE says
+    let store_config = ContentStoreConfig::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+    let store = build_content_store(&store_config)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+    println!("Content store: {}", store.name());
 
-    HttpServer::new(|| {
+    // semantic search runs in-process against an index stored next to the database
+    let embedding_config = EmbeddingConfig::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+    let semantic_index = SemanticIndex::open(&embedding_config.index_path, build_embedder(&embedding_config))
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    println!(
+        "Vector index {:?}: {} records ({})",
+        embedding_config.index_path,
+        semantic_index.len(),
+        semantic_index.embedder_name()
+    );
+
+    // passage vectors of the document bodies are kept in the database, same embedder
+    let passage_index = PassageIndex::new(repo.clone(), build_embedder(&embedding_config));
+    match passage_index.len().await {
+        Ok(count) => println!("Passage index: {} passages", count),
+        Err(e) => println!("Passage index unreadable: {}", e),
+    }
+
+    // uploads and edits only write the outbox; this task carries the changes into both indexes
+    let index_sync = Arc::new(IndexSync::new(
+        Arc::new(semantic_index),
+        Arc::new(passage_index),
+        repo.clone(),
+        embedding_config.sync_interval,
+    ));
+    match index_sync.reconcile().await {
+        Ok(0) => {}
+        Ok(queued) => println!("Queued {} records the vector index is out of step with", queued),
+        Err(e) => println!("Vector index reconcile failed: {}", e),
+    }
+    let sync_task = index_sync.clone();
+    actix_web::rt::spawn(async move { sync_task.run().await });
+
+    // uploads only store the file and queue a job; these workers run the pipeline
+    let job_config = JobConfig::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+    let jobs = Arc::new(JobQueue::new(
+        repo.clone(),
+        extractor.get_ref().clone(),
+        chunking,
+        store.clone(),
+        memo_sender.clone(),
+        index_sync.clone(),
+        job_config,
+    ));
+    match jobs.recover().await {
+        Ok(0) => {}
+        Ok(requeued) => println!("Requeued {} upload jobs that were interrupted", requeued),
+        Err(e) => println!("Could not requeue interrupted upload jobs: {}", e),
+    }
+    for _ in 0..jobs.workers() {
+        let worker = jobs.clone();
+        actix_web::rt::spawn(async move { worker.run().await });
+    }
+    println!("{} upload job workers running", jobs.workers());
+
+    let memo_sender = web::Data::from(memo_sender);
+    let index_sync = web::Data::from(index_sync);
+    let jobs = web::Data::from(jobs);
+    let store = web::Data::new(store);
+    let repo = web::Data::new(repo);
+    // downloads are verified once, then served from a copy on disk
+    let downloads = web::Data::new(DownloadCache::new(store_config.download_cache_bytes));
+
+    // how many files one upload may hold and how many are checked at once
+    let upload_config = UploadConfig::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+
+    // resumable uploads live in the database until they're completed; this task throws away the
+    // ones that went quiet
+    let sessions = Arc::new(UploadSessions::new(repo.get_ref().clone(), upload_config.clone()));
+    let sweeper = sessions.clone();
+    actix_web::rt::spawn(async move { sweeper.run().await });
+    let sessions = web::Data::from(sessions);
+    let upload_config = web::Data::new(upload_config);
+
+    HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin("http://localhost:8080") // Vite dev server origin
-            .allowed_methods(vec!["GET", "POST", "OPTIONS"])
+            .allowed_methods(vec!["GET", "HEAD", "POST", "PATCH", "DELETE", "OPTIONS"])
             .allowed_headers(vec![
                 http::header::CONTENT_TYPE,
                 http::header::ACCEPT,
                 http::header::AUTHORIZATION,
+                http::header::HeaderName::from_static("upload-offset"),
+                http::header::RANGE,
+                http::header::IF_RANGE,
+                http::header::IF_NONE_MATCH,
+            ])
+            // resumable uploads tell the client where to carry on in these, downloads what they got
+            .expose_headers(vec![
+                http::header::LOCATION,
+                http::header::HeaderName::from_static("upload-offset"),
+                http::header::CONTENT_DISPOSITION,
+                http::header::CONTENT_RANGE,
+                http::header::ACCEPT_RANGES,
+                http::header::ETAG,
             ])
             .supports_credentials() // only if your frontend needs cookies/auth
             .max_age(3600);
 
         App::new()
             .wrap(cors) // <- apply CORS middleware
+            .app_data(extractor.clone())
+            .app_data(answerer.clone())
+            .app_data(memo_sender.clone())
+            .app_data(store.clone())
+            .app_data(downloads.clone())
+            .app_data(index_sync.clone())
+            .app_data(upload_config.clone())
+            .app_data(sessions.clone())
+            .app_data(jobs.clone())
+            .app_data(repo.clone())
             .service(search)
             .service(difficulty)
             .service(genre)
             .service(clusters)
             .service(list_all)
             .service(get_entry_by_id)
+            .service(edit_entry)
+            .service(entry_history)
+            .service(delete_entry)
+            .service(index_status)
+            .service(index_summary)
+            .service(rebuild_index)
+            .service(verify_entry)
+            .service(document_content)
+            .service(ipfs_content)
+            .service(verify_file)
+            .service(verify_hash)
+            .service(search_hybrid)
+            .service(search_passages)
+            .service(ask_archive)
             .service(search_by_field)
             .service(hello)
             .service(upload)
+            .service(open_upload)
+            .service(upload_session_status)
+            .service(append_upload)
+            .service(complete_upload)
+            .service(cancel_upload)
+            .service(job_status)
             .route("/health", web::get().to(|| async { HttpResponse::Ok().body("OK") }))
     })
         .bind(("127.0.0.1", 5000))?
@@ -0,0 +1,53 @@
+// config.rs: numeric settings read out of the environment, the same way for every *Config::from_env
+use std::env;
+
+use crate::error::{Error, Result};
+
+
+// a whole-number setting: `default` when it isn't set, an error when it's set to anything but a
+// number of at least `min`. most settings want at least 1; 0 is only for things that can be
+// switched off, like the chunk overlap
+pub(crate) fn env_u64(name: &str, default: u64, min: u64) -> Result<u64> {
+    match env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|v| *v >= min)
+            .ok_or_else(|| Error::Config(format!("{} '{}' must be a whole number of at least {}", name, value, min))),
+        Err(_) => Ok(default),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every test sets its own variable, so running them side by side is fine
+    fn set(name: &str, value: &str) {
+        unsafe { env::set_var(name, value) };
+    }
+
+    #[test]
+    fn unset_gives_the_default() {
+        assert_eq!(env_u64("CONFIG_TEST_UNSET", 7, 1).unwrap(), 7);
+    }
+
+    #[test]
+    fn parses_numbers_at_or_above_the_minimum() {
+        set("CONFIG_TEST_NUMBER", " 42 ");
+        assert_eq!(env_u64("CONFIG_TEST_NUMBER", 7, 1).unwrap(), 42);
+        set("CONFIG_TEST_ZERO", "0");
+        assert_eq!(env_u64("CONFIG_TEST_ZERO", 7, 0).unwrap(), 0);
+        assert!(matches!(env_u64("CONFIG_TEST_ZERO", 7, 1), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_whole_number() {
+        for (name, value) in [("CONFIG_TEST_NEGATIVE", "-3"), ("CONFIG_TEST_FLOAT", "2.5"), ("CONFIG_TEST_WORD", "lots")] {
+            set(name, value);
+            assert!(matches!(env_u64(name, 7, 0), Err(Error::Config(message)) if message.contains(value)));
+        }
+    }
+}
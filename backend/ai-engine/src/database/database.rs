@@ -1,38 +1,182 @@
 // database.rs: Utilty functions for database integration and handling
 
-use rusqlite::{Connection, Result};
+use rusqlite::{Connection, OptionalExtension, Transaction, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::error::{Error, Result};
+use crate::extract::extract::ExtractedDocument;
+use crate::nlp::chunker::split_passages;
 use crate::nlp::engine::ExtractedMetaData;
 use crate::nlp::engine::FileRecord;
+use crate::solana::solana::MemoReceipt;
 
-// because of our lord and saviour: thoughtful developers we can write one function that does both:
-// 1. creating the database
-// 2. adding to the database
+// keywords, topics and authors each get a lookup table plus a join table back to archive:
+// (term kind, lookup table, join table, join column)
+pub const TERM_TABLES: [(&str, &str, &str, &str); 3] = [
+    ("keyword", "keyword", "archive_keyword", "keyword_id"),
+    ("topic", "topic", "archive_topic", "topic_id"),
+    ("author", "author", "archive_author", "author_id"),
+];
 
+// the list-valued metadata of one record, loaded from the join tables
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RecordTerms {
+    pub keywords: Vec<String>,
+    pub topics: Vec<String>,
+    pub authors: Vec<String>,
+}
 
-// please don't get angry at my naming conventions lmao ;)
-pub fn add_to_or_create_database(metadata: &ExtractedMetaData, hash: &FileRecord, database_name: String) -> Result<(), Box<dyn std::error::Error>> {
-    let conn = Connection::open(database_name)?;
+// one archive row with its terms, the shape every endpoint hands out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveRecord {
+    pub id: i64,
+    pub genre: String,
+    pub title: String,
+    pub difficulty: String,
+    pub summary: String,
+    pub file_hash: String,
+    pub file_cid: String,
+    pub resource_type: String,
+    pub language: String,
+    pub keywords: Vec<String>,
+    pub topics: Vec<String>,
+    pub authors: Vec<String>,
+    pub solana_signature: Option<String>,
+    pub solana_slot: Option<i64>,
+    pub solana_block_time: Option<i64>,
+    pub created_at: Option<i64>, // unix seconds, unknown for some records archived before it was kept
+    pub updated_at: Option<i64>,
+}
 
+// a hand edit of a record's metadata, as PATCH /metadata/{id} takes it; fields left out stay as they are.
+// hash, cid and the anchor describe the file itself and can't be edited
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetadataPatch {
+    pub title: Option<String>,
+    pub genre: Option<String>,
+    pub difficulty: Option<String>,
+    pub summary: Option<String>,
+    pub resource_type: Option<String>,
+    pub language: Option<String>,
+    pub keywords: Option<Vec<String>>,
+    pub topics: Option<Vec<String>>,
+    pub authors: Option<Vec<String>>,
+}
 
-    // if we dont have a database active; create one
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS archive (
-            id INTEGER PRIMARY KEY,
-            genre TEXT NOT NULL,
-            title TEXT NOT NULL,
-            difficulty TEXT NOT NULL,
-            summary TEXT NOT NULL,
-            file_hash TEXT NOT NULL,
-            file_cid TEXT NOT NULL
-        )",
-        (),
-    )?;
-
-    // Always insert a new row (duplicates allowed)
-    conn.execute(
+// one field changed by hand; values are json (a string, or a list for keywords/topics/authors)
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordEdit {
+    pub id: i64,
+    pub record_id: i64,
+    pub field: String,
+    pub old_value: serde_json::Value,
+    pub new_value: serde_json::Value,
+    pub edited_at: i64, // unix seconds
+}
+
+// what a change to a record means for the vector index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexOp {
+    Upsert,
+    Delete,
+}
+
+impl IndexOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IndexOp::Upsert => "upsert",
+            IndexOp::Delete => "delete",
+        }
+    }
+}
+
+// outbox rows for one record that still have to reach the index; only the newest op matters
+#[derive(Debug, Clone)]
+pub struct IndexChange {
+    pub outbox_ids: Vec<i64>,
+    pub archive_id: i64,
+    pub op: IndexOp,
+}
+
+// where a record stands with the vector index: pending | indexed | failed | deleted
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexState {
+    pub record_id: i64,
+    pub status: String,
+    pub embedder: Option<String>,
+    pub updated_at: i64, // unix seconds
+    pub attempts: i64,
+    pub last_error: Option<String>,
+}
+
+// a search passage: a piece of one page (or of the whole text, for unpaged formats)
+#[derive(Debug, Clone, Serialize)]
+pub struct Passage {
+    pub id: i64,
+    pub archive_id: i64,
+    pub position: i64,
+    pub page: Option<i64>, // 1 based, pdfs only
+    pub text: String,
+}
+
+// a document's stored text, decompressed
+#[derive(Debug, Clone)]
+pub struct StoredText {
+    pub format: String,
+    pub pages: Vec<String>,
+    pub paged: bool,
+}
+
+// what a download needs to know about a live record's file
+#[derive(Debug, Clone)]
+pub struct ArchivedFile {
+    pub record_id: i64,
+    pub file_hash: String,
+    pub file_cid: String,
+    pub original_filename: Option<String>, // unknown for records archived before it was kept
+    pub format: Option<String>,            // from the stored text, as DocumentFormat::as_str names it
+}
+
+// the metadata a hand edit may change, as named in MetadataPatch and record_edit.field
+pub const EDITABLE_FIELDS: [&str; 9] =
+    ["title", "genre", "difficulty", "summary", "resource_type", "language", "keywords", "topics", "authors"];
+
+// pages are stored joined by form feeds, the way pdftotext separates them
+const PAGE_SEPARATOR: char = '\u{c}';
+
+// records whose terms are loaded with one query
+const TERM_BATCH_SIZE: usize = 500;
+
+// an outbox row is retried this many times before it's left as failed (a rebuild, or a newer
+// change to the same record, resets it)
+pub const MAX_INDEX_ATTEMPTS: i64 = 8;
+
+// a failed outbox row waits this long before its first retry, doubling every time after
+const INDEX_RETRY_DELAY_SECS: i64 = 30;
+const MAX_INDEX_RETRY_DELAY_SECS: i64 = 3600;
+
+
+// please don't get angry at my naming conventions lmao ;)
+// adds one record with its terms and text and queues it for the vector index; returns the id of
+// the new archive row. pass a transaction so the outbox entry commits with the record
+pub fn insert_record(
+    tx: &Transaction,
+    metadata: &ExtractedMetaData,
+    hash: &FileRecord,
+    anchor: &MemoReceipt,
+    document: &ExtractedDocument,
+    original_filename: Option<&str>,
+) -> Result<i64> {
+    // one live row per file: the unique index on file_hash rejects a second copy, so callers check
+    // find_record_by_hash first
+    tx.execute(
         "INSERT INTO archive
-         (genre, title, difficulty, summary, file_hash, file_cid)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+         (genre, title, difficulty, summary, file_hash, file_cid, resource_type, language,
+          solana_signature, solana_slot, solana_block_time, created_at, updated_at, original_filename)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?12, ?13)",
         (
             &metadata.genre,
             &metadata.title,
@@ -40,17 +184,777 @@ pub fn add_to_or_create_database(metadata: &ExtractedMetaData, hash: &FileRecord
             &metadata.summary,
             &hash.file_hash,
             &hash.file_cid,
+            &metadata.resource_type,
+            &metadata.language,
+            &anchor.signature,
+            anchor.slot.map(|slot| slot as i64),
+            anchor.block_time,
+            unix_now(),
+            original_filename,
         ),
     )?;
-    println!("Inserted new record.");
+    let id = tx.last_insert_rowid();
+
+    link_terms(tx, id, TERM_TABLES[0], &metadata.keywords)?;
+    link_terms(tx, id, TERM_TABLES[1], &metadata.topics)?;
+    link_terms(tx, id, TERM_TABLES[2], &metadata.authors)?;
+    store_document_text(tx, id, document)?;
+
+    // same transaction, so the index can never miss a record that made it into the archive
+    enqueue_index_change(tx, id, IndexOp::Upsert)?;
+    Ok(id)
+}
+
+// the archive columns archive_from_row reads, in order
+pub const ARCHIVE_COLUMNS: &str = "id, genre, title, difficulty, summary, file_hash, file_cid, resource_type, language, \
+     solana_signature, solana_slot, solana_block_time, created_at, updated_at";
+
+// one full record, terms included; deleted records are QueryReturnedNoRows like missing ones
+pub fn load_record(conn: &Connection, id: i64) -> Result<ArchiveRecord> {
+    let mut record = conn.query_row(
+        &format!("SELECT {ARCHIVE_COLUMNS} FROM archive WHERE id = ?1 AND deleted_at IS NULL"),
+        [id],
+        archive_from_row,
+    )?;
 
-    // List all genres currently stored
-    let mut stmt = conn.prepare("SELECT genre FROM archive")?;
-    let genres_iter = stmt.query_map([], |row| row.get::<_, String>(0))?;
-    println!("All genres in DB:");
-    for g in genres_iter {
-        println!("  - {}", g?);
+    let terms = get_record_terms(conn, record.id)?;
+    record.keywords = terms.keywords;
+    record.topics = terms.topics;
+    record.authors = terms.authors;
+    Ok(record)
+}
+
+// the record already holding this exact file, if any (the oldest one on pre-dedup databases)
+pub fn find_record_by_hash(conn: &Connection, file_hash: &str) -> Result<Option<i64>> {
+    let mut stmt = conn.prepare("SELECT id FROM archive WHERE file_hash = ?1 AND deleted_at IS NULL ORDER BY id LIMIT 1")?;
+    let mut rows = stmt.query([file_hash])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
     }
+}
 
+// the file behind a live record, None if there is no such record
+pub fn load_archived_file(conn: &Connection, id: i64) -> Result<Option<ArchivedFile>> {
+    query_archived_file(conn, "a.id = ?1", id)
+}
+
+// the live record holding the file with this cid (the oldest, should there be several)
+pub fn find_archived_file_by_cid(conn: &Connection, file_cid: &str) -> Result<Option<ArchivedFile>> {
+    query_archived_file(conn, "a.file_cid = ?1", file_cid)
+}
+
+// swaps in freshly extracted metadata (and text) for an existing record; hash, cid and the anchor
+// stay as they are. QueryReturnedNoRows if there is no such record
+pub fn update_record(tx: &Transaction, archive_id: i64, metadata: &ExtractedMetaData, document: &ExtractedDocument) -> Result<()> {
+    write_metadata(tx, archive_id, metadata)?;
+    store_document_text(tx, archive_id, document)?;
+    enqueue_index_change(tx, archive_id, IndexOp::Upsert)?;
     Ok(())
 }
+
+// applies a hand edit on top of the record's current metadata, validated like the model's output
+// (bad values are InvalidInput), and logs every field that actually changed; returns those log
+// rows, none if the patch changed nothing. QueryReturnedNoRows if there is no such record
+pub fn edit_record(tx: &Transaction, archive_id: i64, patch: &MetadataPatch) -> Result<Vec<RecordEdit>> {
+    let record = load_record(tx, archive_id)?;
+    let current = ExtractedMetaData {
+        title: record.title,
+        difficulty: record.difficulty,
+        genre: record.genre,
+        summary: record.summary,
+        resource_type: record.resource_type,
+        keywords: record.keywords,
+        topics: record.topics,
+        language: record.language,
+        authors: record.authors,
+    };
+
+    let mut edited = current.clone();
+    let patch = patch.clone();
+    for (field, value) in [
+        (&mut edited.title, patch.title),
+        (&mut edited.genre, patch.genre),
+        (&mut edited.difficulty, patch.difficulty),
+        (&mut edited.summary, patch.summary),
+        (&mut edited.resource_type, patch.resource_type),
+        (&mut edited.language, patch.language),
+    ] {
+        if let Some(value) = value {
+            *field = value;
+        }
+    }
+    for (field, value) in [(&mut edited.keywords, patch.keywords), (&mut edited.topics, patch.topics), (&mut edited.authors, patch.authors)] {
+        if let Some(value) = value {
+            *field = value;
+        }
+    }
+    let edited = edited.validate().map_err(|e| match e {
+        Error::LlmValidation(message) => Error::InvalidInput(message),
+        e => e,
+    })?;
+
+    let now = unix_now();
+    let mut edits = Vec::new();
+    for field in EDITABLE_FIELDS {
+        let (old_value, new_value) = (metadata_field(&current, field), metadata_field(&edited, field));
+        if old_value == new_value {
+            continue;
+        }
+        tx.execute(
+            "INSERT INTO record_edit (archive_id, field, old_value, new_value, edited_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            (archive_id, field, old_value.to_string(), new_value.to_string(), now),
+        )?;
+        edits.push(RecordEdit {
+            id: tx.last_insert_rowid(),
+            record_id: archive_id,
+            field: field.to_string(),
+            old_value,
+            new_value,
+            edited_at: now,
+        });
+    }
+
+    if !edits.is_empty() {
+        write_metadata(tx, archive_id, &edited)?;
+        enqueue_index_change(tx, archive_id, IndexOp::Upsert)?;
+    }
+    Ok(edits)
+}
+
+// every hand edit of a record, oldest first
+pub fn record_history(conn: &Connection, archive_id: i64) -> Result<Vec<RecordEdit>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, archive_id, field, old_value, new_value, edited_at FROM record_edit
+         WHERE archive_id = ?1 ORDER BY id",
+    )?;
+    let edits = stmt
+        .query_map([archive_id], |row| {
+            let old_value: String = row.get(3)?;
+            let new_value: String = row.get(4)?;
+            Ok(RecordEdit {
+                id: row.get(0)?,
+                record_id: row.get(1)?,
+                field: row.get(2)?,
+                old_value: serde_json::from_str(&old_value).unwrap_or(serde_json::Value::String(old_value)),
+                new_value: serde_json::from_str(&new_value).unwrap_or(serde_json::Value::String(new_value)),
+                edited_at: row.get(5)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(edits)
+}
+
+// marks a record deleted: every read path skips it from now on, its passages go and its vectors are
+// queued for removal. the row, its terms and text stay for the audit trail. false if there was no
+// live record to delete
+pub fn delete_record(tx: &Transaction, archive_id: i64) -> Result<bool> {
+    let now = unix_now();
+    let deleted = tx.execute(
+        "UPDATE archive SET deleted_at = ?1, updated_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+        (now, archive_id),
+    )?;
+    if deleted > 0 {
+        tx.execute("DELETE FROM passage WHERE archive_id = ?1", [archive_id])?;
+        enqueue_index_change(tx, archive_id, IndexOp::Delete)?;
+    }
+    Ok(deleted > 0)
+}
+
+// keywords, topics and authors for one record, in the order the model gave them
+pub fn get_record_terms(conn: &Connection, archive_id: i64) -> Result<RecordTerms> {
+    let mut lists = Vec::with_capacity(TERM_TABLES.len());
+    for (_, table, join_table, join_column) in TERM_TABLES {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT t.name FROM {join_table} j JOIN {table} t ON t.id = j.{join_column}
+             WHERE j.archive_id = ?1 ORDER BY j.position"
+        ))?;
+        let names = stmt
+            .query_map([archive_id], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        lists.push(names);
+    }
+
+    let authors = lists.pop().unwrap_or_default();
+    let topics = lists.pop().unwrap_or_default();
+    let keywords = lists.pop().unwrap_or_default();
+    Ok(RecordTerms { keywords, topics, authors })
+}
+
+// the terms of many records at once, one query per term table instead of three per record;
+// records without any terms are left out of the map
+pub fn get_terms_for_records(conn: &Connection, archive_ids: &[i64]) -> Result<HashMap<i64, RecordTerms>> {
+    let mut terms: HashMap<i64, RecordTerms> = HashMap::new();
+
+    // search results aren't paged, so the ids go in batches under sqlite's limit on bound parameters
+    for batch in archive_ids.chunks(TERM_BATCH_SIZE) {
+        let placeholders = vec!["?"; batch.len()].join(", ");
+        for (kind, table, join_table, join_column) in TERM_TABLES {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT j.archive_id, t.name FROM {join_table} j JOIN {table} t ON t.id = j.{join_column}
+                 WHERE j.archive_id IN ({placeholders}) ORDER BY j.archive_id, j.position"
+            ))?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(batch), |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?;
+            for row in rows {
+                let (archive_id, name) = row?;
+                let record = terms.entry(archive_id).or_default();
+                match kind {
+                    "keyword" => record.keywords.push(name),
+                    "topic" => record.topics.push(name),
+                    _ => record.authors.push(name),
+                }
+            }
+        }
+    }
+    Ok(terms)
+}
+
+
+// a full text hit: bm25 (lower is better, as sqlite reports it) and a highlighted snippet
+#[derive(Debug, Clone)]
+pub struct TextMatch {
+    pub id: i64,
+    pub bm25: f64,
+    pub snippet: String,
+}
+
+// bm25 ranked full text search, best first. every word of the query is matched as a prefix and any
+// of them may match, so user input never reaches fts5 as query syntax. genre and difficulty are
+// optional exact (case-insensitive) filters
+pub fn full_text_search(
+    conn: &Connection,
+    query: &str,
+    genre: Option<&str>,
+    difficulty: Option<&str>,
+    limit: usize,
+) -> Result<Vec<TextMatch>> {
+    let terms = query_terms(query);
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+    let match_expr = terms.iter().map(|t| format!("\"{}\"*", t)).collect::<Vec<_>>().join(" OR ");
+
+    // title and keywords count for more than the summary
+    let mut stmt = conn.prepare(
+        "SELECT f.rowid,
+                bm25(archive_fts, 10.0, 1.0, 2.0, 5.0) AS rank,
+                snippet(archive_fts, -1, '<mark>', '</mark>', '…', 16)
+         FROM archive_fts f JOIN archive a ON a.id = f.rowid
+         WHERE archive_fts MATCH ?1 AND a.deleted_at IS NULL
+           AND (?2 IS NULL OR a.genre = ?2 COLLATE NOCASE)
+           AND (?3 IS NULL OR a.difficulty = ?3 COLLATE NOCASE)
+         ORDER BY rank LIMIT ?4",
+    )?;
+    let matches = stmt
+        .query_map((match_expr, genre, difficulty, limit as i64), |row| {
+            Ok(TextMatch { id: row.get(0)?, bm25: row.get(1)?, snippet: row.get(2)? })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(matches)
+}
+
+// how many live records the full text search would find with no limit, plus the records in `also`
+// it wouldn't find itself (the vector side's hits), under the same filters
+pub fn count_search_matches(
+    conn: &Connection,
+    query: &str,
+    genre: Option<&str>,
+    difficulty: Option<&str>,
+    also: &[i64],
+) -> Result<usize> {
+    let terms = query_terms(query);
+    let match_expr = terms.iter().map(|t| format!("\"{}\"*", t)).collect::<Vec<_>>().join(" OR ");
+    let also = format!("[{}]", also.iter().map(i64::to_string).collect::<Vec<_>>().join(","));
+
+    // fts5 rejects an empty match expression, so with no terms only the extra ids count
+    let text_clause = if terms.is_empty() {
+        "?1 IS NOT NULL"
+    } else {
+        "a.id IN (SELECT rowid FROM archive_fts WHERE archive_fts MATCH ?1)"
+    };
+    let sql = format!(
+        "SELECT COUNT(*) FROM archive a
+         WHERE a.deleted_at IS NULL
+           AND ({} OR a.id IN (SELECT value FROM json_each(?4)))
+           AND (?2 IS NULL OR a.genre = ?2 COLLATE NOCASE)
+           AND (?3 IS NULL OR a.difficulty = ?3 COLLATE NOCASE)",
+        text_clause
+    );
+    let match_expr = (!terms.is_empty()).then_some(match_expr);
+    let count: i64 = conn.query_row(&sql, (match_expr, genre, difficulty, also), |row| row.get(0))?;
+    Ok(count as usize)
+}
+
+// a passage hit from the full text side
+#[derive(Debug, Clone)]
+pub struct PassageMatch {
+    pub passage_id: i64,
+    pub bm25: f64,
+    pub snippet: String,
+}
+
+// bm25 ranked passage search, best first, optionally inside one record
+pub fn passage_text_search(conn: &Connection, query: &str, archive_id: Option<i64>, limit: usize) -> Result<Vec<PassageMatch>> {
+    let terms = query_terms(query);
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+    let match_expr = terms.iter().map(|t| format!("\"{}\"*", t)).collect::<Vec<_>>().join(" OR ");
+
+    let mut stmt = conn.prepare(
+        "SELECT f.rowid, bm25(passage_fts) AS rank, snippet(passage_fts, 0, '<mark>', '</mark>', '…', 24)
+         FROM passage_fts f JOIN passage p ON p.id = f.rowid
+         WHERE passage_fts MATCH ?1 AND (?2 IS NULL OR p.archive_id = ?2)
+         ORDER BY rank LIMIT ?3",
+    )?;
+    let matches = stmt
+        .query_map((match_expr, archive_id, limit as i64), |row| {
+            Ok(PassageMatch { passage_id: row.get(0)?, bm25: row.get(1)?, snippet: row.get(2)? })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(matches)
+}
+
+// lowercase words of a search query, anything but letters and digits dropped
+pub fn query_terms(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+// keeps the extracted text of a record (deflated) and re-cuts its passages; pass the write's
+// transaction so the outbox entry that follows sees them
+pub fn store_document_text(conn: &Connection, archive_id: i64, document: &ExtractedDocument) -> Result<()> {
+    let joined = document.pages.join(&PAGE_SEPARATOR.to_string());
+
+    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(joined.as_bytes())?;
+    let content = encoder.finish()?;
+
+    conn.execute(
+        "INSERT OR REPLACE INTO document_text (archive_id, format, paged, original_size, content)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        (archive_id, document.format.as_str(), document.paged, joined.len() as i64, content),
+    )?;
+
+    conn.execute("DELETE FROM passage WHERE archive_id = ?1", [archive_id])?;
+    for (position, (page, text)) in split_passages(&document.pages, document.paged).into_iter().enumerate() {
+        conn.execute(
+            "INSERT INTO passage (archive_id, position, page, text) VALUES (?1, ?2, ?3, ?4)",
+            (archive_id, position as i64, page, text),
+        )?;
+    }
+    Ok(())
+}
+
+// the stored text of a record, None for records archived before text was kept
+pub fn load_document_text(conn: &Connection, archive_id: i64) -> Result<Option<StoredText>> {
+    let row = conn
+        .query_row(
+            "SELECT format, paged, content FROM document_text WHERE archive_id = ?1",
+            [archive_id],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?, row.get::<_, Vec<u8>>(2)?)),
+        )
+        .optional()?;
+    let Some((format, paged, content)) = row else {
+        return Ok(None);
+    };
+
+    let mut joined = String::new();
+    flate2::read::DeflateDecoder::new(content.as_slice())
+        .read_to_string(&mut joined)
+        .map_err(|e| Error::Io(std::io::Error::new(e.kind(), format!("stored text of record {} is corrupt: {}", archive_id, e))))?;
+
+    let pages = joined.split(PAGE_SEPARATOR).map(str::to_string).collect();
+    Ok(Some(StoredText { format, pages, paged }))
+}
+
+// every passage of a record, in document order
+pub fn load_passages(conn: &Connection, archive_id: i64) -> Result<Vec<Passage>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, archive_id, position, page, text FROM passage WHERE archive_id = ?1 ORDER BY position",
+    )?;
+    let passages = stmt
+        .query_map([archive_id], passage_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(passages)
+}
+
+pub fn load_passage(conn: &Connection, passage_id: i64) -> Result<Passage> {
+    Ok(conn.query_row(
+        "SELECT id, archive_id, position, page, text FROM passage WHERE id = ?1",
+        [passage_id],
+        passage_from_row,
+    )?)
+}
+
+// logs a change the vector index has to pick up; pass the write's transaction so both land together.
+// older changes to the record that are still waiting are folded into this one, so they get a fresh
+// set of attempts with it
+pub fn enqueue_index_change(conn: &Connection, archive_id: i64, op: IndexOp) -> Result<()> {
+    let now = unix_now();
+    conn.execute(
+        "UPDATE index_outbox SET attempts = 0, retry_after = 0 WHERE archive_id = ?1 AND processed_at IS NULL",
+        [archive_id],
+    )?;
+    conn.execute(
+        "INSERT INTO index_outbox (archive_id, op, created_at) VALUES (?1, ?2, ?3)",
+        (archive_id, op.as_str(), now),
+    )?;
+    conn.execute(
+        "INSERT INTO index_state (archive_id, status, updated_at) VALUES (?1, 'pending', ?2)
+         ON CONFLICT(archive_id) DO UPDATE SET status = 'pending', updated_at = ?2, attempts = 0, last_error = NULL",
+        (archive_id, now),
+    )?;
+    Ok(())
+}
+
+// the oldest unprocessed changes that are due, at most limit outbox rows, folded to one change per
+// record. changes waiting out a retry delay, or out of attempts, are left
+pub fn pending_index_changes(conn: &Connection, limit: usize) -> Result<Vec<IndexChange>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, archive_id, op FROM index_outbox
+         WHERE processed_at IS NULL AND attempts < ?1 AND retry_after <= ?2
+         ORDER BY id LIMIT ?3",
+    )?;
+    let rows = stmt
+        .query_map((MAX_INDEX_ATTEMPTS, unix_now(), limit as i64), |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut changes: Vec<IndexChange> = Vec::new();
+    for (outbox_id, archive_id, op) in rows {
+        let op = if op == "delete" { IndexOp::Delete } else { IndexOp::Upsert };
+        match changes.iter_mut().find(|change| change.archive_id == archive_id) {
+            Some(change) => {
+                change.outbox_ids.push(outbox_id);
+                change.op = op;
+            }
+            None => changes.push(IndexChange { outbox_ids: vec![outbox_id], archive_id, op }),
+        }
+    }
+    Ok(changes)
+}
+
+// marks the changes as done and the records as indexed (or deleted) by embedder
+pub fn complete_index_changes(conn: &mut Connection, changes: &[IndexChange], embedder: &str) -> Result<()> {
+    let now = unix_now();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    for change in changes {
+        for outbox_id in &change.outbox_ids {
+            tx.execute("UPDATE index_outbox SET processed_at = ?1 WHERE id = ?2", (now, outbox_id))?;
+        }
+        let status = match change.op {
+            IndexOp::Upsert => "indexed",
+            IndexOp::Delete => "deleted",
+        };
+        // a newer change for the same record may have come in meanwhile, that one stays pending
+        tx.execute(
+            "UPDATE index_state SET status = ?1, embedder = ?2, updated_at = ?3, attempts = 0, last_error = NULL
+             WHERE archive_id = ?4
+               AND NOT EXISTS (SELECT 1 FROM index_outbox WHERE archive_id = ?4 AND processed_at IS NULL)",
+            (status, embedder, now, change.archive_id),
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// counts a failed attempt against each of the changes and holds them back for the retry delay
+pub fn fail_index_changes(conn: &mut Connection, changes: &[IndexChange], error: &str) -> Result<()> {
+    let now = unix_now();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    for change in changes {
+        for outbox_id in &change.outbox_ids {
+            let attempts: i64 = tx.query_row(
+                "UPDATE index_outbox SET attempts = attempts + 1, last_error = ?1 WHERE id = ?2 RETURNING attempts",
+                (error, outbox_id),
+                |row| row.get(0),
+            )?;
+            tx.execute(
+                "UPDATE index_outbox SET retry_after = ?1 WHERE id = ?2",
+                (now + index_retry_delay(attempts), outbox_id),
+            )?;
+        }
+        tx.execute(
+            "UPDATE index_state SET status = 'failed', updated_at = ?1, attempts = attempts + 1, last_error = ?2
+             WHERE archive_id = ?3",
+            (now, error, change.archive_id),
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// queues every live archive record for (re)indexing, returns how many
+pub fn enqueue_full_reindex(conn: &mut Connection) -> Result<usize> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let ids = {
+        let mut stmt = tx.prepare("SELECT id FROM archive WHERE deleted_at IS NULL ORDER BY id")?;
+        stmt.query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    // whatever was still queued is superseded by the rebuild
+    tx.execute("UPDATE index_outbox SET processed_at = ?1 WHERE processed_at IS NULL", [unix_now()])?;
+    for id in &ids {
+        enqueue_index_change(&tx, *id, IndexOp::Upsert)?;
+    }
+    tx.commit()?;
+    Ok(ids.len())
+}
+
+pub fn get_index_state(conn: &Connection, archive_id: i64) -> Result<Option<IndexState>> {
+    let state = conn
+        .query_row(
+            "SELECT archive_id, status, embedder, updated_at, attempts, last_error FROM index_state WHERE archive_id = ?1",
+            [archive_id],
+            |row| {
+                Ok(IndexState {
+                    record_id: row.get(0)?,
+                    status: row.get(1)?,
+                    embedder: row.get(2)?,
+                    updated_at: row.get(3)?,
+                    attempts: row.get(4)?,
+                    last_error: row.get(5)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(state)
+}
+
+// how many records are in each index status
+pub fn index_state_counts(conn: &Connection) -> Result<BTreeMap<String, i64>> {
+    let mut stmt = conn.prepare("SELECT status, COUNT(*) FROM index_state GROUP BY status")?;
+    let counts = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+        .collect::<rusqlite::Result<BTreeMap<_, _>>>()?;
+    Ok(counts)
+}
+
+
+// helper functions
+// one live record's file, picked by `condition` on the archive row
+fn query_archived_file(conn: &Connection, condition: &str, param: impl rusqlite::ToSql) -> Result<Option<ArchivedFile>> {
+    let file = conn
+        .query_row(
+            &format!(
+                "SELECT a.id, a.file_hash, a.file_cid, a.original_filename, t.format
+                 FROM archive a LEFT JOIN document_text t ON t.archive_id = a.id
+                 WHERE {condition} AND a.deleted_at IS NULL ORDER BY a.id LIMIT 1"
+            ),
+            [param],
+            |row| {
+                Ok(ArchivedFile {
+                    record_id: row.get(0)?,
+                    file_hash: row.get(1)?,
+                    file_cid: row.get(2)?,
+                    original_filename: row.get(3)?,
+                    format: row.get(4)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(file)
+}
+
+// an archive row selected as ARCHIVE_COLUMNS; the terms are left empty for the caller to load
+pub fn archive_from_row(row: &rusqlite::Row) -> rusqlite::Result<ArchiveRecord> {
+    Ok(ArchiveRecord {
+        id: row.get(0)?,
+        genre: row.get(1)?,
+        title: row.get(2)?,
+        difficulty: row.get(3)?,
+        summary: row.get(4)?,
+        file_hash: row.get(5)?,
+        file_cid: row.get(6)?,
+        resource_type: row.get(7)?,
+        language: row.get(8)?,
+        keywords: Vec::new(),
+        topics: Vec::new(),
+        authors: Vec::new(),
+        solana_signature: row.get(9)?,
+        solana_slot: row.get(10)?,
+        solana_block_time: row.get(11)?,
+        created_at: row.get(12)?,
+        updated_at: row.get(13)?,
+    })
+}
+
+fn passage_from_row(row: &rusqlite::Row) -> rusqlite::Result<Passage> {
+    Ok(Passage {
+        id: row.get(0)?,
+        archive_id: row.get(1)?,
+        position: row.get(2)?,
+        page: row.get(3)?,
+        text: row.get(4)?,
+    })
+}
+
+// how long a change that has failed `attempts` times waits before the next try
+pub fn index_retry_delay(attempts: i64) -> i64 {
+    let doublings = (attempts - 1).clamp(0, 16) as u32;
+    (INDEX_RETRY_DELAY_SECS << doublings).min(MAX_INDEX_RETRY_DELAY_SECS)
+}
+
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+// one of EDITABLE_FIELDS as json, the way record_edit stores it
+fn metadata_field(metadata: &ExtractedMetaData, field: &str) -> serde_json::Value {
+    match field {
+        "title" => metadata.title.clone().into(),
+        "genre" => metadata.genre.clone().into(),
+        "difficulty" => metadata.difficulty.clone().into(),
+        "summary" => metadata.summary.clone().into(),
+        "resource_type" => metadata.resource_type.clone().into(),
+        "language" => metadata.language.clone().into(),
+        "keywords" => metadata.keywords.clone().into(),
+        "topics" => metadata.topics.clone().into(),
+        "authors" => metadata.authors.clone().into(),
+        _ => serde_json::Value::Null,
+    }
+}
+
+// the metadata columns and term links of one record, replaced as a whole; QueryReturnedNoRows if
+// there is no such record
+fn write_metadata(tx: &Transaction, archive_id: i64, metadata: &ExtractedMetaData) -> Result<()> {
+    let updated = tx.execute(
+        "UPDATE archive
+         SET genre = ?1, title = ?2, difficulty = ?3, summary = ?4, resource_type = ?5, language = ?6,
+             updated_at = ?8
+         WHERE id = ?7 AND deleted_at IS NULL",
+        (
+            &metadata.genre,
+            &metadata.title,
+            &metadata.difficulty,
+            &metadata.summary,
+            &metadata.resource_type,
+            &metadata.language,
+            archive_id,
+            unix_now(),
+        ),
+    )?;
+    if updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows.into());
+    }
+
+    for (_, _, join_table, _) in TERM_TABLES {
+        tx.execute(&format!("DELETE FROM {join_table} WHERE archive_id = ?1"), [archive_id])?;
+    }
+    link_terms(tx, archive_id, TERM_TABLES[0], &metadata.keywords)?;
+    link_terms(tx, archive_id, TERM_TABLES[1], &metadata.topics)?;
+    link_terms(tx, archive_id, TERM_TABLES[2], &metadata.authors)?;
+    Ok(())
+}
+
+fn link_terms(tx: &Transaction, archive_id: i64, tables: (&str, &str, &str, &str), terms: &[String]) -> Result<()> {
+    let (_, table, join_table, join_column) = tables;
+    for (position, term) in terms.iter().enumerate() {
+        // names are unique ignoring case, so "Rust" and "rust" share one row
+        tx.execute(&format!("INSERT OR IGNORE INTO {table} (name) VALUES (?1)"), [term])?;
+        let term_id: i64 = tx.query_row(
+            &format!("SELECT id FROM {table} WHERE name = ?1"),
+            [term],
+            |row| row.get(0),
+        )?;
+        tx.execute(
+            &format!("INSERT OR IGNORE INTO {join_table} (archive_id, {join_column}, position) VALUES (?1, ?2, ?3)"),
+            (archive_id, term_id, position as i64),
+        )?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::repository::ArchiveRepository;
+
+    fn state(conn: &Connection, archive_id: i64) -> IndexState {
+        get_index_state(conn, archive_id).unwrap().unwrap()
+    }
+
+    #[test]
+    fn pending_changes_fold_to_the_newest_op_per_record() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let conn = repo.conn().unwrap();
+        enqueue_index_change(&conn, 1, IndexOp::Upsert).unwrap();
+        enqueue_index_change(&conn, 2, IndexOp::Upsert).unwrap();
+        enqueue_index_change(&conn, 1, IndexOp::Upsert).unwrap();
+        enqueue_index_change(&conn, 1, IndexOp::Delete).unwrap();
+
+        let changes = pending_index_changes(&conn, 64).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].archive_id, changes[0].op, changes[0].outbox_ids.len()), (1, IndexOp::Delete, 3));
+        assert_eq!((changes[1].archive_id, changes[1].op, changes[1].outbox_ids.len()), (2, IndexOp::Upsert, 1));
+
+        // the limit counts outbox rows, not records
+        let first = pending_index_changes(&conn, 2).unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[0].outbox_ids.len(), 1);
+    }
+
+    #[test]
+    fn completed_changes_leave_newer_ones_pending() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut conn = repo.conn().unwrap();
+        enqueue_index_change(&conn, 1, IndexOp::Upsert).unwrap();
+        enqueue_index_change(&conn, 2, IndexOp::Delete).unwrap();
+        let changes = pending_index_changes(&conn, 64).unwrap();
+
+        // record 1 changed again while the batch was being embedded
+        enqueue_index_change(&conn, 1, IndexOp::Upsert).unwrap();
+        complete_index_changes(&mut conn, &changes, "hash/64").unwrap();
+
+        assert_eq!(state(&conn, 1).status, "pending");
+        let deleted = state(&conn, 2);
+        assert_eq!((deleted.status.as_str(), deleted.embedder.as_deref()), ("deleted", Some("hash/64")));
+        let left = pending_index_changes(&conn, 64).unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!((left[0].archive_id, left[0].outbox_ids.len()), (1, 1));
+    }
+
+    #[test]
+    fn failed_changes_wait_and_give_up_after_the_cap() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut conn = repo.conn().unwrap();
+        enqueue_index_change(&conn, 1, IndexOp::Upsert).unwrap();
+
+        for attempt in 1..=MAX_INDEX_ATTEMPTS {
+            let changes = pending_index_changes(&conn, 64).unwrap();
+            assert_eq!(changes.len(), 1, "attempt {}", attempt);
+            fail_index_changes(&mut conn, &changes, "embedder down").unwrap();
+
+            // held back until the retry delay is over
+            assert!(pending_index_changes(&conn, 64).unwrap().is_empty());
+            conn.execute("UPDATE index_outbox SET retry_after = 0", ()).unwrap();
+        }
+        assert!(pending_index_changes(&conn, 64).unwrap().is_empty());
+        let failed = state(&conn, 1);
+        assert_eq!((failed.status.as_str(), failed.attempts), ("failed", MAX_INDEX_ATTEMPTS));
+        assert_eq!(failed.last_error.as_deref(), Some("embedder down"));
+
+        // a new change to the record starts its attempts over, the old row with it
+        enqueue_index_change(&conn, 1, IndexOp::Upsert).unwrap();
+        let changes = pending_index_changes(&conn, 64).unwrap();
+        assert_eq!(changes[0].outbox_ids.len(), 2);
+        assert_eq!(state(&conn, 1).status, "pending");
+    }
+
+    #[test]
+    fn retry_delays_double_up_to_an_hour() {
+        assert_eq!(index_retry_delay(1), 30);
+        assert_eq!(index_retry_delay(2), 60);
+        assert_eq!(index_retry_delay(4), 240);
+        assert_eq!(index_retry_delay(MAX_INDEX_ATTEMPTS), 3600);
+        assert_eq!(index_retry_delay(100), 3600);
+    }
+}
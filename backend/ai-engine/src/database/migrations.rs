@@ -0,0 +1,530 @@
+// migrations.rs: the archive schema as an ordered list of steps, applied once each and recorded in schema_version
+use rusqlite::{Connection, OptionalExtension, Transaction, TransactionBehavior};
+
+use crate::database::database::{unix_now, TERM_TABLES};
+use crate::error::{Error, Result};
+
+
+// one step of the schema; versions only ever grow and a released step is never edited,
+// changes go into a new one
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: fn(&Transaction) -> Result<()>,
+}
+
+// databases from before versioning can be at any point of 1-6, so those steps must be safe to
+// run against a schema that already has (some of) them. from 7 on, each step runs on exactly the
+// schema the previous one left
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "archive table", up: archive_table },
+    Migration { version: 2, name: "unique file hash", up: unique_file_hash },
+    Migration { version: 3, name: "keyword, topic and author tables", up: term_tables },
+    Migration { version: 4, name: "vector index outbox", up: index_outbox },
+    Migration { version: 5, name: "document text and passages", up: document_text },
+    Migration { version: 6, name: "full text index", up: archive_fts },
+    Migration { version: 7, name: "record timestamps", up: record_timestamps },
+    Migration { version: 8, name: "record edits and soft delete", up: edits_and_soft_delete },
+    Migration { version: 9, name: "upload jobs", up: upload_jobs },
+    Migration { version: 10, name: "resumable upload sessions", up: upload_sessions },
+    Migration { version: 11, name: "original file names", up: original_filenames },
+    Migration { version: 12, name: "index retry delay", up: index_retry_delay },
+    Migration { version: 13, name: "passage vectors", up: passage_vectors },
+    Migration { version: 14, name: "upload session file path", up: upload_session_file_path },
+];
+
+// brings the database up to the newest version, each step in its own transaction; returns the
+// versions that were applied. a database newer than this binary is refused rather than guessed at
+pub fn migrate(conn: &mut Connection) -> Result<Vec<i64>> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        );",
+    )?;
+
+    let current = schema_version(conn)?;
+    let latest = MIGRATIONS.last().map_or(0, |m| m.version);
+    if current > latest {
+        return Err(Error::Migration {
+            version: current,
+            message: format!("database is at schema version {current}, this build only knows up to {latest}"),
+        });
+    }
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        (migration.up)(&tx).map_err(|e| Error::Migration { version: migration.version, message: e.to_string() })?;
+        tx.execute(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES (?1, ?2, ?3)",
+            (migration.version, migration.name, unix_now()),
+        )?;
+        tx.commit()?;
+
+        println!("Applied migration {} ({})", migration.version, migration.name);
+        applied.push(migration.version);
+    }
+    Ok(applied)
+}
+
+// the newest migration applied to this database, 0 for a fresh (or pre-versioning) one
+pub fn schema_version(conn: &Connection) -> Result<i64> {
+    let version: Option<i64> = conn
+        .query_row("SELECT MAX(version) FROM schema_version", [], |row| row.get(0))
+        .optional()?
+        .flatten();
+    Ok(version.unwrap_or(0))
+}
+
+
+// the migrations
+fn archive_table(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS archive (
+            id INTEGER PRIMARY KEY,
+            genre TEXT NOT NULL,
+            title TEXT NOT NULL,
+            difficulty TEXT NOT NULL,
+            summary TEXT NOT NULL,
+            file_hash TEXT NOT NULL,
+            file_cid TEXT NOT NULL,
+            resource_type TEXT NOT NULL DEFAULT '',
+            language TEXT NOT NULL DEFAULT '',
+            solana_signature TEXT,
+            solana_slot INTEGER,
+            solana_block_time INTEGER
+        )",
+        (),
+    )?;
+
+    // databases created before these columns existed
+    add_column_if_missing(tx, "archive", "resource_type", "TEXT NOT NULL DEFAULT ''")?;
+    add_column_if_missing(tx, "archive", "language", "TEXT NOT NULL DEFAULT ''")?;
+    add_column_if_missing(tx, "archive", "solana_signature", "TEXT")?;
+    add_column_if_missing(tx, "archive", "solana_slot", "INTEGER")?;
+    add_column_if_missing(tx, "archive", "solana_block_time", "INTEGER")?;
+    Ok(())
+}
+
+// one record per file. older databases may already hold duplicates, those keep a plain index
+// (lookups still work); once they're cleaned up a later migration can make it unique
+fn unique_file_hash(tx: &Transaction) -> Result<()> {
+    let duplicates: i64 = tx.query_row(
+        "SELECT COUNT(*) FROM (SELECT file_hash FROM archive GROUP BY file_hash HAVING COUNT(*) > 1)",
+        [],
+        |row| row.get(0),
+    )?;
+    if duplicates == 0 {
+        tx.execute_batch(
+            "DROP INDEX IF EXISTS idx_archive_file_hash;
+             CREATE UNIQUE INDEX IF NOT EXISTS ux_archive_file_hash ON archive(file_hash);",
+        )?;
+    } else {
+        println!("archive has {} duplicated file hashes, not enforcing a unique file_hash", duplicates);
+        tx.execute("CREATE INDEX IF NOT EXISTS idx_archive_file_hash ON archive(file_hash)", ())?;
+    }
+    Ok(())
+}
+
+fn term_tables(tx: &Transaction) -> Result<()> {
+    for (_, table, join_table, join_column) in TERM_TABLES {
+        tx.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE COLLATE NOCASE
+            );
+            CREATE TABLE IF NOT EXISTS {join_table} (
+                archive_id INTEGER NOT NULL REFERENCES archive(id) ON DELETE CASCADE,
+                {join_column} INTEGER NOT NULL REFERENCES {table}(id),
+                position INTEGER NOT NULL,
+                PRIMARY KEY (archive_id, {join_column})
+            );
+            CREATE INDEX IF NOT EXISTS idx_{join_table}_{join_column} ON {join_table}({join_column});"
+        ))?;
+    }
+    Ok(())
+}
+
+// the change log the vector index syncs from, and where each record stands with it
+fn index_outbox(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS index_outbox (
+            id INTEGER PRIMARY KEY,
+            archive_id INTEGER NOT NULL,
+            op TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            processed_at INTEGER,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_index_outbox_pending ON index_outbox(processed_at, id);
+        CREATE TABLE IF NOT EXISTS index_state (
+            archive_id INTEGER PRIMARY KEY,
+            status TEXT NOT NULL,
+            embedder TEXT,
+            updated_at INTEGER NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT
+        );",
+    )?;
+    Ok(())
+}
+
+// the extracted text (deflated) and the passages cut from it
+fn document_text(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS document_text (
+            archive_id INTEGER PRIMARY KEY REFERENCES archive(id) ON DELETE CASCADE,
+            format TEXT NOT NULL,
+            paged INTEGER NOT NULL,
+            original_size INTEGER NOT NULL,
+            content BLOB NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS passage (
+            id INTEGER PRIMARY KEY,
+            archive_id INTEGER NOT NULL REFERENCES archive(id) ON DELETE CASCADE,
+            position INTEGER NOT NULL,
+            page INTEGER,
+            text TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_passage_archive ON passage(archive_id, position);
+
+        CREATE VIRTUAL TABLE IF NOT EXISTS passage_fts USING fts5(
+            text, content = 'passage', content_rowid = 'id',
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+        CREATE TRIGGER IF NOT EXISTS passage_fts_insert AFTER INSERT ON passage BEGIN
+            INSERT INTO passage_fts (rowid, text) VALUES (new.id, new.text);
+        END;
+        CREATE TRIGGER IF NOT EXISTS passage_fts_delete AFTER DELETE ON passage BEGIN
+            INSERT INTO passage_fts (passage_fts, rowid, text) VALUES ('delete', old.id, old.text);
+        END;",
+    )?;
+    Ok(())
+}
+
+// full text index over title/summary/genre/keywords, kept current by triggers so no write path
+// has to remember it. rowid is the archive id
+fn archive_fts(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS archive_fts USING fts5(
+            title, summary, genre, keywords,
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS archive_fts_insert AFTER INSERT ON archive BEGIN
+            INSERT INTO archive_fts (rowid, title, summary, genre, keywords)
+            VALUES (new.id, new.title, new.summary, new.genre, '');
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS archive_fts_update AFTER UPDATE OF title, summary, genre ON archive BEGIN
+            UPDATE archive_fts SET title = new.title, summary = new.summary, genre = new.genre
+            WHERE rowid = new.id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS archive_fts_delete AFTER DELETE ON archive BEGIN
+            DELETE FROM archive_fts WHERE rowid = old.id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS archive_fts_keyword_insert AFTER INSERT ON archive_keyword BEGIN
+            UPDATE archive_fts SET keywords = (
+                SELECT COALESCE(group_concat(k.name, ' '), '') FROM archive_keyword j
+                JOIN keyword k ON k.id = j.keyword_id WHERE j.archive_id = new.archive_id
+            ) WHERE rowid = new.archive_id;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS archive_fts_keyword_delete AFTER DELETE ON archive_keyword BEGIN
+            UPDATE archive_fts SET keywords = (
+                SELECT COALESCE(group_concat(k.name, ' '), '') FROM archive_keyword j
+                JOIN keyword k ON k.id = j.keyword_id WHERE j.archive_id = old.archive_id
+            ) WHERE rowid = old.archive_id;
+        END;
+
+        -- records written before the table existed
+        INSERT INTO archive_fts (rowid, title, summary, genre, keywords)
+        SELECT a.id, a.title, a.summary, a.genre, (
+            SELECT COALESCE(group_concat(k.name, ' '), '') FROM archive_keyword j
+            JOIN keyword k ON k.id = j.keyword_id WHERE j.archive_id = a.id
+        )
+        FROM archive a WHERE a.id NOT IN (SELECT rowid FROM archive_fts);",
+    )?;
+    Ok(())
+}
+
+// when a record was archived and last changed (unix seconds). older records only have the time
+// their memo landed on chain, which is close enough; without one they stay unknown
+fn record_timestamps(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE archive ADD COLUMN created_at INTEGER;
+         ALTER TABLE archive ADD COLUMN updated_at INTEGER;
+         UPDATE archive SET created_at = solana_block_time, updated_at = solana_block_time;
+         CREATE INDEX idx_archive_created_at ON archive(created_at);",
+    )?;
+    Ok(())
+}
+
+// hand edits keep what they replaced (the first old value of a field is what the model wrote),
+// and a delete only marks the record: it stays for the audit trail, off every read path.
+// the file hash is only unique among live records, so a deleted file can be archived again
+fn edits_and_soft_delete(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE archive ADD COLUMN deleted_at INTEGER;
+         ALTER TABLE archive ADD COLUMN revocation_signature TEXT;
+         CREATE TABLE record_edit (
+            id INTEGER PRIMARY KEY,
+            archive_id INTEGER NOT NULL REFERENCES archive(id),
+            field TEXT NOT NULL,
+            old_value TEXT NOT NULL,
+            new_value TEXT NOT NULL,
+            edited_at INTEGER NOT NULL
+         );
+         CREATE INDEX idx_record_edit_archive ON record_edit(archive_id, id);",
+    )?;
+
+    // databases that kept duplicates (see unique_file_hash) only have the plain index, leave those be
+    let unique: bool = tx.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = 'ux_archive_file_hash')",
+        [],
+        |row| row.get(0),
+    )?;
+    if unique {
+        tx.execute_batch(
+            "DROP INDEX ux_archive_file_hash;
+             CREATE UNIQUE INDEX ux_archive_file_hash_live ON archive(file_hash) WHERE deleted_at IS NULL;",
+        )?;
+    }
+    Ok(())
+}
+
+// the background upload queue: one row per job, one per stage of it. each stage's output is kept
+// on the job so a retry carries on where the last attempt failed
+fn upload_jobs(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE job (
+            id TEXT PRIMARY KEY,
+            kind TEXT NOT NULL,
+            status TEXT NOT NULL,
+            file_path TEXT NOT NULL,
+            original_filename TEXT,
+            record_id INTEGER,
+            duplicate INTEGER NOT NULL DEFAULT 0,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            run_after INTEGER NOT NULL,
+            error TEXT,
+            metadata TEXT,
+            file_hash TEXT,
+            file_cid TEXT,
+            solana_signature TEXT,
+            solana_slot INTEGER,
+            solana_block_time INTEGER,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+         );
+         CREATE INDEX idx_job_runnable ON job(status, run_after);
+         CREATE TABLE job_stage (
+            job_id TEXT NOT NULL REFERENCES job(id) ON DELETE CASCADE,
+            stage TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            status TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            started_at INTEGER,
+            finished_at INTEGER,
+            error TEXT,
+            PRIMARY KEY (job_id, stage)
+         );",
+    )?;
+    Ok(())
+}
+
+// a file sent a piece at a time; received is how much of it is safely on disk, result is what
+// completing it produced
+fn upload_sessions(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE upload_session (
+            id TEXT PRIMARY KEY,
+            original_filename TEXT,
+            content_type TEXT,
+            size INTEGER,
+            received INTEGER NOT NULL DEFAULT 0,
+            status TEXT NOT NULL,
+            result TEXT,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL
+         );
+         CREATE INDEX idx_upload_session_expiry ON upload_session(expires_at);",
+    )?;
+    Ok(())
+}
+
+// the name a file was uploaded under, for downloads. records from before the job queue never
+// had one; later ones get it back from the job that archived them
+fn original_filenames(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE archive ADD COLUMN original_filename TEXT;
+         UPDATE archive SET original_filename = (
+            SELECT j.original_filename FROM job j
+            WHERE j.record_id = archive.id AND j.kind = 'upload' AND j.duplicate = 0 AND j.original_filename IS NOT NULL
+            ORDER BY j.created_at LIMIT 1
+         );",
+    )?;
+    Ok(())
+}
+
+// when a failed outbox row may be tried again (unix seconds); 0 is straight away
+fn index_retry_delay(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("ALTER TABLE index_outbox ADD COLUMN retry_after INTEGER NOT NULL DEFAULT 0;")?;
+    Ok(())
+}
+
+// passage vectors live next to their text instead of in one json file that was rewritten on every
+// sync batch; embedder says which model made the vector, so switching models re-embeds
+fn passage_vectors(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE passage ADD COLUMN embedding BLOB;
+         ALTER TABLE passage ADD COLUMN embedder TEXT;
+         ALTER TABLE passage ADD COLUMN embedded_at INTEGER;
+         CREATE INDEX IF NOT EXISTS idx_passage_embedder ON passage(embedder, archive_id);",
+    )?;
+    Ok(())
+}
+
+// where a completing session's file was moved to, so a completion that died half way doesn't
+// leave it in the upload directory for good
+fn upload_session_file_path(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("ALTER TABLE upload_session ADD COLUMN file_path TEXT;")?;
+    Ok(())
+}
+
+
+// helper functions
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"), ())?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the archive table as the first release created it, before any migration existed
+    fn baseline(rows: &[(&str, &str)]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE archive (
+                id INTEGER PRIMARY KEY,
+                genre TEXT NOT NULL,
+                title TEXT NOT NULL,
+                difficulty TEXT NOT NULL,
+                summary TEXT NOT NULL,
+                file_hash TEXT NOT NULL,
+                file_cid TEXT NOT NULL
+            );",
+        )
+        .unwrap();
+        for (title, hash) in rows {
+            conn.execute(
+                "INSERT INTO archive (genre, title, difficulty, summary, file_hash, file_cid)
+                 VALUES ('Biology', ?1, 'Beginner', 'how plants make food', ?2, 'QmCid')",
+                (title, hash),
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    fn has_index(conn: &Connection, name: &str) -> bool {
+        conn.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = ?1)", [name], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    fn all_versions() -> Vec<i64> {
+        MIGRATIONS.iter().map(|m| m.version).collect()
+    }
+
+    #[test]
+    fn baseline_rows_survive_every_step() {
+        let mut conn = baseline(&[("Photosynthesis", "aaa"), ("Cell Division", "bbb")]);
+        assert_eq!(migrate(&mut conn).unwrap(), all_versions());
+
+        let rows: Vec<(i64, String, String, String, String, Option<i64>)> = conn
+            .prepare("SELECT id, title, file_hash, resource_type, language, deleted_at FROM archive ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(rows, vec![
+            (1, "Photosynthesis".to_string(), "aaa".to_string(), String::new(), String::new(), None),
+            (2, "Cell Division".to_string(), "bbb".to_string(), String::new(), String::new(), None),
+        ]);
+
+        // the old rows were backfilled into the full text index
+        let found: i64 = conn
+            .query_row("SELECT rowid FROM archive_fts WHERE archive_fts MATCH 'photosynthesis'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(found, 1);
+        assert!(has_index(&conn, "ux_archive_file_hash_live"));
+    }
+
+    #[test]
+    fn live_hashes_are_unique_and_deleted_ones_free_up() {
+        let mut conn = baseline(&[("Photosynthesis", "aaa")]);
+        migrate(&mut conn).unwrap();
+        assert!(!has_index(&conn, "ux_archive_file_hash"));
+
+        let insert = "INSERT INTO archive (genre, title, difficulty, summary, file_hash, file_cid)
+                      VALUES ('Biology', 'Again', 'Beginner', '', 'aaa', 'QmCid')";
+        assert!(conn.execute(insert, ()).is_err());
+        conn.execute("UPDATE archive SET deleted_at = 1 WHERE id = 1", ()).unwrap();
+        conn.execute(insert, ()).unwrap();
+    }
+
+    #[test]
+    fn duplicate_hashes_keep_a_plain_index() {
+        let mut conn = baseline(&[("First copy", "aaa"), ("Second copy", "aaa")]);
+        assert_eq!(migrate(&mut conn).unwrap(), all_versions());
+
+        assert!(has_index(&conn, "idx_archive_file_hash"));
+        assert!(!has_index(&conn, "ux_archive_file_hash"));
+        assert!(!has_index(&conn, "ux_archive_file_hash_live"));
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM archive WHERE file_hash = 'aaa'", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn migrating_twice_changes_nothing() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(migrate(&mut conn).unwrap(), all_versions());
+        assert_eq!(migrate(&mut conn).unwrap(), Vec::<i64>::new());
+        assert_eq!(schema_version(&conn).unwrap(), MIGRATIONS.last().unwrap().version);
+    }
+
+    #[test]
+    fn a_newer_schema_is_refused() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let future = MIGRATIONS.last().unwrap().version + 1;
+        conn.execute("INSERT INTO schema_version (version, name, applied_at) VALUES (?1, 'from the future', 0)", [future])
+            .unwrap();
+
+        match migrate(&mut conn) {
+            Err(Error::Migration { version, .. }) => assert_eq!(version, future),
+            other => panic!("expected a migration error, got {:?}", other),
+        }
+    }
+}
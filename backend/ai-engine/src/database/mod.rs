@@ -1 +1,4 @@
-pub mod database;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod database;
+pub mod migrations;
+pub mod repository;
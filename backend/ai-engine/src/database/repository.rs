@@ -0,0 +1,570 @@
+// repository.rs: typed access to the archive over a pool of sqlite connections, so handlers never open the file themselves
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::TransactionBehavior;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::database::database::{
+    archive_from_row, delete_record, edit_record, find_archived_file_by_cid, find_record_by_hash, get_terms_for_records,
+    insert_record, load_archived_file, load_record, record_history, update_record, ArchiveRecord, ArchivedFile,
+    MetadataPatch, RecordEdit, ARCHIVE_COLUMNS, TERM_TABLES,
+};
+use crate::database::migrations::migrate;
+use crate::error::{Error, Result};
+use crate::extract::extract::ExtractedDocument;
+use crate::nlp::engine::{ExtractedMetaData, FileRecord};
+use crate::solana::solana::MemoReceipt;
+
+
+pub type PooledConn = PooledConnection<SqliteConnectionManager>;
+
+// sqlite allows one writer at a time anyway; this is mostly readers
+const POOL_SIZE: u32 = 8;
+
+// how long a write waits for another one to finish before giving up with SQLITE_BUSY. writes
+// start their transaction IMMEDIATE so they queue on this; a deferred one that upgrades from read
+// to write mid-way fails straight away instead
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// archive columns /search may LIKE-match directly; keyword/topic/author go through their join tables
+pub const SEARCHABLE_COLUMNS: [&str; 8] =
+    ["genre", "title", "difficulty", "summary", "file_hash", "file_cid", "resource_type", "language"];
+
+// columns the analytics endpoints may group by
+const COUNTABLE_COLUMNS: [&str; 4] = ["genre", "difficulty", "resource_type", "language"];
+
+// the /search filters: field + q is a LIKE match, resource_type and language are exact
+// (case-insensitive); everything given must hold
+#[derive(Debug, Clone, Default)]
+pub struct RecordSearch {
+    pub field: Option<String>,
+    pub q: Option<String>,
+    pub resource_type: Option<String>,
+    pub language: Option<String>,
+}
+
+pub const MAX_PAGE_SIZE: usize = 200;
+
+// what /metadata can be ordered by; id is also the order records were archived in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordSort {
+    #[default]
+    Id,
+    Title,
+    Created,
+}
+
+impl RecordSort {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "id" => Ok(RecordSort::Id),
+            "title" => Ok(RecordSort::Title),
+            "created" | "created_at" => Ok(RecordSort::Created),
+            other => Err(Error::InvalidInput(format!("can't sort by '{}', use id, title or created", other))),
+        }
+    }
+
+    // id breaks ties so pages never overlap or skip; records without a creation time go last
+    // either way
+    fn order_by(&self, descending: bool) -> String {
+        let dir = if descending { "DESC" } else { "ASC" };
+        match self {
+            RecordSort::Id => format!("id {dir}"),
+            RecordSort::Title => format!("title COLLATE NOCASE {dir}, id {dir}"),
+            RecordSort::Created => format!("created_at IS NULL, created_at {dir}, id {dir}"),
+        }
+    }
+}
+
+// one page of /metadata: filters (exact, case-insensitive), order and the window
+#[derive(Debug, Clone)]
+pub struct RecordQuery {
+    pub genre: Option<String>,
+    pub difficulty: Option<String>,
+    pub sort: RecordSort,
+    pub descending: bool,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for RecordQuery {
+    fn default() -> Self {
+        RecordQuery { genre: None, difficulty: None, sort: RecordSort::Id, descending: false, offset: 0, limit: 50 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordPage {
+    pub total: usize, // records matching the filters, across all pages
+    pub offset: usize,
+    pub limit: usize,
+    pub next_offset: Option<usize>, // None on the last page
+    pub results: Vec<ArchiveRecord>,
+}
+
+// cheap to clone, every clone shares the pool
+#[derive(Clone)]
+pub struct ArchiveRepository {
+    pool: Pool<SqliteConnectionManager>,
+}
+
+impl ArchiveRepository {
+    // a pool over the database file: WAL so readers don't wait on the writer, and a busy timeout
+    // so concurrent writers queue up instead of failing
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let manager = SqliteConnectionManager::file(path).with_init(|conn| {
+            conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+            conn.pragma_update(None, "synchronous", "NORMAL")?;
+            conn.busy_timeout(BUSY_TIMEOUT)
+        });
+        let pool = Pool::builder().max_size(POOL_SIZE).build(manager).map_err(pool_error)?;
+        Ok(ArchiveRepository { pool })
+    }
+
+    // a private database in memory, for tests and throwaway tools. it lives in its one connection,
+    // so never hold conn() while calling another method
+    pub fn in_memory() -> Result<Self> {
+        let pool = Pool::builder().max_size(1).build(SqliteConnectionManager::memory()).map_err(pool_error)?;
+        let repo = ArchiveRepository { pool };
+        repo.migrate()?;
+        Ok(repo)
+    }
+
+    // applies pending schema migrations; returns the versions applied
+    pub fn migrate(&self) -> Result<Vec<i64>> {
+        let mut conn = self.conn()?;
+        migrate(&mut conn)
+    }
+
+    // a connection from the pool for queries the methods below don't cover
+    pub fn conn(&self) -> Result<PooledConn> {
+        self.pool.get().map_err(pool_error)
+    }
+
+    // the new record's id; the record, its terms, text and outbox entry commit together
+    pub fn insert(
+        &self,
+        metadata: &ExtractedMetaData,
+        hash: &FileRecord,
+        anchor: &MemoReceipt,
+        document: &ExtractedDocument,
+        original_filename: Option<&str>,
+    ) -> Result<i64> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let id = insert_record(&tx, metadata, hash, anchor, document, original_filename)?;
+        tx.commit()?;
+        println!("Inserted new record {}.", id);
+        Ok(id)
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<ArchiveRecord>> {
+        let conn = self.conn()?;
+        match load_record(&conn, id) {
+            Ok(record) => Ok(Some(record)),
+            Err(Error::Database(rusqlite::Error::QueryReturnedNoRows)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // one page of records plus how many match in total
+    pub fn list(&self, query: &RecordQuery) -> Result<RecordPage> {
+        let limit = query.limit.clamp(1, MAX_PAGE_SIZE);
+
+        let mut conditions: Vec<String> = vec!["deleted_at IS NULL".to_string()];
+        let mut params: Vec<String> = Vec::new();
+        for (column, value) in [("genre", &query.genre), ("difficulty", &query.difficulty)] {
+            if let Some(value) = value {
+                conditions.push(format!("{} = ?{} COLLATE NOCASE", column, params.len() + 1));
+                params.push(value.clone());
+            }
+        }
+        let filter = format!("WHERE {}", conditions.join(" AND "));
+
+        let total: i64 = self.conn()?.query_row(
+            &format!("SELECT COUNT(*) FROM archive {filter}"),
+            rusqlite::params_from_iter(params.iter()),
+            |row| row.get(0),
+        )?;
+
+        let sql = format!(
+            "SELECT {ARCHIVE_COLUMNS} FROM archive {filter} ORDER BY {} LIMIT {limit} OFFSET {}",
+            query.sort.order_by(query.descending),
+            query.offset
+        );
+        let results = self.query_records(&sql, params)?;
+
+        let total = total as usize;
+        let next_offset = Some(query.offset + results.len()).filter(|next| !results.is_empty() && *next < total);
+        Ok(RecordPage { total, offset: query.offset, limit, next_offset, results })
+    }
+
+    pub fn search(&self, search: &RecordSearch) -> Result<Vec<ArchiveRecord>> {
+        // column names can't be bound, so only whitelisted ones are put into the sql;
+        // the values themselves are always bound
+        let mut conditions: Vec<String> = Vec::new();
+        let mut params: Vec<String> = Vec::new();
+
+        match (search.field.as_deref(), &search.q) {
+            (Some(field), Some(q)) => {
+                if SEARCHABLE_COLUMNS.contains(&field) {
+                    conditions.push(format!("a.{} LIKE ?{}", field, params.len() + 1));
+                } else if let Some((_, table, join_table, join_column)) = TERM_TABLES.iter().find(|(kind, ..)| *kind == field) {
+                    // keyword / topic / author live in join tables
+                    conditions.push(format!(
+                        "EXISTS (SELECT 1 FROM {join_table} j JOIN {table} t ON t.id = j.{join_column}
+                                 WHERE j.archive_id = a.id AND t.name LIKE ?{})",
+                        params.len() + 1
+                    ));
+                } else {
+                    return Err(Error::InvalidInput(format!("field '{}' is not searchable", field)));
+                }
+                params.push(format!("%{}%", q));
+            }
+            (Some(_), None) => return Err(Error::InvalidInput("missing 'q' query param".to_string())),
+            (None, Some(_)) => return Err(Error::InvalidInput("missing 'field' query param".to_string())),
+            (None, None) => {}
+        }
+
+        for (column, value) in [("resource_type", &search.resource_type), ("language", &search.language)] {
+            if let Some(value) = value {
+                conditions.push(format!("a.{} = ?{} COLLATE NOCASE", column, params.len() + 1));
+                params.push(value.clone());
+            }
+        }
+
+        if conditions.is_empty() {
+            return Err(Error::InvalidInput(
+                "give 'field' and 'q', or a 'resource_type' / 'language' filter".to_string(),
+            ));
+        }
+
+        let sql = format!(
+            "SELECT {} FROM archive a WHERE a.deleted_at IS NULL AND {} ORDER BY a.id",
+            ARCHIVE_COLUMNS,
+            conditions.join(" AND ")
+        );
+        self.query_records(&sql, params)
+    }
+
+    // new metadata and text for an existing record; QueryReturnedNoRows if it doesn't exist
+    pub fn update(&self, id: i64, metadata: &ExtractedMetaData, document: &ExtractedDocument) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        update_record(&tx, id, metadata, document)?;
+        tx.commit()?;
+        println!("Re-extracted metadata for record {}.", id);
+        Ok(())
+    }
+
+    // a hand edit; the fields it changed, empty if it changed nothing. QueryReturnedNoRows if there
+    // is no such record, InvalidInput if the result doesn't validate
+    pub fn edit(&self, id: i64, patch: &MetadataPatch) -> Result<Vec<RecordEdit>> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let edits = edit_record(&tx, id, patch)?;
+        tx.commit()?;
+        if !edits.is_empty() {
+            println!("Edited {} field(s) of record {}.", edits.len(), id);
+        }
+        Ok(edits)
+    }
+
+    // every hand edit of a record, oldest first
+    pub fn history(&self, id: i64) -> Result<Vec<RecordEdit>> {
+        let conn = self.conn()?;
+        record_history(&conn, id)
+    }
+
+    // soft delete; false if there was no live record to delete
+    pub fn delete(&self, id: i64) -> Result<bool> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let deleted = delete_record(&tx, id)?;
+        tx.commit()?;
+        if deleted {
+            println!("Deleted record {}.", id);
+        }
+        Ok(deleted)
+    }
+
+    // the memo that revoked a deleted record's anchor
+    pub fn set_revocation(&self, id: i64, signature: &str) -> Result<()> {
+        self.conn()?.execute("UPDATE archive SET revocation_signature = ?1 WHERE id = ?2", (signature, id))?;
+        Ok(())
+    }
+
+    // whether a live record still points at this cid, in which case it must stay pinned
+    pub fn cid_in_use(&self, file_cid: &str) -> Result<bool> {
+        let in_use = self.conn()?.query_row(
+            "SELECT EXISTS (SELECT 1 FROM archive WHERE file_cid = ?1 AND deleted_at IS NULL)",
+            [file_cid],
+            |row| row.get(0),
+        )?;
+        Ok(in_use)
+    }
+
+    // the record already holding this exact file, if any
+    pub fn find_by_hash(&self, file_hash: &str) -> Result<Option<i64>> {
+        let conn = self.conn()?;
+        find_record_by_hash(&conn, file_hash)
+    }
+
+    // the file behind a live record, for downloads
+    pub fn archived_file(&self, id: i64) -> Result<Option<ArchivedFile>> {
+        let conn = self.conn()?;
+        load_archived_file(&conn, id)
+    }
+
+    // the same by cid; only files some live record holds are found
+    pub fn archived_file_by_cid(&self, file_cid: &str) -> Result<Option<ArchivedFile>> {
+        let conn = self.conn()?;
+        find_archived_file_by_cid(&conn, file_cid)
+    }
+
+    // every record with this hash; more than one only on databases from before deduplication
+    pub fn all_by_hash(&self, file_hash: &str) -> Result<Vec<ArchiveRecord>> {
+        self.query_records(
+            &format!("SELECT {ARCHIVE_COLUMNS} FROM archive WHERE file_hash = ?1 AND deleted_at IS NULL ORDER BY id"),
+            vec![file_hash.to_string()],
+        )
+    }
+
+    // how many records share each value of one column
+    pub fn count_by(&self, column: &str) -> Result<BTreeMap<String, i64>> {
+        if !COUNTABLE_COLUMNS.contains(&column) {
+            return Err(Error::InvalidInput(format!("can't group by '{}'", column)));
+        }
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!("SELECT {column}, COUNT(*) FROM archive WHERE deleted_at IS NULL GROUP BY {column}"))?;
+        let counts = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(counts)
+    }
+
+    // runs a query selecting ARCHIVE_COLUMNS and fills in each record's terms
+    fn query_records(&self, sql: &str, params: Vec<String>) -> Result<Vec<ArchiveRecord>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(sql)?;
+        let mut records = stmt
+            .query_map(rusqlite::params_from_iter(params.iter()), archive_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let ids: Vec<i64> = records.iter().map(|record| record.id).collect();
+        let mut terms = get_terms_for_records(&conn, &ids)?;
+        for record in &mut records {
+            let record_terms = terms.remove(&record.id).unwrap_or_default();
+            record.keywords = record_terms.keywords;
+            record.topics = record_terms.topics;
+            record.authors = record_terms.authors;
+        }
+        Ok(records)
+    }
+}
+
+
+// helper functions
+fn pool_error(e: r2d2::Error) -> Error {
+    Error::Io(std::io::Error::other(format!("database pool: {}", e)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::database::{load_passages, pending_index_changes, IndexOp};
+    use crate::extract::extract::DocumentFormat;
+
+    fn metadata(title: &str, genre: &str, keywords: &[&str]) -> ExtractedMetaData {
+        ExtractedMetaData {
+            title: title.to_string(),
+            difficulty: "Beginner".to_string(),
+            genre: genre.to_string(),
+            summary: format!("a summary of {}", title),
+            resource_type: "Textbook".to_string(),
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            topics: vec!["topic".to_string()],
+            language: "en".to_string(),
+            authors: vec!["Ada Lovelace".to_string()],
+        }
+    }
+
+    fn document(text: &str) -> ExtractedDocument {
+        ExtractedDocument { format: DocumentFormat::Txt, pages: vec![text.to_string()], paged: false }
+    }
+
+    fn insert(repo: &ArchiveRepository, n: usize, genre: &str, keywords: &[&str]) -> i64 {
+        let title = format!("Book {}", n);
+        let file = FileRecord { file_hash: format!("hash-{}", n), file_cid: format!("cid-{}", n) };
+        let anchor = MemoReceipt { signature: format!("sig-{}", n), slot: Some(n as u64), block_time: None };
+        repo.insert(&metadata(&title, genre, keywords), &file, &anchor, &document(&title), Some("book.txt"))
+            .unwrap()
+    }
+
+    #[test]
+    fn inserts_and_gets_a_record_with_its_terms() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let id = insert(&repo, 1, "Science", &["rust", "sqlite"]);
+
+        let record = repo.get(id).unwrap().unwrap();
+        assert_eq!(record.title, "Book 1");
+        assert_eq!(record.keywords, vec!["rust", "sqlite"]);
+        assert_eq!(record.topics, vec!["topic"]);
+        assert_eq!(record.authors, vec!["Ada Lovelace"]);
+        assert_eq!(record.solana_signature.as_deref(), Some("sig-1"));
+        assert!(record.created_at.is_some());
+
+        assert_eq!(repo.find_by_hash("hash-1").unwrap(), Some(id));
+        assert!(repo.get(id + 1).unwrap().is_none());
+    }
+
+    #[test]
+    fn lists_in_pages() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        for n in 1..=5 {
+            insert(&repo, n, if n % 2 == 0 { "Science" } else { "History" }, &[]);
+        }
+
+        let first = repo.list(&RecordQuery { limit: 2, ..Default::default() }).unwrap();
+        assert_eq!(first.total, 5);
+        assert_eq!(first.results.iter().map(|r| r.title.as_str()).collect::<Vec<_>>(), ["Book 1", "Book 2"]);
+        assert_eq!(first.next_offset, Some(2));
+
+        let last = repo.list(&RecordQuery { offset: 4, limit: 2, ..Default::default() }).unwrap();
+        assert_eq!(last.results.len(), 1);
+        assert_eq!(last.next_offset, None);
+
+        let science = repo
+            .list(&RecordQuery { genre: Some("science".to_string()), descending: true, ..Default::default() })
+            .unwrap();
+        assert_eq!(science.total, 2);
+        assert_eq!(science.results.iter().map(|r| r.title.as_str()).collect::<Vec<_>>(), ["Book 4", "Book 2"]);
+    }
+
+    #[test]
+    fn searches_columns_and_terms() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        insert(&repo, 1, "Science", &["rust"]);
+        insert(&repo, 2, "History", &["rome"]);
+
+        let by_title = RecordSearch { field: Some("title".to_string()), q: Some("book 2".to_string()), ..Default::default() };
+        assert_eq!(repo.search(&by_title).unwrap().len(), 1);
+
+        let by_keyword = RecordSearch { field: Some("keyword".to_string()), q: Some("rus".to_string()), ..Default::default() };
+        let found = repo.search(&by_keyword).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].keywords, vec!["rust"]);
+
+        let bad_field = RecordSearch { field: Some("file_path".to_string()), q: Some("x".to_string()), ..Default::default() };
+        assert!(matches!(repo.search(&bad_field), Err(Error::InvalidInput(_))));
+        assert!(matches!(repo.search(&RecordSearch::default()), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn updates_metadata_but_not_the_file() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let id = insert(&repo, 1, "Science", &["rust"]);
+
+        repo.update(id, &metadata("Renamed", "History", &["go"]), &document("new text")).unwrap();
+        let record = repo.get(id).unwrap().unwrap();
+        assert_eq!((record.title.as_str(), record.genre.as_str()), ("Renamed", "History"));
+        assert_eq!(record.keywords, vec!["go"]);
+        assert_eq!(record.file_hash, "hash-1");
+
+        assert!(matches!(
+            repo.update(id + 1, &metadata("x", "y", &[]), &document("x")),
+            Err(Error::Database(rusqlite::Error::QueryReturnedNoRows))
+        ));
+    }
+
+    #[test]
+    fn edits_are_logged_per_field() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let id = insert(&repo, 1, "Science", &["rust"]);
+
+        let patch = MetadataPatch { title: Some("Renamed".to_string()), keywords: Some(vec!["go".to_string()]), ..Default::default() };
+        let edits = repo.edit(id, &patch).unwrap();
+        assert_eq!(edits.iter().map(|e| e.field.as_str()).collect::<Vec<_>>(), ["title", "keywords"]);
+        assert_eq!((edits[0].old_value.clone(), edits[0].new_value.clone()), ("Book 1".into(), "Renamed".into()));
+
+        // the same patch again changes nothing and logs nothing
+        assert!(repo.edit(id, &patch).unwrap().is_empty());
+        repo.edit(id, &MetadataPatch { title: Some("Renamed again".to_string()), ..Default::default() }).unwrap();
+
+        let history = repo.history(id).unwrap();
+        assert_eq!(history.iter().map(|e| e.field.as_str()).collect::<Vec<_>>(), ["title", "keywords", "title"]);
+        assert_eq!(history[1].old_value, serde_json::json!(["rust"]));
+        assert_eq!(history[2].old_value, "Renamed");
+        let record = repo.get(id).unwrap().unwrap();
+        assert_eq!((record.title.as_str(), record.keywords.clone()), ("Renamed again", vec!["go".to_string()]));
+    }
+
+    #[test]
+    fn invalid_edits_are_refused() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let id = insert(&repo, 1, "Science", &["rust"]);
+
+        for patch in [
+            MetadataPatch { title: Some("  ".to_string()), ..Default::default() },
+            MetadataPatch { difficulty: Some("Impossible".to_string()), ..Default::default() },
+            MetadataPatch { keywords: Some(Vec::new()), ..Default::default() },
+        ] {
+            assert!(matches!(repo.edit(id, &patch), Err(Error::InvalidInput(_))), "{:?}", patch);
+        }
+        assert!(repo.history(id).unwrap().is_empty());
+        assert_eq!(repo.get(id).unwrap().unwrap().title, "Book 1");
+        assert!(matches!(
+            repo.edit(id + 1, &MetadataPatch::default()),
+            Err(Error::Database(rusqlite::Error::QueryReturnedNoRows))
+        ));
+    }
+
+    #[test]
+    fn soft_delete_drops_passages_and_queues_the_index_delete() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let id = insert(&repo, 1, "Science", &[]);
+        assert!(!load_passages(&repo.conn().unwrap(), id).unwrap().is_empty());
+
+        assert!(repo.delete(id).unwrap());
+        let conn = repo.conn().unwrap();
+        assert!(load_passages(&conn, id).unwrap().is_empty());
+        let changes = pending_index_changes(&conn, 64).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].archive_id, changes[0].op), (id, IndexOp::Delete));
+        drop(conn);
+
+        repo.set_revocation(id, "revoke-sig").unwrap();
+        let revoked: Option<String> = repo
+            .conn()
+            .unwrap()
+            .query_row("SELECT revocation_signature FROM archive WHERE id = ?1", [id], |row| row.get(0))
+            .unwrap();
+        assert_eq!(revoked.as_deref(), Some("revoke-sig"));
+    }
+
+    #[test]
+    fn soft_delete_hides_the_record() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let id = insert(&repo, 1, "Science", &[]);
+        insert(&repo, 2, "Science", &[]);
+
+        assert!(repo.delete(id).unwrap());
+        assert!(!repo.delete(id).unwrap());
+        assert!(repo.get(id).unwrap().is_none());
+        assert_eq!(repo.list(&RecordQuery::default()).unwrap().total, 1);
+        assert_eq!(repo.find_by_hash("hash-1").unwrap(), None);
+        assert!(!repo.cid_in_use("cid-1").unwrap());
+
+        // the same file can be archived again once its record is gone, but only once
+        let again = insert(&repo, 1, "Science", &[]);
+        assert_ne!(again, id);
+        assert_eq!(repo.find_by_hash("hash-1").unwrap(), Some(again));
+        let file = FileRecord { file_hash: "hash-1".to_string(), file_cid: "cid-1".to_string() };
+        let anchor = MemoReceipt { signature: "sig-x".to_string(), slot: None, block_time: None };
+        assert!(repo.insert(&metadata("Copy", "Science", &[]), &file, &anchor, &document("x"), None).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+// error.rs: the one error type the whole crate returns, and how each variant looks over http
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde_json::json;
+
+use crate::extract::extract::ExtractError;
+
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("configuration error: {0}")]
+    Config(String),
+
+    #[error("invalid request: {0}")]
+    InvalidInput(String),
+
+    #[error("too large: {0}")]
+    TooLarge(String),
+
+    #[error("conflict: {0}")]
+    Conflict(String),
+
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Extraction(#[from] ExtractError),
+
+    #[error("request to the model failed: {0}")]
+    LlmTransport(#[source] reqwest::Error),
+
+    #[error("model api returned {status}: {body}")]
+    LlmApi { status: u16, body: String },
+
+    #[error("model output could not be parsed: {0}")]
+    LlmParse(String),
+
+    #[error("model output failed validation: {0}")]
+    LlmValidation(String),
+
+    #[error("gave up after {attempts} attempts, last error: {last}")]
+    LlmExhausted { attempts: usize, last: Box<Error> },
+
+    #[error("embedding failed: {0}")]
+    Embedding(String),
+
+    #[error("ipfs error: {0}")]
+    Ipfs(String),
+
+    #[error("solana error: {0}")]
+    Solana(String),
+
+    #[error("database error: {0}")]
+    Database(#[from] rusqlite::Error),
+
+    #[error("schema migration {version}: {message}")]
+    Migration { version: i64, message: String },
+}
+
+impl Error {
+    // short machine readable name for the json body
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Config(_) => "config",
+            Error::InvalidInput(_) => "bad_request",
+            Error::TooLarge(_) => "too_large",
+            Error::Conflict(_) => "conflict",
+            Error::NotFound(_) => "not_found",
+            Error::Io(_) => "io",
+            Error::Extraction(ExtractError::Unsupported(_)) => "unsupported_format",
+            Error::Extraction(_) => "extraction",
+            Error::LlmTransport(_) | Error::LlmApi { .. } => "llm_transport",
+            Error::LlmParse(_) | Error::LlmValidation(_) | Error::LlmExhausted { .. } => "llm_parse",
+            Error::Embedding(_) => "embedding",
+            Error::Ipfs(_) => "ipfs",
+            Error::Solana(_) => "solana",
+            Error::Database(_) => "database",
+            Error::Migration { .. } => "migration",
+        }
+    }
+}
+
+// runs blocking work (sqlite, zip files, the memo rpc) on tokio's blocking pool; a task that
+// panicked comes back as an io error
+pub(crate) async fn run_blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(format!("blocking task failed: {}", e))))?
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            // the client sent us something we can't work with
+            Error::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            Error::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Extraction(ExtractError::Unsupported(_)) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::Extraction(_) => StatusCode::UNPROCESSABLE_ENTITY,
+
+            // an upstream service (model, embedder, ipfs daemon, solana rpc) failed us
+            Error::LlmTransport(_)
+            | Error::LlmApi { .. }
+            | Error::LlmParse(_)
+            | Error::LlmValidation(_)
+            | Error::LlmExhausted { .. }
+            | Error::Embedding(_)
+            | Error::Ipfs(_)
+            | Error::Solana(_) => StatusCode::BAD_GATEWAY,
+
+            // our own fault
+            Error::Config(_) | Error::Io(_) | Error::Database(_) | Error::Migration { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({
+            "status": "error",
+            "error": self.kind(),
+            "message": self.to_string(),
+        }))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+
+    #[test]
+    fn every_variant_maps_to_a_status_and_kind() {
+        let cases = [
+            (Error::Config("x".into()), StatusCode::INTERNAL_SERVER_ERROR, "config"),
+            (Error::InvalidInput("x".into()), StatusCode::BAD_REQUEST, "bad_request"),
+            (Error::TooLarge("x".into()), StatusCode::PAYLOAD_TOO_LARGE, "too_large"),
+            (Error::Conflict("x".into()), StatusCode::CONFLICT, "conflict"),
+            (Error::NotFound("x".into()), StatusCode::NOT_FOUND, "not_found"),
+            (Error::Io(std::io::Error::other("x")), StatusCode::INTERNAL_SERVER_ERROR, "io"),
+            (Error::Extraction(ExtractError::Unsupported("x".into())), StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_format"),
+            (Error::Extraction(ExtractError::Malformed("x".into())), StatusCode::UNPROCESSABLE_ENTITY, "extraction"),
+            (Error::LlmApi { status: 500, body: "x".into() }, StatusCode::BAD_GATEWAY, "llm_transport"),
+            (Error::LlmParse("x".into()), StatusCode::BAD_GATEWAY, "llm_parse"),
+            (Error::LlmValidation("x".into()), StatusCode::BAD_GATEWAY, "llm_parse"),
+            (
+                Error::LlmExhausted { attempts: 3, last: Box::new(Error::LlmParse("x".into())) },
+                StatusCode::BAD_GATEWAY,
+                "llm_parse",
+            ),
+            (Error::Embedding("x".into()), StatusCode::BAD_GATEWAY, "embedding"),
+            (Error::Ipfs("x".into()), StatusCode::BAD_GATEWAY, "ipfs"),
+            (Error::Solana("x".into()), StatusCode::BAD_GATEWAY, "solana"),
+            (Error::Database(rusqlite::Error::QueryReturnedNoRows), StatusCode::INTERNAL_SERVER_ERROR, "database"),
+            (Error::Migration { version: 2, message: "x".into() }, StatusCode::INTERNAL_SERVER_ERROR, "migration"),
+        ];
+        for (error, status, kind) in cases {
+            assert_eq!((error.status_code(), error.kind()), (status, kind), "{:?}", error);
+        }
+    }
+
+    #[actix_web::test]
+    async fn the_body_carries_kind_and_message() {
+        let response = Error::NotFound("record 7".into()).error_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body, json!({ "status": "error", "error": "not_found", "message": "not found: record 7" }));
+    }
+}
@@ -0,0 +1,549 @@
+// extract.rs: works out what kind of document we were handed and pulls the plain text out of it
+use pdf_extract::{extract_text_from_mem, extract_text_from_mem_by_pages};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+
+// every format the pipeline can turn into text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentFormat {
+    Pdf,
+    Docx,
+    Epub,
+    Txt,
+    Markdown,
+    Html,
+}
+
+impl DocumentFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DocumentFormat::Pdf => "pdf",
+            DocumentFormat::Docx => "docx",
+            DocumentFormat::Epub => "epub",
+            DocumentFormat::Txt => "txt",
+            DocumentFormat::Markdown => "markdown",
+            DocumentFormat::Html => "html",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pdf" => Some(DocumentFormat::Pdf),
+            "docx" => Some(DocumentFormat::Docx),
+            "epub" => Some(DocumentFormat::Epub),
+            "txt" => Some(DocumentFormat::Txt),
+            "markdown" => Some(DocumentFormat::Markdown),
+            "html" => Some(DocumentFormat::Html),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            DocumentFormat::Pdf => "application/pdf",
+            DocumentFormat::Docx => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            DocumentFormat::Epub => "application/epub+zip",
+            DocumentFormat::Txt => "text/plain",
+            DocumentFormat::Markdown => "text/markdown",
+            DocumentFormat::Html => "text/html",
+        }
+    }
+
+    // the other way round, including the aliases clients actually send
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        match mime.trim().to_ascii_lowercase().as_str() {
+            "application/pdf" | "application/x-pdf" => Some(DocumentFormat::Pdf),
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => Some(DocumentFormat::Docx),
+            "application/epub+zip" => Some(DocumentFormat::Epub),
+            "text/plain" => Some(DocumentFormat::Txt),
+            "text/markdown" | "text/x-markdown" => Some(DocumentFormat::Markdown),
+            "text/html" | "application/xhtml+xml" => Some(DocumentFormat::Html),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExtractError {
+    #[error("unsupported format: {0}")]
+    Unsupported(String),
+
+    #[error("could not extract text from pdf: {0}")]
+    Pdf(String),
+
+    #[error("could not read document archive: {0}")]
+    Archive(#[from] zip::result::ZipError),
+
+    #[error("malformed document: {0}")]
+    Malformed(String),
+
+    #[error("document too long to summarise: {0}")]
+    TooLong(String),
+}
+
+// a document's text split the way the format splits it: one entry per page for pdfs, a single
+// entry for everything else (paged says which)
+#[derive(Debug, Clone)]
+pub struct ExtractedDocument {
+    pub format: DocumentFormat,
+    pub pages: Vec<String>,
+    pub paged: bool,
+}
+
+impl ExtractedDocument {
+    pub fn text(&self) -> String {
+        self.pages.join("\n\n")
+    }
+}
+
+// how much of the file we look at when deciding if it's text or binary
+const SNIFF_WINDOW: usize = 8 * 1024;
+
+
+// the one most callers want: sniff the bytes, then extract with the matching extractor
+pub fn extract_text_from_document(bytes: &[u8], file_name: Option<&str>) -> Result<(DocumentFormat, String), ExtractError> {
+    let format = sniff_format(bytes, file_name)?;
+    let text = extract_text(bytes, format)?;
+    Ok((format, text))
+}
+
+// decides the format from magic bytes; the file name is only used to tell markdown apart from plain text
+pub fn sniff_format(bytes: &[u8], file_name: Option<&str>) -> Result<DocumentFormat, ExtractError> {
+    if bytes.starts_with(b"%PDF-") {
+        return Ok(DocumentFormat::Pdf);
+    }
+
+    // docx and epub are both zip containers, so we have to peek inside
+    if bytes.starts_with(b"PK\x03\x04") {
+        return sniff_zip(Cursor::new(bytes));
+    }
+
+    let head = &bytes[..bytes.len().min(SNIFF_WINDOW)];
+    if !looks_like_text(head) {
+        return Err(ExtractError::Unsupported("binary data that is not pdf, docx or epub".to_string()));
+    }
+
+    let lowered = String::from_utf8_lossy(head).trim_start_matches('\u{feff}').trim_start().to_ascii_lowercase();
+    if lowered.starts_with("<!doctype html") || lowered.starts_with("<html") {
+        return Ok(DocumentFormat::Html);
+    }
+
+    let extension = file_name
+        .and_then(|name| Path::new(name).extension())
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("md") | Some("markdown") => Ok(DocumentFormat::Markdown),
+        Some("html") | Some("htm") | Some("xhtml") => Ok(DocumentFormat::Html),
+        _ => Ok(DocumentFormat::Txt),
+    }
+}
+
+// sniff_format for a file on disk, reading only as much of it as the decision needs (zips are
+// opened in place rather than loaded)
+pub fn sniff_file_format(path: &Path, file_name: Option<&str>) -> Result<DocumentFormat, ExtractError> {
+    let unreadable = |e: std::io::Error| ExtractError::Malformed(format!("could not read {}: {}", path.display(), e));
+    let mut file = File::open(path).map_err(unreadable)?;
+    let mut head = Vec::with_capacity(SNIFF_WINDOW);
+    file.by_ref().take(SNIFF_WINDOW as u64).read_to_end(&mut head).map_err(unreadable)?;
+
+    if head.starts_with(b"PK\x03\x04") {
+        return sniff_zip(file);
+    }
+    sniff_format(&head, file_name)
+}
+
+// like extract_text_from_document, but keeps pdf page boundaries so passages can cite pages
+pub fn extract_document(bytes: &[u8], file_name: Option<&str>) -> Result<ExtractedDocument, ExtractError> {
+    let format = sniff_format(bytes, file_name)?;
+    match format {
+        DocumentFormat::Pdf => Ok(ExtractedDocument { format, pages: extract_pdf_pages(bytes)?, paged: true }),
+        _ => Ok(ExtractedDocument { format, pages: vec![extract_text(bytes, format)?], paged: false }),
+    }
+}
+
+pub fn extract_text(bytes: &[u8], format: DocumentFormat) -> Result<String, ExtractError> {
+    match format {
+        DocumentFormat::Pdf => extract_pdf(bytes),
+        DocumentFormat::Docx => extract_docx(bytes),
+        DocumentFormat::Epub => extract_epub(bytes),
+        DocumentFormat::Txt | DocumentFormat::Markdown => Ok(decode_text(bytes)),
+        DocumentFormat::Html => Ok(html_to_text(&decode_text(bytes))),
+    }
+}
+
+
+// helper functions
+// pdf-extract panics on some corrupted files instead of returning an error, so we catch that here
+// rather than letting it take down the worker
+fn extract_pdf(bytes: &[u8]) -> Result<String, ExtractError> {
+    match std::panic::catch_unwind(|| extract_text_from_mem(bytes)) {
+        Ok(Ok(text)) => Ok(text),
+        Ok(Err(e)) => Err(ExtractError::Pdf(e.to_string())),
+        Err(_) => Err(ExtractError::Pdf("the pdf is corrupted or uses features we can't read".to_string())),
+    }
+}
+
+fn extract_pdf_pages(bytes: &[u8]) -> Result<Vec<String>, ExtractError> {
+    match std::panic::catch_unwind(|| extract_text_from_mem_by_pages(bytes)) {
+        Ok(Ok(pages)) => Ok(pages),
+        Ok(Err(e)) => Err(ExtractError::Pdf(e.to_string())),
+        Err(_) => Err(ExtractError::Pdf("the pdf is corrupted or uses features we can't read".to_string())),
+    }
+}
+
+fn sniff_zip<R: Read + Seek>(reader: R) -> Result<DocumentFormat, ExtractError> {
+    let mut archive = ZipArchive::new(reader)?;
+
+    // epub spec: the first entry is an uncompressed "mimetype" file
+    if let Ok(mimetype) = read_zip_entry(&mut archive, "mimetype")
+        && mimetype.trim() == "application/epub+zip"
+    {
+        return Ok(DocumentFormat::Epub);
+    }
+
+    if archive.by_name("word/document.xml").is_ok() {
+        return Ok(DocumentFormat::Docx);
+    }
+
+    Err(ExtractError::Unsupported("zip archive that is not docx or epub".to_string()))
+}
+
+// no NUL bytes and valid utf-8 (allowing a multi-byte char cut off at the end of the window)
+fn looks_like_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+fn decode_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_start_matches('\u{feff}').to_string()
+}
+
+fn read_zip_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<String, ExtractError> {
+    let mut entry = archive.by_name(name)?;
+    let mut out = String::new();
+    entry
+        .read_to_string(&mut out)
+        .map_err(|e| ExtractError::Malformed(format!("{} is not readable text: {}", name, e)))?;
+    Ok(out)
+}
+
+// word keeps the body in word/document.xml: text lives in <w:t>, paragraphs are <w:p>
+fn extract_docx(bytes: &[u8]) -> Result<String, ExtractError> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let xml = read_zip_entry(&mut archive, "word/document.xml")?;
+
+    let mut reader = Reader::from_str(&xml);
+    let mut out = String::new();
+    let mut in_text = false;
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if e.local_name().as_ref() == b"t" => in_text = true,
+            Ok(Event::End(e)) => match e.local_name().as_ref() {
+                b"t" => in_text = false,
+                b"p" => out.push('\n'),
+                _ => {}
+            },
+            Ok(Event::Empty(e)) => match e.local_name().as_ref() {
+                b"tab" => out.push('\t'),
+                b"br" | b"cr" => out.push('\n'),
+                _ => {}
+            },
+            Ok(Event::Text(t)) if in_text => {
+                let text = t.unescape().map_err(|e| ExtractError::Malformed(e.to_string()))?;
+                out.push_str(&text);
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(e) => return Err(ExtractError::Malformed(format!("word/document.xml: {}", e))),
+        }
+    }
+
+    Ok(out)
+}
+
+// epub: container.xml points at the package (.opf), whose spine lists the chapters in reading order
+fn extract_epub(bytes: &[u8]) -> Result<String, ExtractError> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+
+    let container = read_zip_entry(&mut archive, "META-INF/container.xml")?;
+    let opf_path = first_attribute(&container, b"rootfile", b"full-path")?
+        .ok_or_else(|| ExtractError::Malformed("container.xml has no rootfile".to_string()))?;
+    let opf = read_zip_entry(&mut archive, &opf_path)?;
+
+    // chapter hrefs are relative to the folder the .opf lives in
+    let base = match opf_path.rfind('/') {
+        Some(i) => &opf_path[..=i],
+        None => "",
+    };
+
+    let mut manifest = Vec::new(); // (id, href)
+    let mut spine = Vec::new(); // idrefs
+    let mut reader = Reader::from_str(&opf);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => match e.local_name().as_ref() {
+                b"item" => {
+                    if let (Some(id), Some(href)) = (attribute(&e, b"id")?, attribute(&e, b"href")?) {
+                        manifest.push((id, href));
+                    }
+                }
+                b"itemref" => {
+                    if let Some(idref) = attribute(&e, b"idref")? {
+                        spine.push(idref);
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(e) => return Err(ExtractError::Malformed(format!("{}: {}", opf_path, e))),
+        }
+    }
+
+    let mut out = String::new();
+    for idref in spine {
+        let Some((_, href)) = manifest.iter().find(|(id, _)| *id == idref) else {
+            continue;
+        };
+        let path = format!("{}{}", base, href);
+        // a broken chapter shouldn't cost us the whole book
+        match read_zip_entry(&mut archive, &path) {
+            Ok(chapter) => {
+                out.push_str(&html_to_text(&chapter));
+                out.push_str("\n\n");
+            }
+            Err(e) => println!("Skipping epub chapter {}: {}", path, e),
+        }
+    }
+
+    if out.trim().is_empty() {
+        return Err(ExtractError::Malformed("epub has no readable chapters".to_string()));
+    }
+    Ok(out)
+}
+
+fn attribute(e: &quick_xml::events::BytesStart, name: &[u8]) -> Result<Option<String>, ExtractError> {
+    for attr in e.attributes() {
+        let attr = attr.map_err(|e| ExtractError::Malformed(e.to_string()))?;
+        if attr.key.local_name().as_ref() == name {
+            let value = attr.unescape_value().map_err(|e| ExtractError::Malformed(e.to_string()))?;
+            return Ok(Some(value.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+fn first_attribute(xml: &str, element: &[u8], name: &[u8]) -> Result<Option<String>, ExtractError> {
+    let mut reader = Reader::from_str(xml);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) if e.local_name().as_ref() == element => {
+                return attribute(&e, name);
+            }
+            Ok(Event::Eof) => return Ok(None),
+            Ok(_) => {}
+            Err(e) => return Err(ExtractError::Malformed(e.to_string())),
+        }
+    }
+}
+
+// html isn't xml, so a forgiving tag stripper: drop script/style, break lines on block elements
+pub(crate) fn html_to_text(html: &str) -> String {
+    const BLOCK_TAGS: [&str; 16] = [
+        "p", "div", "br", "li", "tr", "h1", "h2", "h3", "h4", "h5", "h6", "section", "article", "blockquote", "pre", "title",
+    ];
+
+    let mut out = String::with_capacity(html.len() / 2);
+    let mut rest = html;
+
+    while let Some(open) = rest.find('<') {
+        out.push_str(&decode_entities(&rest[..open]));
+        rest = &rest[open..];
+
+        // comments can contain '>' so they need their own terminator
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map(|end| &rest[end + 3..]).unwrap_or("");
+            continue;
+        }
+
+        let Some(close) = rest.find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = &rest[1..close];
+        rest = &rest[close + 1..];
+
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        if (name == "script" || name == "style") && !tag.starts_with('/') {
+            let end_tag = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&end_tag) {
+                Some(end) => rest[end..].find('>').map(|gt| &rest[end + gt + 1..]).unwrap_or(""),
+                None => "",
+            };
+            continue;
+        }
+
+        if BLOCK_TAGS.contains(&name.as_str()) && !out.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    out.push_str(&decode_entities(rest));
+
+    // collapse the whitespace soup html leaves behind
+    out.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    // a zip holding the given entries in order, stored uncompressed like an epub's mimetype
+    fn zip(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        for (name, contents) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn docx(body: &str) -> Vec<u8> {
+        zip(&[("[Content_Types].xml", "<Types/>"), ("word/document.xml", body)])
+    }
+
+    fn epub(chapter: &str) -> Vec<u8> {
+        zip(&[
+            ("mimetype", "application/epub+zip"),
+            (
+                "META-INF/container.xml",
+                r#"<container><rootfiles><rootfile full-path="OEBPS/book.opf"/></rootfiles></container>"#,
+            ),
+            (
+                "OEBPS/book.opf",
+                r#"<package><manifest><item id="c1" href="one.xhtml"/></manifest><spine><itemref idref="c1"/></spine></package>"#,
+            ),
+            ("OEBPS/one.xhtml", chapter),
+        ])
+    }
+
+    #[test]
+    fn sniffs_pdfs_by_their_magic() {
+        assert_eq!(sniff_format(b"%PDF-1.7\n...", Some("notes.txt")).unwrap(), DocumentFormat::Pdf);
+        assert_eq!(sniff_format(b"%PDF-1.4", None).unwrap(), DocumentFormat::Pdf);
+    }
+
+    #[test]
+    fn sniffs_docx_and_epub_inside_the_zip() {
+        let body = r#"<w:document><w:body><w:p><w:r><w:t>Hello</w:t></w:r></w:p><w:p><w:r><w:t>world</w:t></w:r></w:p></w:body></w:document>"#;
+        let docx = docx(body);
+        // the name doesn't matter, the contents do
+        assert_eq!(sniff_format(&docx, Some("report.zip")).unwrap(), DocumentFormat::Docx);
+        assert_eq!(extract_text(&docx, DocumentFormat::Docx).unwrap(), "Hello\nworld\n");
+
+        let epub = epub("<html><body><h1>Chapter One</h1><p>It was a dark night.</p></body></html>");
+        assert_eq!(sniff_format(&epub, None).unwrap(), DocumentFormat::Epub);
+        assert_eq!(extract_text(&epub, DocumentFormat::Epub).unwrap().trim(), "Chapter One\nIt was a dark night.");
+    }
+
+    #[test]
+    fn a_plain_zip_is_unsupported() {
+        let plain = zip(&[("readme.txt", "just a zip")]);
+        assert!(matches!(sniff_format(&plain, Some("book.docx")), Err(ExtractError::Unsupported(_))));
+        // something that only starts like a zip isn't one
+        assert!(matches!(sniff_format(b"PK\x03\x04garbage", None), Err(ExtractError::Archive(_))));
+    }
+
+    #[test]
+    fn binary_data_is_unsupported() {
+        assert!(matches!(sniff_format(&[0x89, b'P', b'N', b'G', 0, 0, 0, 13], None), Err(ExtractError::Unsupported(_))));
+        assert!(matches!(sniff_format(&[0xff, 0xfe, 0xfd, b'a'], None), Err(ExtractError::Unsupported(_))));
+    }
+
+    #[test]
+    fn text_is_html_by_its_contents_or_markdown_by_its_name() {
+        assert_eq!(sniff_format(b"<!DOCTYPE html><p>hi</p>", Some("page.txt")).unwrap(), DocumentFormat::Html);
+        assert_eq!(sniff_format("\u{feff}  <html><body>hi</body></html>".as_bytes(), None).unwrap(), DocumentFormat::Html);
+        assert_eq!(sniff_format(b"<p>a fragment</p>", Some("page.HTM")).unwrap(), DocumentFormat::Html);
+        assert_eq!(sniff_format(b"# Title\n\nbody", Some("notes.md")).unwrap(), DocumentFormat::Markdown);
+        assert_eq!(sniff_format(b"# Title\n\nbody", Some("notes.markdown")).unwrap(), DocumentFormat::Markdown);
+        assert_eq!(sniff_format(b"# Title\n\nbody", Some("notes")).unwrap(), DocumentFormat::Txt);
+        assert_eq!(sniff_format(b"plain words", None).unwrap(), DocumentFormat::Txt);
+    }
+
+    #[test]
+    fn a_multi_byte_char_cut_by_the_window_is_still_text() {
+        let mut text = "a".repeat(SNIFF_WINDOW - 1).into_bytes();
+        text.extend("é and more".as_bytes());
+        assert_eq!(sniff_format(&text, None).unwrap(), DocumentFormat::Txt);
+    }
+
+    #[test]
+    fn sniffs_files_on_disk() {
+        let path = std::env::temp_dir().join(format!("sniff-test-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, docx("<w:document/>")).unwrap();
+        assert_eq!(sniff_file_format(&path, None).unwrap(), DocumentFormat::Docx);
+        std::fs::write(&path, b"# Title").unwrap();
+        assert_eq!(sniff_file_format(&path, Some("notes.md")).unwrap(), DocumentFormat::Markdown);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(sniff_file_format(&path, None), Err(ExtractError::Malformed(_))));
+    }
+
+    #[test]
+    fn decodes_entities_once() {
+        assert_eq!(decode_entities("fish &amp; chips"), "fish & chips");
+        // an escaped entity stays an entity, it isn't decoded twice
+        assert_eq!(decode_entities("write &amp;lt; for <"), "write &lt; for <");
+        assert_eq!(decode_entities("&lt;b&gt; &quot;q&quot; &#39;s&apos;&nbsp;x"), "<b> \"q\" 's' x");
+        assert_eq!(decode_entities("no entities"), "no entities");
+    }
+
+    #[test]
+    fn html_loses_tags_scripts_and_comments() {
+        let html = "<html><head><title>T</title><style>p { color: red }</style></head>\
+                    <body><!-- a > b --><p>One &amp;lt;two&amp;gt;</p><script>alert('<p>')</script><div>Three</div></body></html>";
+        assert_eq!(html_to_text(html), "T\nOne &lt;two&gt;\nThree");
+    }
+}
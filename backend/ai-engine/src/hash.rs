@@ -1,7 +1,9 @@
 use sha2::{Sha256, Digest};
 use std::path::Path;
 use tokio::fs;
-use anyhow::{anyhow, Context};
+use tokio::io::AsyncReadExt;
+
+use crate::error::Result;
 
 
 // basic hash function
@@ -12,15 +14,37 @@ pub fn compute_sha256(bytes: &[u8]) -> String {
     format!("{:x}", result)
 }
 
-pub async fn compute_sha256_hex<P: AsRef<Path>>(path: P) -> anyhow::Result<String> {
-    // read file bytes asynchronously
-    let bytes = fs::read(&path)
-        .await
-        .with_context(|| format!("reading file {:?}", path.as_ref()))?;
+// the same hash for bytes that arrive in pieces (an upload as it's written to disk): feed every
+// chunk in order, then finish
+#[derive(Default)]
+pub struct Sha256Stream {
+    hasher: Sha256,
+}
+
+impl Sha256Stream {
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+    }
+
+    pub fn finish(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+// a file's hash, read from disk 64 KiB at a time so a big file is never in memory whole
+pub async fn compute_sha256_hex<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut file = fs::File::open(&path).await?;
+    let mut buffer = vec![0u8; 64 * 1024];
 
     // compute SHA-256
     let mut hasher = Sha256::new();
-    hasher.update(&bytes);
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
     let digest = hasher.finalize();
 
     // return hex string
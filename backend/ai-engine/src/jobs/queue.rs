@@ -0,0 +1,501 @@
+// queue.rs: the sqlite side of the upload job queue: enqueueing, claiming, per-stage bookkeeping and reports
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::database::database::unix_now;
+use crate::error::{Error, Result};
+use crate::nlp::engine::{ExtractedMetaData, FileRecord};
+use crate::solana::solana::MemoReceipt;
+
+
+// what a job does with its file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    Upload,    // a new file: extract, add to the content store, anchor, insert
+    Reextract, // a file we already hold (?force=true): extract again and update its record
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::Upload => "upload",
+            JobKind::Reextract => "reextract",
+        }
+    }
+
+    // the stages a job of this kind runs, in order
+    pub fn stages(&self) -> &'static [Stage] {
+        match self {
+            JobKind::Upload => &[Stage::Extract, Stage::Store, Stage::Anchor, Stage::Record],
+            JobKind::Reextract => &[Stage::Extract, Stage::Record],
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "upload" => Some(JobKind::Upload),
+            "reextract" => Some(JobKind::Reextract),
+            _ => None,
+        }
+    }
+}
+
+// one step of the pipeline; each is retried on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Extract, // text extraction and the llm call
+    Store,   // hash + content store put
+    Anchor,  // the solana memo
+    Record,  // the database write
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Extract => "extract",
+            Stage::Store => "store",
+            Stage::Anchor => "anchor",
+            Stage::Record => "record",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "extract" => Some(Stage::Extract),
+            "store" => Some(Stage::Store),
+            "anchor" => Some(Stage::Anchor),
+            "record" => Some(Stage::Record),
+            _ => None,
+        }
+    }
+}
+
+// a claimed job as the worker sees it: the file, the stages still to run and whatever the stages
+// before them produced (on an earlier attempt too)
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: String,
+    pub kind: JobKind,
+    pub file_path: String,
+    pub file_hash: Option<String>, // taken while the file was received, so the store stage needn't
+    pub original_filename: Option<String>,
+    pub record_id: Option<i64>,
+    pub pending: Vec<Stage>,
+    pub metadata: Option<ExtractedMetaData>,
+    pub file_record: Option<FileRecord>,
+    pub anchor: Option<MemoReceipt>,
+}
+
+// what a finished stage hands on to the ones after it
+#[derive(Debug, Clone)]
+pub enum StageOutput {
+    Metadata(ExtractedMetaData),
+    Stored(FileRecord),
+    Anchored(MemoReceipt),
+    Recorded { record_id: i64, duplicate: bool },
+}
+
+impl Job {
+    // keeps a stage's output for the stages after it
+    pub fn apply(&mut self, output: StageOutput) {
+        match output {
+            StageOutput::Metadata(metadata) => self.metadata = Some(metadata),
+            StageOutput::Stored(file_record) => self.file_record = Some(file_record),
+            StageOutput::Anchored(anchor) => self.anchor = Some(anchor),
+            StageOutput::Recorded { record_id, .. } => self.record_id = Some(record_id),
+        }
+    }
+}
+
+// one stage as GET /jobs/{id} shows it: pending | running | done | failed
+#[derive(Debug, Clone, Serialize)]
+pub struct StageReport {
+    pub stage: String,
+    pub status: String,
+    pub attempts: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    pub error: Option<String>,
+}
+
+// a job's progress: status is queued | running | succeeded | failed, stage is the one running or
+// up next. record_id is there once the record is written
+#[derive(Debug, Clone, Serialize)]
+pub struct JobReport {
+    pub id: String,
+    pub kind: String,
+    pub status: String,
+    pub stage: Option<String>,
+    pub original_filename: Option<String>,
+    pub record_id: Option<i64>,
+    pub duplicate: bool, // the same file was archived by another job first, record_id is that record
+    pub attempts: i64,
+    pub error: Option<String>,
+    pub retry_at: Option<i64>, // when a job waiting out a failed attempt runs again
+    pub file_hash: Option<String>,
+    pub file_cid: Option<String>,
+    pub solana_signature: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub stages: Vec<StageReport>,
+}
+
+
+// queues a file for the workers; returns the job id. file_hash is the file's sha-256 if the upload
+// already worked it out, record_id the record a reextract updates
+pub fn enqueue_job(
+    conn: &mut Connection,
+    kind: JobKind,
+    file_path: &str,
+    file_hash: Option<&str>,
+    original_filename: Option<&str>,
+    record_id: Option<i64>,
+) -> Result<String> {
+    let id = Uuid::new_v4().to_string();
+    let now = unix_now();
+
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    tx.execute(
+        "INSERT INTO job (id, kind, status, file_path, file_hash, original_filename, record_id, run_after, created_at, updated_at)
+         VALUES (?1, ?2, 'queued', ?3, ?4, ?5, ?6, ?7, ?7, ?7)",
+        (&id, kind.as_str(), file_path, file_hash, original_filename, record_id, now),
+    )?;
+    for (position, stage) in kind.stages().iter().enumerate() {
+        tx.execute(
+            "INSERT INTO job_stage (job_id, stage, position, status) VALUES (?1, ?2, ?3, 'pending')",
+            (&id, stage.as_str(), position as i64),
+        )?;
+    }
+    tx.commit()?;
+    Ok(id)
+}
+
+// takes the oldest runnable job and marks it running, so no other worker picks it up
+pub fn claim_job(conn: &mut Connection) -> Result<Option<Job>> {
+    let now = unix_now();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let id: Option<String> = tx
+        .query_row(
+            "SELECT id FROM job WHERE status = 'queued' AND run_after <= ?1 ORDER BY run_after, created_at LIMIT 1",
+            [now],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let Some(id) = id else { return Ok(None) };
+
+    tx.execute(
+        "UPDATE job SET status = 'running', attempts = attempts + 1, updated_at = ?1 WHERE id = ?2",
+        (now, &id),
+    )?;
+    let job = load_job(&tx, &id)?;
+    tx.commit()?;
+    Ok(Some(job))
+}
+
+// a stage is starting; returns which attempt at it this is (1 based)
+pub fn start_stage(conn: &Connection, job_id: &str, stage: Stage) -> Result<i64> {
+    let attempts = conn.query_row(
+        "UPDATE job_stage SET status = 'running', attempts = attempts + 1, started_at = ?1, finished_at = NULL
+         WHERE job_id = ?2 AND stage = ?3
+         RETURNING attempts",
+        (unix_now(), job_id, stage.as_str()),
+        |row| row.get(0),
+    )?;
+    Ok(attempts)
+}
+
+// stores a stage's output on the job and marks the stage done; the job succeeds with its last stage.
+// pass a transaction, the record stage commits its database write with this
+pub fn complete_stage(conn: &Connection, job_id: &str, stage: Stage, output: &StageOutput) -> Result<()> {
+    let now = unix_now();
+    match output {
+        StageOutput::Metadata(metadata) => {
+            let json = serde_json::to_string(metadata)
+                .map_err(|e| Error::Io(std::io::Error::other(format!("could not serialise metadata: {}", e))))?;
+            conn.execute("UPDATE job SET metadata = ?1 WHERE id = ?2", (json, job_id))?;
+        }
+        StageOutput::Stored(file_record) => {
+            conn.execute(
+                "UPDATE job SET file_hash = ?1, file_cid = ?2 WHERE id = ?3",
+                (&file_record.file_hash, &file_record.file_cid, job_id),
+            )?;
+        }
+        StageOutput::Anchored(anchor) => {
+            conn.execute(
+                "UPDATE job SET solana_signature = ?1, solana_slot = ?2, solana_block_time = ?3 WHERE id = ?4",
+                (&anchor.signature, anchor.slot.map(|slot| slot as i64), anchor.block_time, job_id),
+            )?;
+        }
+        StageOutput::Recorded { record_id, duplicate } => {
+            conn.execute(
+                "UPDATE job SET record_id = ?1, duplicate = ?2 WHERE id = ?3",
+                (record_id, duplicate, job_id),
+            )?;
+        }
+    }
+
+    conn.execute(
+        "UPDATE job_stage SET status = 'done', finished_at = ?1, error = NULL WHERE job_id = ?2 AND stage = ?3",
+        (now, job_id, stage.as_str()),
+    )?;
+    conn.execute(
+        "UPDATE job SET error = NULL, updated_at = ?1,
+             status = CASE WHEN EXISTS (SELECT 1 FROM job_stage WHERE job_id = ?2 AND status != 'done')
+                           THEN status ELSE 'succeeded' END
+         WHERE id = ?2",
+        (now, job_id),
+    )?;
+    Ok(())
+}
+
+// a stage failed: with retry_at the job goes back in the queue for then, without it the job is failed
+pub fn fail_stage(conn: &mut Connection, job_id: &str, stage: Stage, error: &str, retry_at: Option<i64>) -> Result<()> {
+    let now = unix_now();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    tx.execute(
+        "UPDATE job_stage SET status = 'failed', finished_at = ?1, error = ?2 WHERE job_id = ?3 AND stage = ?4",
+        (now, error, job_id, stage.as_str()),
+    )?;
+    match retry_at {
+        Some(retry_at) => tx.execute(
+            "UPDATE job SET status = 'queued', run_after = ?1, error = ?2, updated_at = ?3 WHERE id = ?4",
+            (retry_at, error, now, job_id),
+        )?,
+        None => tx.execute(
+            "UPDATE job SET status = 'failed', error = ?1, updated_at = ?2 WHERE id = ?3",
+            (error, now, job_id),
+        )?,
+    };
+    tx.commit()?;
+    Ok(())
+}
+
+// jobs that were running when the server stopped go back in the queue; returns how many. finished
+// stages stay finished, the one that was cut off runs again
+pub fn requeue_interrupted(conn: &mut Connection) -> Result<usize> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    tx.execute(
+        "UPDATE job_stage SET status = 'pending' WHERE status = 'running'
+         AND job_id IN (SELECT id FROM job WHERE status = 'running')",
+        (),
+    )?;
+    let requeued = tx.execute("UPDATE job SET status = 'queued', updated_at = ?1 WHERE status = 'running'", [unix_now()])?;
+    tx.commit()?;
+    Ok(requeued)
+}
+
+pub fn job_report(conn: &Connection, job_id: &str) -> Result<Option<JobReport>> {
+    let report = conn
+        .query_row(
+            "SELECT id, kind, status, original_filename, record_id, duplicate, attempts, error, run_after,
+                    file_hash, file_cid, solana_signature, created_at, updated_at
+             FROM job WHERE id = ?1",
+            [job_id],
+            |row| {
+                let status: String = row.get(2)?;
+                let attempts: i64 = row.get(6)?;
+                let run_after: i64 = row.get(8)?;
+                Ok(JobReport {
+                    id: row.get(0)?,
+                    kind: row.get(1)?,
+                    retry_at: (status == "queued" && attempts > 0).then_some(run_after),
+                    status,
+                    stage: None,
+                    original_filename: row.get(3)?,
+                    record_id: row.get(4)?,
+                    duplicate: row.get(5)?,
+                    attempts,
+                    error: row.get(7)?,
+                    file_hash: row.get(9)?,
+                    file_cid: row.get(10)?,
+                    solana_signature: row.get(11)?,
+                    created_at: row.get(12)?,
+                    updated_at: row.get(13)?,
+                    stages: Vec::new(),
+                })
+            },
+        )
+        .optional()?;
+    let Some(mut report) = report else { return Ok(None) };
+
+    let mut stmt = conn.prepare(
+        "SELECT stage, status, attempts, started_at, finished_at, error FROM job_stage
+         WHERE job_id = ?1 ORDER BY position",
+    )?;
+    report.stages = stmt
+        .query_map([job_id], |row| {
+            Ok(StageReport {
+                stage: row.get(0)?,
+                status: row.get(1)?,
+                attempts: row.get(2)?,
+                started_at: row.get(3)?,
+                finished_at: row.get(4)?,
+                error: row.get(5)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    report.stage = report.stages.iter().find(|s| s.status != "done").map(|s| s.stage.clone());
+    Ok(Some(report))
+}
+
+
+// helper functions
+fn load_job(conn: &Connection, job_id: &str) -> Result<Job> {
+    let mut job = conn.query_row(
+        "SELECT kind, file_path, original_filename, record_id, metadata, file_hash, file_cid,
+                solana_signature, solana_slot, solana_block_time
+         FROM job WHERE id = ?1",
+        [job_id],
+        |row| {
+            let kind: String = row.get(0)?;
+            let kind = JobKind::from_name(&kind)
+                .ok_or_else(|| rusqlite::Error::InvalidColumnType(0, format!("kind '{}'", kind), rusqlite::types::Type::Text))?;
+            // a stored output we can't read back is as good as none
+            let metadata: Option<String> = row.get(4)?;
+            let file_hash: Option<String> = row.get(5)?;
+            let file_cid: Option<String> = row.get(6)?;
+            let signature: Option<String> = row.get(7)?;
+            let slot: Option<i64> = row.get(8)?;
+            let block_time: Option<i64> = row.get(9)?;
+            Ok(Job {
+                id: job_id.to_string(),
+                kind,
+                file_path: row.get(1)?,
+                file_hash: file_hash.clone(),
+                original_filename: row.get(2)?,
+                record_id: row.get(3)?,
+                pending: Vec::new(),
+                metadata: metadata.and_then(|json| serde_json::from_str(&json).ok()),
+                file_record: file_hash.zip(file_cid).map(|(file_hash, file_cid)| FileRecord { file_hash, file_cid }),
+                anchor: signature.map(|signature| MemoReceipt {
+                    signature,
+                    slot: slot.map(|slot| slot as u64),
+                    block_time,
+                }),
+            })
+        },
+    )?;
+
+    let mut stmt = conn.prepare("SELECT stage FROM job_stage WHERE job_id = ?1 AND status != 'done' ORDER BY position")?;
+    job.pending = stmt
+        .query_map([job_id], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .iter()
+        .filter_map(|name| Stage::from_name(name))
+        .collect();
+    Ok(job)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::repository::ArchiveRepository;
+
+    fn status(conn: &Connection, job_id: &str) -> (String, i64, i64) {
+        conn.query_row("SELECT status, attempts, run_after FROM job WHERE id = ?1", [job_id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .unwrap()
+    }
+
+    fn stage_status(conn: &Connection, job_id: &str, stage: Stage) -> String {
+        conn.query_row(
+            "SELECT status FROM job_stage WHERE job_id = ?1 AND stage = ?2",
+            (job_id, stage.as_str()),
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn claims_the_oldest_runnable_job_once() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut conn = repo.conn().unwrap();
+        let later = enqueue_job(&mut conn, JobKind::Upload, "/tmp/later.txt", None, None, None).unwrap();
+        let first = enqueue_job(&mut conn, JobKind::Upload, "/tmp/first.txt", Some("hash"), Some("first.txt"), None).unwrap();
+        let waiting = enqueue_job(&mut conn, JobKind::Upload, "/tmp/waiting.txt", None, None, None).unwrap();
+        let now = unix_now();
+        conn.execute("UPDATE job SET run_after = ?1 WHERE id = ?2", (now - 10, &first)).unwrap();
+        conn.execute("UPDATE job SET run_after = ?1 WHERE id = ?2", (now + 3600, &waiting)).unwrap();
+
+        let job = claim_job(&mut conn).unwrap().unwrap();
+        assert_eq!(job.id, first);
+        assert_eq!((job.file_path.as_str(), job.file_hash.as_deref()), ("/tmp/first.txt", Some("hash")));
+        assert_eq!(job.pending, JobKind::Upload.stages());
+        assert_eq!(status(&conn, &first).0, "running");
+        assert_eq!(status(&conn, &first).1, 1);
+
+        // a running job isn't handed out again, and one waiting out a retry isn't due yet
+        assert_eq!(claim_job(&mut conn).unwrap().unwrap().id, later);
+        assert!(claim_job(&mut conn).unwrap().is_none());
+    }
+
+    #[test]
+    fn a_failed_stage_is_retried_or_fails_the_job() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut conn = repo.conn().unwrap();
+        let id = enqueue_job(&mut conn, JobKind::Upload, "/tmp/notes.txt", None, None, None).unwrap();
+        let stage = JobKind::Upload.stages()[0];
+
+        claim_job(&mut conn).unwrap().unwrap();
+        assert_eq!(start_stage(&conn, &id, stage).unwrap(), 1);
+        let retry_at = unix_now() + 3600;
+        fail_stage(&mut conn, &id, stage, "provider down", Some(retry_at)).unwrap();
+        assert_eq!(status(&conn, &id), ("queued".to_string(), 1, retry_at));
+        assert_eq!(stage_status(&conn, &id, stage), "failed");
+        let report = job_report(&conn, &id).unwrap().unwrap();
+        assert_eq!((report.retry_at, report.error.as_deref()), (Some(retry_at), Some("provider down")));
+        assert_eq!(report.stage.as_deref(), Some(stage.as_str()));
+        assert!(claim_job(&mut conn).unwrap().is_none());
+
+        conn.execute("UPDATE job SET run_after = 0 WHERE id = ?1", [&id]).unwrap();
+        let job = claim_job(&mut conn).unwrap().unwrap();
+        assert_eq!(job.pending[0], stage);
+        assert_eq!(start_stage(&conn, &id, stage).unwrap(), 2);
+        fail_stage(&mut conn, &id, stage, "not a document", None).unwrap();
+        assert_eq!(status(&conn, &id).0, "failed");
+        let report = job_report(&conn, &id).unwrap().unwrap();
+        assert_eq!((report.retry_at, report.attempts), (None, 2));
+        assert!(claim_job(&mut conn).unwrap().is_none());
+    }
+
+    #[test]
+    fn interrupted_jobs_go_back_in_the_queue() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut conn = repo.conn().unwrap();
+        let id = enqueue_job(&mut conn, JobKind::Upload, "/tmp/notes.txt", None, None, None).unwrap();
+        let finished = enqueue_job(&mut conn, JobKind::Upload, "/tmp/other.txt", None, None, None).unwrap();
+        conn.execute("UPDATE job SET status = 'failed' WHERE id = ?1", [&finished]).unwrap();
+        let stages = JobKind::Upload.stages();
+
+        claim_job(&mut conn).unwrap().unwrap();
+        start_stage(&conn, &id, stages[0]).unwrap();
+        let metadata = ExtractedMetaData {
+            title: "Photosynthesis in Plants".to_string(),
+            difficulty: "Beginner".to_string(),
+            genre: "Science".to_string(),
+            summary: "notes".to_string(),
+            resource_type: "Notes".to_string(),
+            keywords: Vec::new(),
+            topics: Vec::new(),
+            language: "en".to_string(),
+            authors: Vec::new(),
+        };
+        complete_stage(&conn, &id, stages[0], &StageOutput::Metadata(metadata)).unwrap();
+        start_stage(&conn, &id, stages[1]).unwrap();
+
+        assert_eq!(requeue_interrupted(&mut conn).unwrap(), 1);
+        assert_eq!(status(&conn, &id).0, "queued");
+        assert_eq!(status(&conn, &finished).0, "failed");
+        assert_eq!(stage_status(&conn, &id, stages[0]), "done");
+        assert_eq!(stage_status(&conn, &id, stages[1]), "pending");
+
+        // the next claim picks up at the stage that was cut off
+        let job = claim_job(&mut conn).unwrap().unwrap();
+        assert_eq!(job.pending, stages[1..].to_vec());
+        assert_eq!(job.metadata.unwrap().title, "Photosynthesis in Plants");
+        assert_eq!(requeue_interrupted(&mut conn).unwrap(), 1);
+    }
+}
@@ -0,0 +1,478 @@
+// worker.rs: runs queued uploads through extraction, the content store, the memo and the database write in the background
+use rusqlite::{Connection, TransactionBehavior};
+use std::sync::Arc;
+use std::time::Duration;
+use dotenv::dotenv;
+use tokio::sync::Notify;
+
+use crate::config::env_u64;
+use crate::database::database::{find_record_by_hash, insert_record, unix_now, update_record};
+use crate::database::repository::ArchiveRepository;
+use crate::error::{run_blocking, Error, Result};
+use crate::extract::extract::{extract_document, ExtractedDocument};
+use crate::jobs::queue::{
+    claim_job, complete_stage, enqueue_job, fail_stage, job_report, requeue_interrupted, start_stage, Job, JobKind,
+    JobReport, Stage, StageOutput,
+};
+use crate::nlp::chunker::ChunkConfig;
+use crate::nlp::engine::{get_meta_data_and_document, package_hash_and_cid};
+use crate::nlp::provider::MetadataExtractor;
+use crate::solana::solana::MemoSender;
+use crate::storage::store::ContentStore;
+use crate::vector::sync::IndexSync;
+
+
+// longest we wait between two attempts at a stage, however many failed before
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(600);
+
+// how the workers run, read from the environment (.env works too)
+//
+//   JOB_WORKERS             jobs processed at the same time (default 2)
+//   JOB_MAX_ATTEMPTS        attempts per stage before the job is failed (default 3)
+//   JOB_RETRY_DELAY_SECS    wait before the second attempt, doubled for every one after (default 15)
+//   JOB_POLL_INTERVAL_SECS  how often the queue is checked when nobody pokes the workers (default 5)
+#[derive(Debug, Clone)]
+pub struct JobConfig {
+    pub workers: usize,
+    pub max_attempts: i64,
+    pub retry_delay: Duration,
+    pub poll_interval: Duration,
+}
+
+impl JobConfig {
+    pub fn from_env() -> Result<Self> {
+        // load the dotenv variables
+        dotenv().ok();
+
+        Ok(JobConfig {
+            workers: env_u64("JOB_WORKERS", 2, 1)? as usize,
+            max_attempts: env_u64("JOB_MAX_ATTEMPTS", 3, 1)? as i64,
+            retry_delay: Duration::from_secs(env_u64("JOB_RETRY_DELAY_SECS", 15, 1)?),
+            poll_interval: Duration::from_secs(env_u64("JOB_POLL_INTERVAL_SECS", 5, 1)?),
+        })
+    }
+}
+
+// the upload pipeline behind /api/upload: handlers only store the file and enqueue a job, the
+// workers run its stages, each one retried on its own, and record how far every job got
+pub struct JobQueue {
+    repo: ArchiveRepository,
+    extractor: Arc<dyn MetadataExtractor>,
+    chunking: ChunkConfig,
+    store: Arc<dyn ContentStore>,
+    memo_sender: Arc<MemoSender>,
+    index_sync: Arc<IndexSync>,
+    config: JobConfig,
+    wake: Notify,
+}
+
+impl JobQueue {
+    pub fn new(
+        repo: ArchiveRepository,
+        extractor: Arc<dyn MetadataExtractor>,
+        chunking: ChunkConfig,
+        store: Arc<dyn ContentStore>,
+        memo_sender: Arc<MemoSender>,
+        index_sync: Arc<IndexSync>,
+        config: JobConfig,
+    ) -> Self {
+        JobQueue { repo, extractor, chunking, store, memo_sender, index_sync, config, wake: Notify::new() }
+    }
+
+    // how many run() loops the server should spawn
+    pub fn workers(&self) -> usize {
+        self.config.workers
+    }
+
+    // queues a stored file and wakes a worker; returns the job id
+    pub async fn enqueue(
+        &self,
+        kind: JobKind,
+        file_path: String,
+        file_hash: Option<String>,
+        original_filename: Option<String>,
+        record_id: Option<i64>,
+    ) -> Result<String> {
+        let id = self
+            .with_db(move |conn| {
+                enqueue_job(conn, kind, &file_path, file_hash.as_deref(), original_filename.as_deref(), record_id)
+            })
+            .await?;
+        self.notify();
+        Ok(id)
+    }
+
+    pub async fn report(&self, job_id: String) -> Result<Option<JobReport>> {
+        self.with_db(move |conn| job_report(conn, &job_id)).await
+    }
+
+    // puts jobs a previous run was in the middle of back in the queue; call before the workers start
+    pub async fn recover(&self) -> Result<usize> {
+        self.with_db(requeue_interrupted).await
+    }
+
+    // a job was queued, don't wait for the next tick
+    pub fn notify(&self) {
+        self.wake.notify_one();
+    }
+
+    // one worker; errors are logged and the loop carries on, never fatal
+    pub async fn run(&self) {
+        loop {
+            match self.run_next().await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => println!("Job worker failed: {}", e),
+            }
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep(self.config.poll_interval) => {}
+            }
+        }
+    }
+
+    // claims one runnable job and runs its remaining stages; false if there was nothing to run
+    async fn run_next(&self) -> Result<bool> {
+        let Some(mut job) = self.with_db(claim_job).await? else {
+            return Ok(false);
+        };
+        // there may be more waiting, let an idle worker look
+        self.wake.notify_one();
+
+        // the extracted text only lives for this attempt, it isn't kept on the job
+        let mut document = None;
+        for stage in job.pending.clone() {
+            let (job_id, attempt_stage) = (job.id.clone(), stage);
+            let attempt = self.with_db(move |conn| start_stage(conn, &job_id, attempt_stage)).await?;
+            println!("Job {}: {} (attempt {})", job.id, stage.as_str(), attempt);
+
+            if let Err(e) = self.run_stage(&mut job, stage, &mut document).await {
+                let retry_at = (!is_permanent(&e) && attempt < self.config.max_attempts)
+                    .then(|| unix_now() + self.retry_delay(attempt).as_secs() as i64);
+                match retry_at {
+                    Some(at) => println!("Job {}: {} failed, retrying at {}: {}", job.id, stage.as_str(), at, e),
+                    None => println!("Job {}: {} failed for good: {}", job.id, stage.as_str(), e),
+                }
+                let (job_id, message) = (job.id.clone(), e.to_string());
+                self.with_db(move |conn| fail_stage(conn, &job_id, stage, &message, retry_at)).await?;
+                // nothing will read the upload again once the job has given up
+                if retry_at.is_none() {
+                    discard_upload(&job).await;
+                }
+                return Ok(true);
+            }
+        }
+
+        // the record write queued the record for the vector index; the content store has the file now
+        self.index_sync.notify();
+        discard_upload(&job).await;
+        println!("Job {} done, record {:?}", job.id, job.record_id);
+        Ok(true)
+    }
+
+    // runs one stage and stores what it produced on the job
+    async fn run_stage(&self, job: &mut Job, stage: Stage, document: &mut Option<ExtractedDocument>) -> Result<()> {
+        let output = match stage {
+            Stage::Extract => {
+                let (metadata, extracted) =
+                    get_meta_data_and_document(job.file_path.clone(), self.extractor.as_ref(), &self.chunking).await?;
+                *document = Some(extracted);
+                StageOutput::Metadata(metadata)
+            }
+            Stage::Store => {
+                let stored = package_hash_and_cid(&job.file_path, job.file_hash.clone(), self.store.as_ref()).await?;
+                StageOutput::Stored(stored)
+            }
+            Stage::Anchor => {
+                let file_record = job.file_record.clone().ok_or_else(|| missing_output(job, Stage::Store))?;
+                // the rpc client is blocking (and so is the airdrop polling)
+                let sender = self.memo_sender.clone();
+                let receipt = tokio::task::spawn_blocking(move || sender.send_memo(&file_record.file_hash, &file_record.file_cid))
+                    .await
+                    .map_err(|e| Error::Io(std::io::Error::other(format!("memo task failed: {}", e))))??;
+                StageOutput::Anchored(receipt)
+            }
+            Stage::Record => {
+                // a retry after the extract stage doesn't have the text any more; pulling it out
+                // again is cheap next to another llm call
+                let extracted = match document.take() {
+                    Some(extracted) => extracted,
+                    None => {
+                        let bytes = tokio::fs::read(&job.file_path).await?;
+                        extract_document(&bytes, Some(&job.file_path))?
+                    }
+                };
+                let output = self.record(job, extracted).await?;
+                job.apply(output);
+                return Ok(());
+            }
+        };
+
+        let (job_id, saved) = (job.id.clone(), output.clone());
+        self.with_db(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            complete_stage(&tx, &job_id, stage, &saved)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+        job.apply(output);
+        Ok(())
+    }
+
+    // the database write, committed together with the stage being marked done so a retry can never
+    // insert the record twice
+    async fn record(&self, job: &Job, document: ExtractedDocument) -> Result<StageOutput> {
+        let metadata = job.metadata.clone().ok_or_else(|| missing_output(job, Stage::Extract))?;
+        let (job_id, kind, record_id) = (job.id.clone(), job.kind, job.record_id);
+        let original_filename = job.original_filename.clone();
+        let (file_record, anchor) = match kind {
+            JobKind::Upload => (
+                Some(job.file_record.clone().ok_or_else(|| missing_output(job, Stage::Store))?),
+                Some(job.anchor.clone().ok_or_else(|| missing_output(job, Stage::Anchor))?),
+            ),
+            JobKind::Reextract => (None, None),
+        };
+
+        self.with_db(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let output = match (kind, file_record, anchor) {
+                (JobKind::Upload, Some(file_record), Some(anchor)) => {
+                    // the same file can be queued twice before either job gets this far
+                    match find_record_by_hash(&tx, &file_record.file_hash)? {
+                        Some(existing) => StageOutput::Recorded { record_id: existing, duplicate: true },
+                        None => StageOutput::Recorded {
+                            record_id: insert_record(
+                                &tx,
+                                &metadata,
+                                &file_record,
+                                &anchor,
+                                &document,
+                                original_filename.as_deref(),
+                            )?,
+                            duplicate: false,
+                        },
+                    }
+                }
+                _ => {
+                    let record_id = record_id
+                        .ok_or_else(|| Error::InvalidInput(format!("job {} has no record to update", job_id)))?;
+                    update_record(&tx, record_id, &metadata, &document)?;
+                    StageOutput::Recorded { record_id, duplicate: false }
+                }
+            };
+            complete_stage(&tx, &job_id, Stage::Record, &output)?;
+            tx.commit()?;
+            Ok(output)
+        })
+        .await
+    }
+
+    // the wait before the attempt after `attempt`: retry_delay, doubled each time, capped
+    fn retry_delay(&self, attempt: i64) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1).clamp(0, 16) as u32);
+        self.config.retry_delay.saturating_mul(factor).min(MAX_RETRY_DELAY)
+    }
+
+    // runs a closure against a pooled connection on the blocking pool
+    async fn with_db<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let repo = self.repo.clone();
+        run_blocking(move || {
+            let mut conn = repo.conn()?;
+            f(&mut conn)
+        })
+        .await
+    }
+}
+
+
+// helper functions
+// errors another attempt can't fix: the file itself is unusable, or the record it was meant for is gone
+fn is_permanent(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::Extraction(_) | Error::InvalidInput(_) | Error::Database(rusqlite::Error::QueryReturnedNoRows)
+    )
+}
+
+// the uploaded file a finished job leaves behind in the upload directory; one that's already gone
+// isn't an error
+async fn discard_upload(job: &Job) {
+    if let Err(e) = tokio::fs::remove_file(&job.file_path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        println!("Job {}: could not remove {}: {}", job.id, job.file_path, e);
+    }
+}
+
+// a stage marked done whose output can't be read back; another attempt wouldn't find it either
+fn missing_output(job: &Job, stage: Stage) -> Error {
+    Error::InvalidInput(format!("job {} has no output from its {} stage", job.id, stage.as_str()))
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::extract::extract::DocumentFormat;
+    use crate::hash::compute_sha256;
+    use crate::nlp::engine::{ExtractedMetaData, FileRecord};
+    use crate::nlp::provider::MockExtractor;
+    use crate::solana::solana::{MemoReceipt, SolanaConfig};
+    use crate::storage::store::MemoryStore;
+    use crate::vector::embedder::HashEmbedder;
+    use crate::vector::index::SemanticIndex;
+    use crate::vector::passages::PassageIndex;
+    use solana_commitment_config::CommitmentConfig;
+    use std::path::PathBuf;
+
+    const NOTES: &str = "Photosynthesis in Plants\n\nChlorophyll absorbs light. Chlorophyll drives photosynthesis.";
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("worker-test-{}-{}", uuid::Uuid::new_v4(), name))
+    }
+
+    // a queue on the mock provider and an in-memory store; the memo sender never gets used by the
+    // jobs these tests run, it only needs a keypair to exist
+    pub(crate) fn queue(repo: &ArchiveRepository, files: &mut Vec<PathBuf>) -> JobQueue {
+        let (keypair, index) = (temp_path("keypair.json"), temp_path("index.json"));
+        files.extend([keypair.clone(), index.clone()]);
+
+        let solana = SolanaConfig {
+            rpc_url: "http://127.0.0.1:1".to_string(),
+            commitment: CommitmentConfig::confirmed(),
+            keypair_path: keypair,
+            airdrop: true,
+        };
+        let embedder = Arc::new(HashEmbedder::new(64));
+        let index_sync = IndexSync::new(
+            Arc::new(SemanticIndex::open(&index, embedder.clone()).unwrap()),
+            Arc::new(PassageIndex::new(repo.clone(), embedder)),
+            repo.clone(),
+            Duration::from_secs(60),
+        );
+        let config = JobConfig {
+            workers: 1,
+            max_attempts: 1,
+            retry_delay: Duration::from_secs(1),
+            poll_interval: Duration::from_secs(1),
+        };
+        JobQueue::new(
+            repo.clone(),
+            Arc::new(MockExtractor),
+            ChunkConfig::default(),
+            Arc::new(MemoryStore::default()),
+            Arc::new(MemoSender::new(&solana).unwrap()),
+            Arc::new(index_sync),
+            config,
+        )
+    }
+
+    #[tokio::test]
+    async fn extract_and_store_run_offline() {
+        let path = temp_path("notes.txt");
+        std::fs::write(&path, NOTES).unwrap();
+        let store = MemoryStore::default();
+
+        let (metadata, document) =
+            get_meta_data_and_document(path.display().to_string(), &MockExtractor, &ChunkConfig::default()).await.unwrap();
+        assert_eq!(metadata.title, "Photosynthesis in Plants");
+        assert!(metadata.keywords.iter().any(|keyword| keyword.eq_ignore_ascii_case("chlorophyll")));
+        assert_eq!(document.format, DocumentFormat::Txt);
+
+        let stored = package_hash_and_cid(&path, None, &store).await.unwrap();
+        assert_eq!(stored.file_hash, compute_sha256(NOTES.as_bytes()));
+        assert_eq!(store.get(&stored.file_cid).await.unwrap(), NOTES.as_bytes());
+
+        // a hash taken during the upload is trusted as it is, the file isn't hashed again
+        let streamed = package_hash_and_cid(&path, Some("streamed".to_string()), &store).await.unwrap();
+        assert_eq!((streamed.file_hash.as_str(), streamed.file_cid), ("streamed", stored.file_cid));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reextract_job_updates_the_record() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut files = Vec::new();
+        let jobs = queue(&repo, &mut files);
+
+        let metadata = ExtractedMetaData {
+            title: "Old title".to_string(),
+            difficulty: "Advanced".to_string(),
+            genre: "Science".to_string(),
+            summary: "old".to_string(),
+            resource_type: "Textbook".to_string(),
+            keywords: Vec::new(),
+            topics: Vec::new(),
+            language: "en".to_string(),
+            authors: Vec::new(),
+        };
+        let file = FileRecord { file_hash: compute_sha256(NOTES.as_bytes()), file_cid: "cid".to_string() };
+        let anchor = MemoReceipt { signature: "sig".to_string(), slot: None, block_time: None };
+        let document = ExtractedDocument { format: DocumentFormat::Txt, pages: vec!["old".to_string()], paged: false };
+        let id = repo.insert(&metadata, &file, &anchor, &document, Some("notes.txt")).unwrap();
+
+        let path = temp_path("notes.txt");
+        std::fs::write(&path, NOTES).unwrap();
+        files.push(path.clone());
+        let job_id =
+            jobs.enqueue(JobKind::Reextract, path.display().to_string(), None, Some("notes.txt".to_string()), Some(id)).await.unwrap();
+
+        assert!(jobs.run_next().await.unwrap());
+        assert!(!jobs.run_next().await.unwrap());
+
+        let report = jobs.report(job_id).await.unwrap().unwrap();
+        assert_eq!((report.status.as_str(), report.record_id, report.error), ("succeeded", Some(id), None));
+        let record = repo.get(id).unwrap().unwrap();
+        assert_eq!((record.title.as_str(), record.difficulty.as_str()), ("Photosynthesis in Plants", "Beginner"));
+        // the upload isn't needed once the record is written
+        assert!(!path.exists());
+
+        for file in files {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_job_that_fails_for_good_drops_its_upload() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut files = Vec::new();
+        let jobs = queue(&repo, &mut files);
+
+        let path = temp_path("scan.bin");
+        std::fs::write(&path, [0u8, 159, 146, 150, 1, 2, 3]).unwrap();
+        files.push(path.clone());
+        let job_id = jobs.enqueue(JobKind::Upload, path.display().to_string(), None, None, None).await.unwrap();
+
+        assert!(jobs.run_next().await.unwrap());
+        let report = jobs.report(job_id).await.unwrap().unwrap();
+        assert_eq!((report.status.as_str(), report.retry_at), ("failed", None));
+        assert!(!path.exists());
+
+        for file in files {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_cap() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut files = Vec::new();
+        let jobs = queue(&repo, &mut files);
+
+        let delays: Vec<u64> = (1..=5).map(|attempt| jobs.retry_delay(attempt).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16]);
+        assert_eq!(jobs.retry_delay(0), Duration::from_secs(1));
+        assert_eq!(jobs.retry_delay(11), MAX_RETRY_DELAY);
+        assert_eq!(jobs.retry_delay(i64::MAX), MAX_RETRY_DELAY);
+
+        for file in files {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
@@ -1,91 +1,73 @@
-mod hash;
+pub mod hash;
+pub mod error;
+pub mod config;
+pub mod extract;
 pub mod nlp;
 pub mod database;
 pub mod solana;
+pub mod vector;
+pub mod search;
+pub mod jobs;
+pub mod upload;
+pub mod storage;
 
-use std::fs;
 
+// the crate wide error type, also knows how to render itself as an http response
+pub use error::{Error, Result};
 
 // structs
 pub use nlp::engine::{FileRecord, ExtractedMetaData};
 
 // we use these two to get the AI response and get the cid and hash for the document
-pub use nlp::engine::get_meta_data_response;
+pub use nlp::engine::{get_meta_data_response, get_meta_data_and_document};
 pub use nlp::engine::package_hash_and_cid;
+pub use nlp::engine::extract_metadata_chunked;
 
-// the database functionality
-pub use database::database::add_to_or_create_database;
-
-// solana blockchain functionality
-pub use solana::solana::send_memo;
-
-
-
-
-
-
-
-
-
-
-
+// pluggable content addressed storage for the archived files (kubo, a directory, or memory), and
+// serving them back out
+pub use storage::store::{ContentStore, ContentStream, ContentStoreConfig, ContentStoreKind, ContentStat, build_content_store};
+pub use storage::store::{KuboStore, LocalStore, MemoryStore};
+pub use storage::cid::cid_v1;
+pub use storage::download::{content_format, content_type, download_filename, etag, etag_matches, fetch_verified};
+pub use storage::download::{parse_range, stored_sha256, ByteRange, DownloadCache, RangeRequest, SpooledFile};
 
+// pluggable llm backends for the metadata extraction
+pub use nlp::provider::{MetadataExtractor, LlmConfig, LlmProvider, build_extractor, MockExtractor, NO_ANSWER};
+pub use nlp::provider::{Answerer, build_answerer};
+pub use nlp::chunker::{ChunkConfig, chunk_text, estimate_tokens, split_passages};
 
+// text extraction for every supported document format
+pub use extract::extract::{extract_text_from_document, extract_document, sniff_file_format, sniff_format, DocumentFormat, ExtractedDocument, ExtractError};
 
+// the database functionality
+pub use database::repository::{ArchiveRepository, RecordSearch, RecordQuery, RecordSort, RecordPage};
+pub use database::database::{get_record_terms, load_record, ArchiveRecord, RecordTerms};
+pub use database::database::{MetadataPatch, RecordEdit, EDITABLE_FIELDS};
+pub use database::database::{load_document_text, load_passages, Passage, StoredText};
+pub use database::database::ArchivedFile;
+pub use database::migrations::{migrate, schema_version, Migration, MIGRATIONS};
 
+// solana blockchain functionality
+pub use solana::solana::{MemoSender, SolanaConfig, MemoReceipt, OnChainMemo, parse_memo, format_revocation};
 
+// semantic search over the archive, served in-process
+pub use vector::embedder::{Embedder, EmbeddingConfig, EmbeddingProvider, HashEmbedder, HttpEmbedder, build_embedder};
+pub use vector::index::{SemanticIndex, IndexEntry, IndexedDocument, VectorSearchResult};
+pub use vector::passages::PassageIndex;
+pub use vector::sync::{IndexSync, IndexSummary, record_document};
 
+// text + vector search fused into one ranking
+pub use search::hybrid::{hybrid_search, HybridQuery, SearchHit, SearchPage};
+pub use search::passages::{passage_search, PassageQuery, PassageHit};
 
-// TODO: all the following functions need to be integrated properly
-// process the document
-// pub fn extract_text_from_document(path: PathBuf) -> Option<String>
-// {
-//     // get the file extension
-//     let extension = get_extension(&path);
-//
-//
-//     match extension {
-//         Some(ext) => {
-//             if ext == "pdf"
-//             {
-//                 extract_text_from_pdf(&path)
-//             }
-//             else { None }
-//         }
-//         None => None
-//     }
-//
-// }
-//
-// fn extract_text_from_pdf(path: &PathBuf) -> Option<String>
-// {
-//     let bytes = std::fs::read(&path).unwrap();
-//     match extract_text_from_mem(&bytes)
-//     {
-//         Ok(text) => Some(text),
-//         Err(err) =>{
-//             println!("Failed to extract text from {:?}: {}", path, err);
-//             None
-//         }
-//     }
-// }
-//
-// fn get_extension(path: &PathBuf) -> Option<String> {
-//     path.extension()
-//         .and_then(|ext| ext.to_str())  // convert OsStr → &str
-//         .map(|s| s.to_string())        // &str → String
-// }
+// question answering grounded in the archive's own passages
+pub use search::ask::{ask, AskQuery, Answer, Citation};
 
+// the background queue uploads are processed through
+pub use jobs::queue::{JobKind, JobReport, Stage, StageReport};
+pub use jobs::worker::{JobConfig, JobQueue};
 
-// #[cfg(test)]
-//
-// mod tests {
-//     use super::*;
-//
-//     #[test]
-//     fn first_read()
-//     {
-//
-//         let result = extract_text_from_document()
-//     }
-// }
+// turning the files of an upload request into jobs
+pub use upload::batch::{admit_batch, discard_received, FileError, FileResult, Received, StoredFile, UploadConfig, UPLOAD_DIR};
+pub use upload::receive::receive_file;
+pub use upload::session::{UploadSession, UploadSessions};
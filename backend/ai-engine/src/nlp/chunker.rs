@@ -0,0 +1,198 @@
+// chunker.rs: splits long documents into pieces that fit in the model's context window
+use dotenv::dotenv;
+
+use crate::config::env_u64;
+use crate::error::{Error, Result};
+
+
+// how the text gets cut up before the map step, read from the environment (.env works too)
+//
+//   CHUNK_TOKENS          max tokens per chunk, also the "fits in one call" threshold (default 6000)
+//   CHUNK_OVERLAP_TOKENS  tokens repeated between neighbouring chunks (default 200)
+//   CHUNK_CONCURRENCY     chunk summaries requested at once (default 4)
+#[derive(Debug, Clone)]
+pub struct ChunkConfig {
+    pub chunk_tokens: usize,
+    pub overlap_tokens: usize,
+    pub concurrency: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        ChunkConfig { chunk_tokens: 6_000, overlap_tokens: 200, concurrency: 4 }
+    }
+}
+
+impl ChunkConfig {
+    pub fn from_env() -> Result<Self> {
+        // load the dotenv variables
+        dotenv().ok();
+
+        let defaults = ChunkConfig::default();
+        let config = ChunkConfig {
+            chunk_tokens: env_u64("CHUNK_TOKENS", defaults.chunk_tokens as u64, 1)? as usize,
+            overlap_tokens: env_u64("CHUNK_OVERLAP_TOKENS", defaults.overlap_tokens as u64, 0)? as usize,
+            concurrency: env_u64("CHUNK_CONCURRENCY", defaults.concurrency as u64, 1)? as usize,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    // otherwise every chunk would start where the last one started and we'd never move forward
+    pub fn validate(&self) -> Result<()> {
+        if self.overlap_tokens >= self.chunk_tokens {
+            return Err(Error::Config(format!(
+                "CHUNK_OVERLAP_TOKENS ({}) must be smaller than CHUNK_TOKENS ({})",
+                self.overlap_tokens, self.chunk_tokens
+            )));
+        }
+        Ok(())
+    }
+}
+
+// rough token count without a tokenizer: ~4 characters per token, at least one per word
+pub fn estimate_tokens(text: &str) -> usize {
+    text.split_whitespace().map(word_tokens).sum()
+}
+
+fn word_tokens(word: &str) -> usize {
+    word.chars().count().div_ceil(4).max(1)
+}
+
+// splits on word boundaries so no chunk goes over chunk_tokens, repeating roughly
+// overlap_tokens worth of words at the start of each chunk after the first
+pub fn chunk_text(text: &str, config: &ChunkConfig) -> Vec<String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < words.len() {
+        // grow the chunk until the next word would blow the budget (always take at least one word)
+        let mut end = start;
+        let mut tokens = 0;
+        while end < words.len() {
+            let next = word_tokens(words[end]);
+            if end > start && tokens + next > config.chunk_tokens {
+                break;
+            }
+            tokens += next;
+            end += 1;
+        }
+
+        chunks.push(words[start..end].join(" "));
+        if end == words.len() {
+            break;
+        }
+
+        // step back from the end by the overlap, but always make progress
+        let mut overlap_start = end;
+        let mut overlap = 0;
+        while overlap_start > start + 1 {
+            let prev = word_tokens(words[overlap_start - 1]);
+            if overlap + prev > config.overlap_tokens {
+                break;
+            }
+            overlap += prev;
+            overlap_start -= 1;
+        }
+        start = overlap_start;
+    }
+
+    chunks
+}
+
+// search passages: small enough that a hit points at the right spot, big enough to read on their own
+pub const PASSAGE_TOKENS: usize = 256;
+pub const PASSAGE_OVERLAP_TOKENS: usize = 32;
+
+// cuts a document into passages page by page, so a passage never spans two pages.
+// returns (1 based page number, or None for unpaged formats, passage text)
+pub fn split_passages(pages: &[String], paged: bool) -> Vec<(Option<i64>, String)> {
+    let config = ChunkConfig { chunk_tokens: PASSAGE_TOKENS, overlap_tokens: PASSAGE_OVERLAP_TOKENS, concurrency: 1 };
+    let mut passages = Vec::new();
+    for (i, page) in pages.iter().enumerate() {
+        let page_number = if paged { Some(i as i64 + 1) } else { None };
+        for passage in chunk_text(page, &config) {
+            passages.push((page_number, passage));
+        }
+    }
+    passages
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(chunk_tokens: usize, overlap_tokens: usize) -> ChunkConfig {
+        ChunkConfig { chunk_tokens, overlap_tokens, concurrency: 1 }
+    }
+
+    // "w0 w1 w2 ...", every word a single token
+    fn words(n: usize) -> String {
+        (0..n).map(|i| format!("w{}", i)).collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn empty_text_has_no_chunks() {
+        assert!(chunk_text("", &config(10, 2)).is_empty());
+        assert!(chunk_text("  \n\t ", &config(10, 2)).is_empty());
+        assert!(split_passages(&[], true).is_empty());
+    }
+
+    #[test]
+    fn short_text_is_one_chunk() {
+        assert_eq!(chunk_text("one two  three", &config(10, 2)), vec!["one two three"]);
+    }
+
+    #[test]
+    fn chunks_stay_within_the_budget_and_overlap() {
+        let text = words(25);
+        let chunks = chunk_text(&text, &config(10, 3));
+
+        assert!(chunks.iter().all(|chunk| estimate_tokens(chunk) <= 10));
+        assert_eq!(chunks[0], words(10));
+        // each chunk after the first starts with the last three words of the one before
+        for pair in chunks.windows(2) {
+            let tail: Vec<&str> = pair[0].split(' ').rev().take(3).collect();
+            let head: Vec<&str> = pair[1].split(' ').take(3).collect();
+            assert_eq!(tail.into_iter().rev().collect::<Vec<_>>(), head);
+        }
+        // nothing is lost: the last word is in the last chunk
+        assert!(chunks.last().unwrap().ends_with("w24"));
+    }
+
+    #[test]
+    fn a_word_bigger_than_a_chunk_still_moves_forward() {
+        let long = "x".repeat(100); // 25 tokens on its own
+        let chunks = chunk_text(&format!("a {} b", long), &config(4, 1));
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1], long);
+    }
+
+    #[test]
+    fn estimates_tokens_per_word() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("a bb ccc dddd"), 4);
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+    }
+
+    #[test]
+    fn rejects_an_overlap_as_big_as_the_chunk() {
+        assert!(config(10, 9).validate().is_ok());
+        assert!(matches!(config(10, 10).validate(), Err(Error::Config(_))));
+        assert!(matches!(config(0, 0).validate(), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn passages_keep_their_page() {
+        let pages = vec![words(300), "last page".to_string()];
+        let passages = split_passages(&pages, true);
+        assert!(passages.len() > 2);
+        assert_eq!(passages.last().unwrap(), &(Some(2), "last page".to_string()));
+        assert!(passages.iter().all(|(page, _)| page.is_some()));
+
+        let unpaged = split_passages(&["one page".to_string()], false);
+        assert_eq!(unpaged, vec![(None, "one page".to_string())]);
+    }
+}
@@ -1,118 +1,113 @@
 use serde_json::{json, Value};
 use serde::Serialize;
 use serde::Deserialize;
-use tokio;
-use reqwest::Client;
-use pdf_extract::extract_text_from_mem;
-use regex::Regex;
-use anyhow::{anyhow, Context};
-use reqwest::multipart::{Form, Part};
-use sha2::{Digest, Sha256};
-use std::env;
 use std::path::Path;
 use tokio::fs;
-use dotenv::dotenv;
 
+use crate::error::{Error, Result};
+use crate::extract::extract::{extract_document, ExtractError, ExtractedDocument};
+use crate::hash::compute_sha256_hex;
+use crate::nlp::chunker::{chunk_text, estimate_tokens, ChunkConfig};
+use crate::nlp::provider::MetadataExtractor;
+use crate::storage::store::ContentStore;
+use futures::stream::{self, StreamExt, TryStreamExt};
 
-// most important functions
-pub async fn get_meta_data_response(file_path: String) -> anyhow::Result<ExtractedMetaData>{
-    let bytes = fs::read(file_path).await.unwrap();
-    let extracted_txt = extract_text_from_mem(&bytes).unwrap();
-
-
-    // load the dotenv variables
-    dotenv().ok();
-
-    // groq api setup
-    let groq_base = env::var("GROQ_BASE").unwrap_or_else(|_| "https://api.groq.com/openai/v1/chat/completions".to_string());
-    let groq_key = env::var("GROQ_API_KEY").context("GROQ_API_KEY not set in environment variables; \n add it to your .env file".to_string())?;
-
-    let client = Client::new();
-
-    // json format request to the ai with the ai model
-    let body = json!({
-        "model": "openai/gpt-oss-120b", // shows th model used
-        "messages": [
-             {
-      "role": "system",
-      "content": "You are an assistant that extracts structured metadata from documents.",
-    },
-            {
-                "role": "user",
-                "content":format!(
-                    "From the following text, extract:\n\
-                     - Genre\n\
-                     - Summary (with optimal keywords)\n\
-                     - Difficulty level (Beginner/Intermediate/Advanced)\n\
-                     - Title\n\n\
-                     - Keywords\n\n\
-                     Text:\n{}",
-                    extracted_txt
-                )
-            }
-        ],
-        "include_reasoning": false, // abstracts the thinking process
-        "temperature": 0.6 // lower values make the answers concise, recommended 0.5 - 0.7
-    });
-
-    // sends the request using reqwest
-    let resp = client
-        .post(groq_base)
-        .bearer_auth(groq_key)
-        .json(&body)
-        .send()
-        .await?;
-
-
-    let json = resp.text().await?;
-
-    // Extract the message
-    let re = Regex::new(
-        r#"(?s)\*\*Genre:\*\*\s*(.*?)\s+.*?\*\*Title:\*\*\s*(.*?)\s+.*?\*\*Difficulty\s*Level:\*\*\s*(.*?)\s+.*?\*\*Summary.*?:\*\*\s*(.*?)""#
-    ).unwrap();
-
-    // prep the ExtractedMetaData struct, if returned then we didn't fetch anything
-    let mut metadata = ExtractedMetaData{
-        genre: "".to_string(),
-        title: "".to_string(),
-        difficulty: "".to_string(),
-        summary: "".to_string(),
-    };
 
-    if let Some(caps) = re.captures(&json) {
-        let genre = caps.get(1).unwrap().as_str().trim();
-        let title = caps.get(2).unwrap().as_str().trim();
-        let difficulty = caps.get(3).unwrap().as_str().trim();
-        let summary = caps.get(4).unwrap().as_str().trim();
+// how many times we'll summarise the summaries. every round has to shrink the text anyway, this
+// only caps the model calls one pathological document can cost
+const MAX_REDUCE_ROUNDS: usize = 8;
 
-        println!("Genre: {}", genre);
-        println!("Title: {}", title);
-        println!("Difficulty: {}", difficulty);
-        println!("Summary: {}", summary);
+// most important functions
+pub async fn get_meta_data_response(file_path: String, extractor: &dyn MetadataExtractor, chunking: &ChunkConfig) -> Result<ExtractedMetaData>{
+    let (metadata, _) = get_meta_data_and_document(file_path, extractor, chunking).await?;
+    Ok(metadata)
+}
 
-        metadata = ExtractedMetaData{title: title.to_string(),
-                                    difficulty: difficulty.to_string(),
-                                    summary: summary.to_string(),
-                                    genre: genre.to_string(),
-                                    };
+// same as get_meta_data_response, but also hands back the extracted text so it can be stored
+pub async fn get_meta_data_and_document(file_path: String, extractor: &dyn MetadataExtractor, chunking: &ChunkConfig) -> Result<(ExtractedMetaData, ExtractedDocument)>{
+    let bytes = fs::read(&file_path).await?;
+    let document = extract_document(&bytes, Some(&file_path))?;
+    let extracted_txt = document.text();
+    println!("Extracted {} characters of {:?} text", extracted_txt.len(), document.format);
+
+    // hand the text to whichever provider the server was configured with
+    println!("Extracting metadata with {}", extractor.name());
+    let metadata = extract_metadata_chunked(&extracted_txt, extractor, chunking).await?;
+    Ok((metadata, document))
+}
 
+// map-reduce for documents bigger than the context window: summarise every chunk, then extract the
+// metadata from the joined chunk summaries, summarising those again until they fit one call. the
+// whole document always makes it in; if it can't be brought down to size that's an error, never a
+// silently truncated text
+pub async fn extract_metadata_chunked(text: &str, extractor: &dyn MetadataExtractor, chunking: &ChunkConfig) -> Result<ExtractedMetaData> {
+    let mut text = text.to_string();
+    let mut round = 0;
+
+    while estimate_tokens(&text) > chunking.chunk_tokens {
+        round += 1;
+        if round > MAX_REDUCE_ROUNDS {
+            return Err(Error::Extraction(ExtractError::TooLong(format!(
+                "still ~{} tokens after {} rounds of summaries, one request takes {}",
+                estimate_tokens(&text),
+                MAX_REDUCE_ROUNDS,
+                chunking.chunk_tokens
+            ))));
+        }
+
+        let chunks = chunk_text(&text, chunking);
+        println!("Round {}: ~{} tokens, summarising {} chunks", round, estimate_tokens(&text), chunks.len());
+
+        // buffered keeps the parts in document order while a few requests run at once
+        let summaries: Vec<String> = stream::iter(chunks.iter().enumerate())
+            .map(|(i, chunk)| async move {
+                extractor
+                    .summarise(chunk)
+                    .await
+                    .map(|summary| format!("Part {}:\n{}", i + 1, summary))
+            })
+            .buffered(chunking.concurrency)
+            .try_collect()
+            .await?;
+
+        // a round that doesn't shrink the text would never converge
+        let reduced = summaries.join("\n\n");
+        if estimate_tokens(&reduced) >= estimate_tokens(&text) {
+            return Err(Error::Extraction(ExtractError::TooLong(format!(
+                "summarising ~{} tokens in {} chunks gave back ~{}, nothing shorter",
+                estimate_tokens(&text),
+                chunks.len(),
+                estimate_tokens(&reduced)
+            ))));
+        }
+        text = reduced;
     }
 
-    Ok(metadata)
-    // let record = package_hash_and_cid(pdf_path).await?;
-    // println!("FileRecord: {:?}", record);
-
-
+    // small enough, one call does it
+    extractor.extract(&text).await
 }
 
+// file_hash is the one taken while the file was uploaded, if there was one; it's only worked out
+// here for files that came some other way
 pub async fn package_hash_and_cid<P: AsRef<Path>>(
     path: P,
-) -> anyhow::Result<FileRecord> {
-    // first: compute the hash
-    let file_hash = compute_sha256_hex(&path).await?;
+    file_hash: Option<String>,
+    store: &dyn ContentStore,
+) -> Result<FileRecord> {
+    // first: the hash, streamed from disk like the put below, so the file is never in memory whole
+    let file_hash = match file_hash {
+        Some(file_hash) => file_hash,
+        None => compute_sha256_hex(&path).await?,
+    };
 
-    // second: upload and obtain CID
-    let file_cid = upload_file_to_ipfs_kubo(&path).await?;
+    // second: hand it to whichever content store is configured and obtain the CID
+    let filename = path
+        .as_ref()
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("file")
+        .to_string();
+    let file_cid = store.put_file(path.as_ref(), &filename).await?;
 
     // package and return
     Ok(FileRecord { file_hash, file_cid })
@@ -128,11 +123,77 @@ pub struct ExtractedMetaData {
     pub genre: String,
     pub summary: String,
 
-    // resource_type: String,  // Lecture note, text book, research paper slides etc
-    // keywords: Vec<String>,  // is it really possible to not have any keywords LMAO
-    // topics: Vec<String>,
+    #[serde(default)]
+    pub resource_type: String,  // Lecture note, text book, research paper slides etc
+    #[serde(default)]
+    pub keywords: Vec<String>,  // is it really possible to not have any keywords LMAO
+    #[serde(default)]
+    pub topics: Vec<String>,
+    #[serde(default)]
+    pub language: String,       // ISO 639-1 code, e.g. "en"
+    #[serde(default)]
+    pub authors: Vec<String>,   // empty when the document doesn't say
+}
+
+impl ExtractedMetaData {
+    // the contract we hand to the model through response_format; keep it in sync with the struct
+    pub fn json_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "title": { "type": "string" },
+                "difficulty": { "type": "string", "enum": DIFFICULTY_LEVELS },
+                "genre": { "type": "string" },
+                "summary": { "type": "string" },
+                "resource_type": { "type": "string", "enum": RESOURCE_TYPES },
+                "keywords": { "type": "array", "items": { "type": "string" } },
+                "topics": { "type": "array", "items": { "type": "string" } },
+                "language": { "type": "string" },
+                "authors": { "type": "array", "items": { "type": "string" } },
+            },
+            "required": ["title", "difficulty", "genre", "summary", "resource_type", "keywords", "topics", "language", "authors"],
+            "additionalProperties": false,
+        })
+    }
+
+    // trims everything, normalises casing and rejects anything we wouldn't want to store
+    pub fn validate(mut self) -> Result<Self> {
+        self.title = self.title.trim().to_string();
+        self.genre = self.genre.trim().to_string();
+        self.summary = self.summary.trim().to_string();
+
+        for (name, value) in [("title", &self.title), ("genre", &self.genre), ("summary", &self.summary)] {
+            if value.is_empty() {
+                return Err(Error::LlmValidation(format!("'{}' must not be empty", name)));
+            }
+        }
+
+        self.difficulty = one_of("difficulty", &self.difficulty, &DIFFICULTY_LEVELS)?;
+        self.resource_type = one_of("resource_type", &self.resource_type, &RESOURCE_TYPES)?;
+
+        // language codes are stored lowercase ("en", "fr", ...)
+        self.language = self.language.trim().to_ascii_lowercase();
+        if self.language.is_empty() || self.language.len() > 8 || !self.language.chars().all(|c| c.is_ascii_alphabetic() || c == '-') {
+            return Err(Error::LlmValidation(format!(
+                "'language' must be an ISO 639-1 code like \"en\", got '{}'",
+                self.language
+            )));
+        }
+
+        self.keywords = clean_terms(self.keywords);
+        if self.keywords.is_empty() {
+            return Err(Error::LlmValidation("'keywords' must contain at least one keyword".to_string()));
+        }
+        self.topics = clean_terms(self.topics);
+        self.authors = clean_terms(self.authors);
+
+        Ok(self)
+    }
 }
 
+pub const DIFFICULTY_LEVELS: [&str; 3] = ["Beginner", "Intermediate", "Advanced"];
+pub const RESOURCE_TYPES: [&str; 6] = ["Lecture Note", "Textbook", "Research Paper", "Slides", "Article", "Other"];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileRecord {
     pub file_hash: String, // SHA-256 hex of file bytes
@@ -141,49 +202,195 @@ pub struct FileRecord {
 
 
 // helper functions
-pub async fn compute_sha256_hex<P: AsRef<Path>>(path: P) -> anyhow::Result<String> {
-    // read file bytes asynchronously
-    let bytes = fs::read(&path)
-        .await
-        .with_context(|| format!("reading file {:?}", path.as_ref()))?;
-
-    // compute SHA-256
-    let mut hasher = Sha256::new();
-    hasher.update(&bytes);
-    let digest = hasher.finalize();
-
-    // return hex string
-    Ok(hex::encode(digest))
+// matches a value against a fixed list ignoring case and returns the canonical spelling
+fn one_of(field: &str, value: &str, allowed: &[&str]) -> Result<String> {
+    let value = value.trim();
+    allowed
+        .iter()
+        .find(|candidate| candidate.eq_ignore_ascii_case(value))
+        .map(|candidate| candidate.to_string())
+        .ok_or_else(|| Error::LlmValidation(format!(
+            "'{}' must be one of {:?}, got '{}'",
+            field, allowed, value
+        )))
 }
 
-async fn upload_file_to_ipfs_kubo<P: AsRef<Path>>(path: P) -> anyhow::Result<String> {
-    // read file bytes
-    let bytes = fs::read(&path).await?;
-    let filename = path
-        .as_ref()
-        .file_name()
-        .and_then(|s| s.to_str())
-        .unwrap_or("file")
-        .to_string();
+// trims, drops empties and removes case-insensitive duplicates while keeping the original order
+fn clean_terms(terms: Vec<String>) -> Vec<String> {
+    let mut out: Vec<String> = Vec::with_capacity(terms.len());
+    for term in terms {
+        let term = term.trim();
+        if !term.is_empty() && !out.iter().any(|seen| seen.eq_ignore_ascii_case(term)) {
+            out.push(term.to_string());
+        }
+    }
+    out
+}
 
-    // build multipart form
-    let part = Part::bytes(bytes).file_name(filename);
-    let form = Form::new().part("file", part);
-
-    // send request to local ipfs daemon
-    let resp_text = Client::new()
-        .post("http://127.0.0.1:5001/api/v0/add")
-        .multipart(form)
-        .send()
-        .await?
-        .text()
-        .await?;
-
-    // parse JSON and return "Hash"
-    let v: Value = serde_json::from_str(&resp_text)?;
-    let cid = v
-        .get("Hash")
-        .and_then(|h| h.as_str())
-        .ok_or_else(|| anyhow!("ipfs response missing 'Hash' field"))?;
-    Ok(cid.to_string())
+// models sometimes wrap the object in a ``` or ```json fence even when held to a schema
+fn strip_code_fence(content: &str) -> &str {
+    let content = content.trim();
+    let Some(fenced) = content.strip_prefix("```").and_then(|rest| rest.strip_suffix("```")) else {
+        return content;
+    };
+    // whatever follows the opening fence on its line is the language tag
+    match fenced.split_once('\n') {
+        Some((tag, body)) if !tag.contains('{') => body.trim(),
+        _ => fenced.trim(),
+    }
+}
+
+// deserializes the model's answer straight into the struct and validates it
+pub fn parse_metadata(content: &str) -> Result<ExtractedMetaData> {
+    let metadata: ExtractedMetaData = serde_json::from_str(strip_code_fence(content))
+        .map_err(|e| Error::LlmParse(format!("not valid metadata json: {}", e)))?;
+    metadata.validate()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nlp::provider::MockExtractor;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    // summarises a chunk down to its first two words and remembers everything it was sent
+    #[derive(Default)]
+    struct Recorder {
+        summarised: Mutex<Vec<String>>,
+        extracted: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl MetadataExtractor for Recorder {
+        async fn extract(&self, text: &str) -> Result<ExtractedMetaData> {
+            self.extracted.lock().unwrap().push(text.to_string());
+            MockExtractor.extract(text).await
+        }
+
+        async fn summarise(&self, text: &str) -> Result<String> {
+            self.summarised.lock().unwrap().push(text.to_string());
+            Ok(text.split_whitespace().take(2).collect::<Vec<_>>().join(" "))
+        }
+
+        fn name(&self) -> String {
+            "recorder".to_string()
+        }
+    }
+
+    // hands every chunk back as its own summary, so nothing ever gets shorter
+    struct Stubborn;
+
+    #[async_trait]
+    impl MetadataExtractor for Stubborn {
+        async fn extract(&self, text: &str) -> Result<ExtractedMetaData> {
+            MockExtractor.extract(text).await
+        }
+
+        async fn summarise(&self, text: &str) -> Result<String> {
+            Ok(text.to_string())
+        }
+
+        fn name(&self) -> String {
+            "stubborn".to_string()
+        }
+    }
+
+    fn chunking(chunk_tokens: usize) -> ChunkConfig {
+        ChunkConfig { chunk_tokens, overlap_tokens: 0, concurrency: 2 }
+    }
+
+    fn words(n: usize) -> String {
+        (0..n).map(|i| format!("w{}", i)).collect::<Vec<_>>().join(" ")
+    }
+
+    #[tokio::test]
+    async fn short_text_goes_in_one_call() {
+        let extractor = Recorder::default();
+        extract_metadata_chunked("a short text", &extractor, &chunking(100)).await.unwrap();
+        assert_eq!(*extractor.extracted.lock().unwrap(), vec!["a short text"]);
+    }
+
+    #[tokio::test]
+    async fn long_text_is_reduced_until_it_fits() {
+        // 1000 words in chunks of 40 give 25 summaries, still more than fit; another round brings them down
+        let extractor = Recorder::default();
+        extract_metadata_chunked(&words(1000), &extractor, &chunking(40)).await.unwrap();
+
+        let extracted = extractor.extracted.lock().unwrap();
+        assert_eq!(extracted.len(), 1);
+        assert!(estimate_tokens(&extracted[0]) <= 40);
+        assert!(extracted[0].starts_with("Part 1:"));
+
+        // every word of the document reached the model, the end of it included
+        let summarised = extractor.summarised.lock().unwrap();
+        assert!(summarised.iter().any(|chunk| chunk.split_whitespace().any(|word| word == "w999")));
+        assert!(summarised.len() > 25);
+    }
+
+    #[tokio::test]
+    async fn text_that_never_shrinks_is_an_error() {
+        let result = extract_metadata_chunked(&words(100), &Stubborn, &chunking(20)).await;
+        assert!(matches!(result, Err(Error::Extraction(ExtractError::TooLong(_)))));
+    }
+
+    const VALID: &str = r#"{
+        "title": "  Cell Biology ",
+        "difficulty": "intermediate",
+        "genre": "Science",
+        "summary": "How cells work.",
+        "resource_type": "lecture note",
+        "keywords": ["cells", " Cells ", "", "mitosis"],
+        "topics": ["Biology"],
+        "language": "EN",
+        "authors": []
+    }"#;
+
+    #[test]
+    fn parses_and_normalises_valid_json() {
+        let metadata = parse_metadata(VALID).unwrap();
+        assert_eq!(metadata.title, "Cell Biology");
+        assert_eq!(metadata.difficulty, "Intermediate");
+        assert_eq!(metadata.resource_type, "Lecture Note");
+        assert_eq!(metadata.language, "en");
+        assert_eq!(metadata.keywords, vec!["cells", "mitosis"]);
+    }
+
+    #[test]
+    fn parses_json_in_a_code_fence() {
+        for fenced in [format!("```json\n{}\n```", VALID), format!("```\n{}\n```", VALID), format!("```{}```", VALID)] {
+            assert_eq!(parse_metadata(&fenced).unwrap().title, "Cell Biology");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_difficulty_and_resource_type() {
+        let hard = VALID.replace("intermediate", "Expert");
+        assert!(matches!(parse_metadata(&hard), Err(Error::LlmValidation(message)) if message.contains("difficulty")));
+        let podcast = VALID.replace("lecture note", "Podcast");
+        assert!(matches!(parse_metadata(&podcast), Err(Error::LlmValidation(message)) if message.contains("resource_type")));
+    }
+
+    #[test]
+    fn rejects_missing_and_empty_fields() {
+        // title, difficulty, genre and summary have no default
+        let untitled = VALID.replace(r#""title": "  Cell Biology ","#, "");
+        assert!(matches!(parse_metadata(&untitled), Err(Error::LlmParse(_))));
+
+        let blank = VALID.replace("  Cell Biology ", "   ");
+        assert!(matches!(parse_metadata(&blank), Err(Error::LlmValidation(message)) if message.contains("title")));
+
+        // the defaulted ones still have to hold something usable
+        let no_keywords = VALID.replace(r#"["cells", " Cells ", "", "mitosis"]"#, "[]");
+        assert!(matches!(parse_metadata(&no_keywords), Err(Error::LlmValidation(message)) if message.contains("keywords")));
+        let no_language = VALID.replace(r#""language": "EN","#, "");
+        assert!(matches!(parse_metadata(&no_language), Err(Error::LlmValidation(message)) if message.contains("language")));
+    }
+
+    #[test]
+    fn rejects_what_is_not_json() {
+        assert!(matches!(parse_metadata("Sure! Here is the metadata you asked for."), Err(Error::LlmParse(_))));
+        assert!(matches!(parse_metadata(""), Err(Error::LlmParse(_))));
+    }
 }
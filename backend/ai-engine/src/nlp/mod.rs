@@ -1 +1,3 @@
-pub mod engine;
\ No newline at end of file
+pub mod chunker;
+pub mod engine;
+pub mod provider;
@@ -0,0 +1,581 @@
+// provider.rs: the different llm backends we can ask for metadata, all hidden behind one trait
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use std::future::Future;
+use std::sync::Arc;
+use dotenv::dotenv;
+
+use crate::error::{Error, Result};
+use crate::nlp::engine::{parse_metadata, ExtractedMetaData};
+
+
+// how many times we ask the model again after it hands back something we can't use
+const MAX_EXTRACTION_ATTEMPTS: usize = 3;
+
+// what Answerer::answer replies with when the passages don't hold the answer
+pub const NO_ANSWER: &str = "NO_ANSWER";
+
+// anything that can turn extracted document text into metadata
+#[async_trait]
+pub trait MetadataExtractor: Send + Sync {
+    async fn extract(&self, text: &str) -> Result<ExtractedMetaData>;
+
+    // free-form summary of one chunk, used as the map step for documents too big for one call
+    async fn summarise(&self, text: &str) -> Result<String>;
+
+    // short label for logs, e.g. "groq/openai/gpt-oss-120b"
+    fn name(&self) -> String;
+}
+
+// anything that can answer a question from passages of the archive (/ask); the same backends as the
+// extraction, configured the same way
+#[async_trait]
+pub trait Answerer: Send + Sync {
+    // answers the question from the numbered passages only (passages[0] is [1]), citing them as [n];
+    // NO_ANSWER when they don't contain the answer
+    async fn answer(&self, question: &str, passages: &[String]) -> Result<String>;
+
+    // the model behind the answers, reported with them
+    fn name(&self) -> String;
+}
+
+// which backend the server should talk to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlmProvider {
+    Groq,
+    OpenAi,
+    Ollama,
+    LlamaCpp,
+    Mock,
+}
+
+impl LlmProvider {
+    fn from_name(name: &str) -> Result<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "groq" => Ok(LlmProvider::Groq),
+            "openai" => Ok(LlmProvider::OpenAi),
+            "ollama" => Ok(LlmProvider::Ollama),
+            "llamacpp" | "llama.cpp" | "llama-cpp" => Ok(LlmProvider::LlamaCpp),
+            "mock" => Ok(LlmProvider::Mock),
+            other => Err(Error::Config(format!("unknown LLM_PROVIDER '{}'", other))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            LlmProvider::Groq => "groq",
+            LlmProvider::OpenAi => "openai",
+            LlmProvider::Ollama => "ollama",
+            LlmProvider::LlamaCpp => "llamacpp",
+            LlmProvider::Mock => "mock",
+        }
+    }
+
+    fn default_base_url(&self) -> &'static str {
+        match self {
+            LlmProvider::Groq => "https://api.groq.com/openai/v1/chat/completions",
+            LlmProvider::OpenAi => "https://api.openai.com/v1/chat/completions",
+            LlmProvider::Ollama => "http://localhost:11434/v1/chat/completions",
+            // llama-server's own default is 8080, which the vite dev server already has
+            LlmProvider::LlamaCpp => "http://localhost:8081/v1/chat/completions",
+            LlmProvider::Mock => "",
+        }
+    }
+
+    fn default_model(&self) -> &'static str {
+        match self {
+            LlmProvider::Groq => "openai/gpt-oss-120b",
+            LlmProvider::OpenAi => "gpt-4o-mini",
+            LlmProvider::Ollama => "llama3.1",
+            // llama.cpp serves whatever model it was started with and ignores this
+            LlmProvider::LlamaCpp => "default",
+            LlmProvider::Mock => "mock",
+        }
+    }
+
+    // hosted apis need a key, local servers usually don't
+    fn needs_api_key(&self) -> bool {
+        matches!(self, LlmProvider::Groq | LlmProvider::OpenAi)
+    }
+}
+
+// everything needed to build an extractor, read from the environment (.env works too)
+//
+//   LLM_PROVIDER     groq | openai | ollama | llamacpp | mock   (default: groq)
+//   LLM_MODEL        model name, defaults per provider
+//   LLM_BASE_URL     full chat completions url, defaults per provider (GROQ_BASE still works)
+//   LLM_API_KEY      falls back to GROQ_API_KEY / OPENAI_API_KEY
+//   LLM_TEMPERATURE  default 0.6
+#[derive(Debug, Clone)]
+pub struct LlmConfig {
+    pub provider: LlmProvider,
+    pub base_url: String,
+    pub model: String,
+    pub api_key: Option<String>,
+    pub temperature: f32,
+}
+
+impl LlmConfig {
+    pub fn from_env() -> Result<Self> {
+        // load the dotenv variables
+        dotenv().ok();
+
+        let provider = match env::var("LLM_PROVIDER") {
+            Ok(name) => LlmProvider::from_name(&name)?,
+            Err(_) => LlmProvider::Groq,
+        };
+
+        let provider_base = match provider {
+            LlmProvider::Groq => env::var("GROQ_BASE").ok(),
+            _ => None,
+        };
+        let base_url = env::var("LLM_BASE_URL")
+            .ok()
+            .or(provider_base)
+            .unwrap_or_else(|| provider.default_base_url().to_string());
+
+        let model = env::var("LLM_MODEL").unwrap_or_else(|_| provider.default_model().to_string());
+
+        let provider_key = match provider {
+            LlmProvider::Groq => env::var("GROQ_API_KEY").ok(),
+            LlmProvider::OpenAi => env::var("OPENAI_API_KEY").ok(),
+            _ => None,
+        };
+        let api_key = env::var("LLM_API_KEY").ok().or(provider_key);
+        if provider.needs_api_key() && api_key.is_none() {
+            return Err(Error::Config(format!(
+                "no api key for provider '{}'; \n set LLM_API_KEY in your .env file",
+                provider.as_str()
+            )));
+        }
+
+        let temperature = match env::var("LLM_TEMPERATURE") {
+            Ok(t) => t
+                .parse()
+                .map_err(|_| Error::Config(format!("LLM_TEMPERATURE '{}' is not a number", t)))?,
+            Err(_) => 0.6, // lower values make the answers concise, recommended 0.5 - 0.7
+        };
+
+        Ok(LlmConfig { provider, base_url, model, api_key, temperature })
+    }
+}
+
+// picks the implementation for the configured provider
+pub fn build_extractor(config: &LlmConfig) -> Arc<dyn MetadataExtractor> {
+    match config.provider {
+        LlmProvider::Mock => Arc::new(MockExtractor),
+        _ => Arc::new(OpenAiCompatibleExtractor::new(config.clone())),
+    }
+}
+
+// the same for the question answering
+pub fn build_answerer(config: &LlmConfig) -> Arc<dyn Answerer> {
+    match config.provider {
+        LlmProvider::Mock => Arc::new(MockExtractor),
+        _ => Arc::new(OpenAiCompatibleExtractor::new(config.clone())),
+    }
+}
+
+
+// groq, openai, ollama and llama.cpp all speak the openai chat completions dialect
+pub struct OpenAiCompatibleExtractor {
+    client: Client,
+    config: LlmConfig,
+}
+
+impl OpenAiCompatibleExtractor {
+    pub fn new(config: LlmConfig) -> Self {
+        OpenAiCompatibleExtractor { client: Client::new(), config }
+    }
+
+    // sends one chat completion and returns the raw message content; pass a response_format to
+    // hold the model to a json schema
+    async fn request_completion(&self, messages: &[Value], response_format: Option<Value>) -> Result<String> {
+        // json format request to the ai with the ai model
+        let mut body = json!({
+            "model": self.config.model,
+            "messages": messages,
+            "temperature": self.config.temperature,
+        });
+
+        if let Some(format) = response_format {
+            body["response_format"] = format;
+        }
+
+        // groq only: abstracts the thinking process; other apis reject unknown fields
+        if self.config.provider == LlmProvider::Groq {
+            body["include_reasoning"] = json!(false);
+        }
+
+        let mut req = self.client.post(&self.config.base_url).json(&body);
+        if let Some(key) = &self.config.api_key {
+            req = req.bearer_auth(key);
+        }
+
+        let resp = req.send().await.map_err(Error::LlmTransport)?;
+        let status = resp.status();
+        let text = resp.text().await.map_err(Error::LlmTransport)?;
+        if !status.is_success() {
+            return Err(Error::LlmApi { status: status.as_u16(), body: text });
+        }
+
+        let completion: ChatCompletion = serde_json::from_str(&text)
+            .map_err(|e| Error::LlmParse(format!("not a chat completion: {}", e)))?;
+        completion
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .filter(|content| !content.trim().is_empty())
+            .ok_or_else(|| Error::LlmParse("response had no message content".to_string()))
+    }
+}
+
+#[async_trait]
+impl MetadataExtractor for OpenAiCompatibleExtractor {
+    async fn extract(&self, text: &str) -> Result<ExtractedMetaData> {
+        // the conversation grows every time we have to re-prompt the model with a validation error
+        let messages = vec![
+            json!({
+                "role": "system",
+                "content": "You are an assistant that extracts structured metadata from documents. \
+                            Always answer with a single JSON object that matches the provided schema.",
+            }),
+            json!({
+                "role": "user",
+                "content": format!(
+                    "From the following text, extract:\n\
+                     - Genre\n\
+                     - Summary (with optimal keywords)\n\
+                     - Difficulty level (Beginner/Intermediate/Advanced)\n\
+                     - Title\n\
+                     - Resource type (Lecture Note/Textbook/Research Paper/Slides/Article/Other)\n\
+                     - Keywords\n\
+                     - Topics (broader subject areas)\n\
+                     - Language (ISO 639-1 code)\n\
+                     - Authors (empty if not stated)\n\n\
+                     Text:\n{}",
+                    text
+                )
+            }),
+        ];
+
+        extract_with_reprompts(messages, |messages| async move {
+            self.request_completion(&messages, Some(metadata_response_format())).await
+        })
+        .await
+    }
+
+    async fn summarise(&self, text: &str) -> Result<String> {
+        let messages = [
+            json!({
+                "role": "system",
+                "content": "You summarise one part of a longer document. Keep the title and authors if you see them, \
+                            what kind of document it is, the subject area, how advanced the material is \
+                            and the key terms. Write the summary in the document's own language.",
+            }),
+            json!({
+                "role": "user",
+                "content": format!("Summarise this part of the document in at most 200 words:\n{}", text),
+            }),
+        ];
+
+        let summary = self.request_completion(&messages, None).await?;
+        Ok(summary.trim().to_string())
+    }
+
+    fn name(&self) -> String {
+        format!("{}/{}", self.config.provider.as_str(), self.config.model)
+    }
+}
+
+#[async_trait]
+impl Answerer for OpenAiCompatibleExtractor {
+    async fn answer(&self, question: &str, passages: &[String]) -> Result<String> {
+        let messages = [
+            json!({
+                "role": "system",
+                "content": format!(
+                    "You answer questions about an archive of documents using only the numbered passages you are given. \
+                     Cite the passage behind every claim with its number in square brackets, e.g. [2]. \
+                     Do not use anything you know from elsewhere. If the passages don't answer the question, \
+                     reply with exactly {} and nothing else. Answer in the language of the question.",
+                    NO_ANSWER
+                ),
+            }),
+            json!({
+                "role": "user",
+                "content": grounded_prompt(question, passages),
+            }),
+        ];
+
+        let answer = self.request_completion(&messages, None).await?;
+        Ok(answer.trim().to_string())
+    }
+
+    fn name(&self) -> String {
+        format!("{}/{}", self.config.provider.as_str(), self.config.model)
+    }
+}
+
+
+// offline stand-in: derives metadata from the text itself so the same input always gives the same output
+pub struct MockExtractor;
+
+#[async_trait]
+impl MetadataExtractor for MockExtractor {
+    async fn extract(&self, text: &str) -> Result<ExtractedMetaData> {
+        let title = text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or("Untitled")
+            .chars()
+            .take(120)
+            .collect::<String>();
+
+        // longer documents are "harder", good enough for exercising the pipeline
+        let words = text.split_whitespace().count();
+        let difficulty = match words {
+            0..=2_000 => "Beginner",
+            2_001..=20_000 => "Intermediate",
+            _ => "Advanced",
+        };
+
+        let summary = text.split_whitespace().take(60).collect::<Vec<_>>().join(" ");
+
+        // the most frequent longer words make passable keywords; ties go to the earliest word
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| w.chars().count() > 4) {
+            let word = word.to_lowercase();
+            match counts.iter_mut().find(|(seen, _)| *seen == word) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((word, 1)),
+            }
+        }
+        counts.sort_by_key(|c| std::cmp::Reverse(c.1));
+        let mut keywords: Vec<String> = counts.into_iter().take(5).map(|(word, _)| word).collect();
+        if keywords.is_empty() {
+            keywords.push("document".to_string());
+        }
+
+        ExtractedMetaData {
+            title,
+            difficulty: difficulty.to_string(),
+            genre: "General".to_string(),
+            summary: if summary.is_empty() { "No text extracted.".to_string() } else { summary },
+            resource_type: "Other".to_string(),
+            keywords,
+            topics: vec!["General".to_string()],
+            language: "en".to_string(),
+            authors: Vec::new(),
+        }
+        .validate()
+    }
+
+    async fn summarise(&self, text: &str) -> Result<String> {
+        Ok(text.split_whitespace().take(40).collect::<Vec<_>>().join(" "))
+    }
+
+    fn name(&self) -> String {
+        "mock".to_string()
+    }
+}
+
+#[async_trait]
+impl Answerer for MockExtractor {
+    // quotes the sentence sharing the most words with the question and cites where it came from
+    async fn answer(&self, question: &str, passages: &[String]) -> Result<String> {
+        let words: Vec<String> = question
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| w.chars().count() > 3)
+            .map(str::to_lowercase)
+            .collect();
+
+        let mut best: Option<(usize, usize, &str)> = None; // (shared words, passage, sentence)
+        for (i, passage) in passages.iter().enumerate() {
+            for sentence in passage.split_inclusive(['.', '!', '?']).map(str::trim).filter(|s| !s.is_empty()) {
+                let lowered = sentence.to_lowercase();
+                let shared = words.iter().filter(|w| lowered.contains(w.as_str())).count();
+                if shared > 0 && best.is_none_or(|(most, _, _)| shared > most) {
+                    best = Some((shared, i, sentence));
+                }
+            }
+        }
+
+        Ok(match best {
+            Some((_, i, sentence)) => format!("{} [{}]", sentence, i + 1),
+            None => NO_ANSWER.to_string(),
+        })
+    }
+
+    fn name(&self) -> String {
+        "mock".to_string()
+    }
+}
+
+
+// the passages numbered the way Answerer::answer cites them, then the question
+fn grounded_prompt(question: &str, passages: &[String]) -> String {
+    let mut prompt = String::from("Passages:\n");
+    for (i, passage) in passages.iter().enumerate() {
+        prompt.push_str(&format!("\n[{}] {}\n", i + 1, passage.trim()));
+    }
+    prompt.push_str(&format!("\nQuestion: {}", question.trim()));
+    prompt
+}
+
+// the schema contract we attach to every extraction request
+fn metadata_response_format() -> Value {
+    json!({
+        "type": "json_schema",
+        "json_schema": {
+            "name": "extracted_metadata",
+            "strict": true,
+            "schema": ExtractedMetaData::json_schema(),
+        }
+    })
+}
+
+// asks until an answer parses and validates, at most MAX_EXTRACTION_ATTEMPTS times; every rejected
+// answer goes back to the model with the reason. `complete` sends the conversation so far and
+// returns the reply, so this doesn't care which backend is on the other end
+async fn extract_with_reprompts<F, Fut>(mut messages: Vec<Value>, mut complete: F) -> Result<ExtractedMetaData>
+where
+    F: FnMut(Vec<Value>) -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    let mut last_err = None;
+    for attempt in 1..=MAX_EXTRACTION_ATTEMPTS {
+        let content = complete(messages.clone()).await?;
+
+        match parse_metadata(&content) {
+            Ok(metadata) => {
+                println!("Extracted metadata on attempt {}: {:?}", attempt, metadata);
+                return Ok(metadata);
+            }
+            Err(err) => {
+                println!("Attempt {} returned unusable metadata: {}", attempt, err);
+
+                // show the model what it said and why it was rejected, then ask again
+                messages.push(json!({ "role": "assistant", "content": content }));
+                messages.push(json!({
+                    "role": "user",
+                    "content": format!(
+                        "Your previous answer was rejected: {}. \
+                         Reply again with only a JSON object that matches the schema.",
+                        err
+                    )
+                }));
+                last_err = Some(err);
+            }
+        }
+    }
+
+    Err(Error::LlmExhausted {
+        attempts: MAX_EXTRACTION_ATTEMPTS,
+        last: Box::new(last_err.expect("at least one attempt was made")),
+    })
+}
+
+// just the parts of an openai style chat completion we actually read
+#[derive(Debug, Deserialize)]
+struct ChatCompletion {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChoice {
+    message: ChatMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    content: Option<String>,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const VALID: &str = r#"{"title": "Cell Biology", "difficulty": "Beginner", "genre": "Science",
+        "summary": "How cells work.", "resource_type": "Textbook", "keywords": ["cells"], "topics": [],
+        "language": "en", "authors": []}"#;
+
+    // a provider that gives the scripted replies in order and keeps every conversation it was sent
+    struct Scripted {
+        replies: Mutex<Vec<String>>,
+        sent: Mutex<Vec<Vec<Value>>>,
+    }
+
+    impl Scripted {
+        fn new(replies: &[&str]) -> Self {
+            Scripted {
+                replies: Mutex::new(replies.iter().rev().map(|reply| reply.to_string()).collect()),
+                sent: Mutex::new(Vec::new()),
+            }
+        }
+
+        async fn extract(&self) -> Result<ExtractedMetaData> {
+            let prompt = vec![json!({ "role": "user", "content": "extract this" })];
+            extract_with_reprompts(prompt, |messages| async move {
+                self.sent.lock().unwrap().push(messages);
+                self.replies.lock().unwrap().pop().ok_or_else(|| Error::LlmParse("out of replies".to_string()))
+            })
+            .await
+        }
+    }
+
+    #[tokio::test]
+    async fn takes_the_first_usable_answer() {
+        let provider = Scripted::new(&[VALID]);
+        assert_eq!(provider.extract().await.unwrap().title, "Cell Biology");
+        assert_eq!(provider.sent.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reprompts_with_the_reason_an_answer_was_rejected() {
+        let unknown_difficulty = VALID.replace("Beginner", "Expert");
+        let provider = Scripted::new(&["not json at all", &unknown_difficulty, VALID]);
+        assert_eq!(provider.extract().await.unwrap().title, "Cell Biology");
+
+        let sent = provider.sent.lock().unwrap();
+        assert_eq!(sent.iter().map(Vec::len).collect::<Vec<_>>(), vec![1, 3, 5]);
+        // the rejected answer goes back as the model's, followed by what was wrong with it
+        assert_eq!(sent[2][3]["content"], unknown_difficulty);
+        assert!(sent[2][4]["content"].as_str().unwrap().contains("difficulty"));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_attempt() {
+        let provider = Scripted::new(&["no", "still no", "```json\n{}\n```", VALID]);
+        match provider.extract().await {
+            Err(Error::LlmExhausted { attempts, last }) => {
+                assert_eq!(attempts, MAX_EXTRACTION_ATTEMPTS);
+                assert!(matches!(*last, Error::LlmParse(_)));
+            }
+            other => panic!("expected the attempts to run out, got {:?}", other.map(|m| m.title)),
+        }
+        assert_eq!(provider.sent.lock().unwrap().len(), MAX_EXTRACTION_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn transport_errors_are_not_retried_here() {
+        let provider = Scripted::new(&[]);
+        assert!(matches!(provider.extract().await, Err(Error::LlmParse(message)) if message == "out of replies"));
+        assert_eq!(provider.sent.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_mock_provider_produces_valid_metadata() {
+        let metadata = MockExtractor.extract("Cell Biology\n\nCells divide by mitosis. Mitosis has phases.").await.unwrap();
+        assert_eq!(metadata.title, "Cell Biology");
+        assert!(metadata.clone().validate().is_ok());
+        assert!(MockExtractor.extract("").await.is_ok());
+    }
+}
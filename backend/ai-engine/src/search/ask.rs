@@ -0,0 +1,271 @@
+// ask.rs: answers questions from the archive itself, retrieval first, then the llm, with citations
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+use crate::database::database::query_terms;
+use crate::database::repository::ArchiveRepository;
+use crate::error::Result;
+use crate::nlp::provider::{Answerer, NO_ANSWER};
+use crate::search::passages::{passage_search, PassageHit, PassageQuery};
+use crate::vector::passages::PassageIndex;
+
+
+// passages handed to the model at most; more only dilutes the prompt
+pub const MAX_CONTEXT_PASSAGES: usize = 10;
+
+// a passage the vector side found this close counts as relevant even without a shared word.
+// stricter than search's cut-off, an answer built on a loose match is worse than no answer
+const MAX_ANSWER_DISTANCE: f32 = 0.6;
+
+// what we say when the archive has nothing to go on
+pub const REFUSAL: &str = "I couldn't find anything in the archive that answers this question.";
+
+// words every question has, they say nothing about whether a passage is on topic
+const STOPWORDS: &[&str] = &[
+    "a", "about", "an", "and", "are", "can", "did", "do", "does", "for", "from", "how", "in", "is", "it", "of",
+    "on", "or", "the", "there", "this", "to", "was", "were", "what", "when", "where", "which", "who", "why",
+    "with", "you",
+];
+
+#[derive(Debug, Clone)]
+pub struct AskQuery {
+    pub question: String,
+    pub record_id: Option<i64>, // only answer from this record
+    pub limit: usize,           // passages to retrieve
+}
+
+// one passage the answer relies on; number is the [n] used in the answer text
+#[derive(Debug, Clone, Serialize)]
+pub struct Citation {
+    pub number: usize,
+    pub record_id: i64,
+    pub title: String,
+    pub file_cid: String,
+    pub passage_id: i64,
+    pub page: Option<i64>,
+    pub position: i64,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Answer {
+    pub question: String,
+    pub answered: bool, // false when retrieval or the model came up empty, answer is then REFUSAL
+    pub answer: String,
+    pub citations: Vec<Citation>,
+    pub model: String,
+}
+
+// retrieves the best passages for the question, keeps the ones that are actually about it and asks
+// the model to answer from those alone. nothing relevant means no llm call at all
+pub async fn ask(
+    repo: &ArchiveRepository,
+    passages: &PassageIndex,
+    answerer: &dyn Answerer,
+    query: AskQuery,
+) -> Result<Answer> {
+    let limit = query.limit.clamp(1, MAX_CONTEXT_PASSAGES);
+    let search = PassageQuery { query: query.question.clone(), record_id: query.record_id, limit: limit * 2 };
+    let hits = passage_search(repo, passages, search).await?;
+
+    let terms = content_terms(&query.question);
+    let context: Vec<PassageHit> = hits.into_iter().filter(|hit| is_relevant(hit, &terms)).take(limit).collect();
+
+    let refusal = |question: String| Answer {
+        question,
+        answered: false,
+        answer: REFUSAL.to_string(),
+        citations: Vec::new(),
+        model: answerer.name(),
+    };
+    if context.is_empty() {
+        return Ok(refusal(query.question));
+    }
+
+    let texts: Vec<String> = context.iter().map(|hit| hit.text.clone()).collect();
+    let reply = answerer.answer(&query.question, &texts).await?;
+    if is_no_answer(&reply) {
+        return Ok(refusal(query.question));
+    }
+
+    // only what the answer cites; a model that cited nothing still answered from all of it
+    let mut cited = cited_numbers(&reply, context.len());
+    if cited.is_empty() {
+        cited = (1..=context.len()).collect();
+    }
+    let citations = cited
+        .into_iter()
+        .map(|number| {
+            let hit = &context[number - 1];
+            Citation {
+                number,
+                record_id: hit.record_id,
+                title: hit.title.clone(),
+                file_cid: hit.file_cid.clone(),
+                passage_id: hit.passage_id,
+                page: hit.page,
+                position: hit.position,
+                text: hit.text.clone(),
+            }
+        })
+        .collect();
+
+    Ok(Answer { question: query.question, answered: true, answer: reply, citations, model: answerer.name() })
+}
+
+
+// helper functions
+// the question's words minus the ones every question has
+fn content_terms(question: &str) -> Vec<String> {
+    query_terms(question)
+        .into_iter()
+        .filter(|t| t.chars().count() > 2 && !STOPWORDS.contains(&t.as_str()))
+        .collect()
+}
+
+// a passage is on topic if it shares a real word with the question (prefix, like the fts match)
+// or the embedder puts it close
+fn is_relevant(hit: &PassageHit, terms: &[String]) -> bool {
+    if hit.distance.is_some_and(|d| d <= MAX_ANSWER_DISTANCE) {
+        return true;
+    }
+    query_terms(&hit.text).iter().any(|word| terms.iter().any(|t| word.starts_with(t.as_str())))
+}
+
+// models like to dress it up ("NO_ANSWER.", "`NO_ANSWER`")
+fn is_no_answer(reply: &str) -> bool {
+    reply.trim().trim_matches(|c: char| !c.is_alphanumeric() && c != '_') == NO_ANSWER || reply.trim().is_empty()
+}
+
+// every [n] (or [n, m]) in the answer that points at a passage we gave it, sorted, no repeats
+fn cited_numbers(answer: &str, passages: usize) -> Vec<usize> {
+    let mut numbers = BTreeSet::new();
+    for part in answer.split('[').skip(1) {
+        let Some((inside, _)) = part.split_once(']') else { continue };
+        let parsed: Option<Vec<usize>> =
+            inside.split(',').map(|n| n.trim().parse::<usize>().ok()).collect();
+        for n in parsed.unwrap_or_default() {
+            if (1..=passages).contains(&n) {
+                numbers.insert(n);
+            }
+        }
+    }
+    numbers.into_iter().collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::extract::{DocumentFormat, ExtractedDocument};
+    use crate::nlp::engine::{ExtractedMetaData, FileRecord};
+    use crate::nlp::provider::MockExtractor;
+    use crate::solana::solana::MemoReceipt;
+    use crate::vector::embedder::HashEmbedder;
+    use std::sync::Arc;
+
+    // an archive of one record whose passages are in both the text and the vector index
+    async fn archive(pages: &[&str]) -> (ArchiveRepository, PassageIndex) {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let metadata = ExtractedMetaData {
+            title: "Plant Biology".to_string(),
+            difficulty: "Beginner".to_string(),
+            genre: "Science".to_string(),
+            summary: "plants".to_string(),
+            resource_type: "Textbook".to_string(),
+            keywords: Vec::new(),
+            topics: Vec::new(),
+            language: "en".to_string(),
+            authors: Vec::new(),
+        };
+        let file = FileRecord { file_hash: "hash".to_string(), file_cid: "cid".to_string() };
+        let anchor = MemoReceipt { signature: "sig".to_string(), slot: None, block_time: None };
+        let document = ExtractedDocument {
+            format: DocumentFormat::Pdf,
+            pages: pages.iter().map(|page| page.to_string()).collect(),
+            paged: true,
+        };
+        let id = repo.insert(&metadata, &file, &anchor, &document, None).unwrap();
+
+        let index = PassageIndex::new(repo.clone(), Arc::new(HashEmbedder::new(256)));
+        index.embed_records(vec![id]).await.unwrap();
+        (repo, index)
+    }
+
+    fn question(text: &str) -> AskQuery {
+        AskQuery { question: text.to_string(), record_id: None, limit: 5 }
+    }
+
+    fn hit(text: &str, distance: Option<f32>) -> PassageHit {
+        PassageHit {
+            passage_id: 1,
+            record_id: 1,
+            title: "t".to_string(),
+            file_cid: "cid".to_string(),
+            page: None,
+            position: 0,
+            text: text.to_string(),
+            snippet: String::new(),
+            score: 0.0,
+            matched_text: false,
+            matched_vector: distance.is_some(),
+            distance,
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_when_nothing_relevant_is_found() {
+        let (repo, index) = archive(&["Chlorophyll absorbs light in the leaves."]).await;
+
+        let answer = ask(&repo, &index, &MockExtractor, question("Who won the football league?")).await.unwrap();
+        assert!(!answer.answered);
+        assert_eq!(answer.answer, REFUSAL);
+        assert!(answer.citations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn answers_with_the_passages_it_cites() {
+        let (repo, index) = archive(&[
+            "Photosynthesis turns light into sugar inside the chloroplasts.",
+            "Roots take up water and minerals from the soil.",
+        ])
+        .await;
+
+        let answer = ask(&repo, &index, &MockExtractor, question("Where does photosynthesis happen?")).await.unwrap();
+        assert!(answer.answered);
+        assert_eq!(answer.model, "mock");
+        assert!(answer.answer.contains("chloroplasts"));
+
+        // the mock cites the one passage it quoted, so that is the only citation
+        assert_eq!(answer.citations.len(), 1);
+        let citation = &answer.citations[0];
+        assert_eq!(citation.page, Some(1));
+        assert!(citation.text.contains("Photosynthesis"));
+        assert!(answer.answer.contains(&format!("[{}]", citation.number)));
+    }
+
+    #[test]
+    fn parses_citations() {
+        assert_eq!(cited_numbers("a [2] b [1, 3] c [2]", 3), vec![1, 2, 3]);
+        // out of range, not numbers, unclosed
+        assert_eq!(cited_numbers("[0] [4] [x] [1", 3), Vec::<usize>::new());
+        assert!(is_no_answer(" `NO_ANSWER`. "));
+        assert!(is_no_answer(""));
+        assert!(!is_no_answer("NO_ANSWER is not what I'd say"));
+    }
+
+    #[test]
+    fn close_vectors_count_only_within_the_cut_off() {
+        let terms = content_terms("What is the capital of France?");
+        assert_eq!(terms, vec!["capital", "france"]);
+
+        // no shared word, so only the distance decides
+        let text = "Paris sits on the Seine.";
+        assert!(is_relevant(&hit(text, Some(MAX_ANSWER_DISTANCE)), &terms));
+        assert!(!is_relevant(&hit(text, Some(MAX_ANSWER_DISTANCE + 0.01)), &terms));
+        assert!(!is_relevant(&hit(text, None), &terms));
+
+        // a shared word is enough however far the vectors are
+        assert!(is_relevant(&hit("The capital city.", Some(0.99)), &terms));
+    }
+}
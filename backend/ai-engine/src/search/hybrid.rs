@@ -0,0 +1,330 @@
+// hybrid.rs: one search that ranks by both full text (bm25) and vector similarity
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::database::database::{count_search_matches, full_text_search, load_record, query_terms, ArchiveRecord};
+use crate::database::repository::ArchiveRepository;
+use crate::error::{run_blocking, Result};
+use crate::vector::index::SemanticIndex;
+
+
+// the usual reciprocal rank fusion constant; keeps one list's top hit from drowning the other list
+const RRF_K: f64 = 60.0;
+
+// how many candidates the text side contributes at least, whatever page is asked for. the vector
+// side always contributes up to the most, so the same records count towards the total on every page
+const MIN_CANDIDATES: usize = 50;
+const MAX_CANDIDATES: usize = 500;
+
+// vector hits further away than this (1 - cosine similarity) share nothing worth showing
+pub(crate) const MAX_VECTOR_DISTANCE: f32 = 0.85;
+
+pub const MAX_PER_PAGE: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct HybridQuery {
+    pub query: String,
+    pub genre: Option<String>,
+    pub difficulty: Option<String>,
+    pub page: usize,     // 1 based
+    pub per_page: usize,
+}
+
+// an archive record plus why it matched
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub record: ArchiveRecord,
+    pub score: f64,
+    pub snippet: String,
+    pub matched_text: bool,
+    pub matched_vector: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchPage {
+    pub query: String,
+    pub page: usize,
+    pub per_page: usize,
+    pub total: usize, // every record matched, not only the ones ranked for this page
+    pub results: Vec<SearchHit>,
+}
+
+// one candidate after rank fusion, and which sides found it
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Fused {
+    pub id: i64,
+    pub score: f64,
+    pub snippet: Option<String>, // from the text side
+    pub distance: Option<f32>,   // from the vector side
+}
+
+impl Fused {
+    pub fn text(&self) -> bool {
+        self.snippet.is_some()
+    }
+
+    pub fn vector(&self) -> bool {
+        self.distance.is_some()
+    }
+}
+
+// bm25 and vector results fused with reciprocal rank fusion (score = sum of 1 / (60 + rank)).
+// if the embedder is down the text results still come back
+pub async fn hybrid_search(repo: &ArchiveRepository, index: &SemanticIndex, query: HybridQuery) -> Result<SearchPage> {
+    let page = query.page.max(1);
+    let per_page = query.per_page.clamp(1, MAX_PER_PAGE);
+    let candidates = (page * per_page).clamp(MIN_CANDIDATES, MAX_CANDIDATES);
+
+    // text side, filtered in sql
+    let (db, q, genre, difficulty) = (repo.clone(), query.query.clone(), query.genre.clone(), query.difficulty.clone());
+    let text_matches = run_blocking(move || {
+        let conn = db.conn()?;
+        full_text_search(&conn, &q, genre.as_deref(), difficulty.as_deref(), candidates)
+    })
+    .await?;
+    let text_ranking = text_matches.into_iter().map(|hit| (hit.id, hit.snippet)).collect();
+
+    // vector side, filtered on the metadata the index keeps
+    let mut vector_ranking = Vec::new();
+    match index.search(&query.query, MAX_CANDIDATES).await {
+        Ok(result) => {
+            let ids = result.ids.into_iter().next().unwrap_or_default();
+            let metadatas = result.metadatas.into_iter().next().unwrap_or_default();
+            let distances = result.distances.into_iter().next().unwrap_or_default();
+            for ((id, metadata), distance) in ids.iter().zip(metadatas).zip(distances) {
+                let Ok(id) = id.parse::<i64>() else { continue };
+                if distance > MAX_VECTOR_DISTANCE {
+                    break; // nearest first, so everything after is further still
+                }
+                if field_matches(&metadata, "genre", query.genre.as_deref())
+                    && field_matches(&metadata, "difficulty", query.difficulty.as_deref())
+                {
+                    vector_ranking.push((id, distance));
+                }
+            }
+        }
+        Err(e) => println!("Hybrid search without vectors, the index failed: {}", e),
+    }
+
+    // the fused list stops at the text side's candidates, so the total is counted apart from it
+    let (db, q, genre, difficulty) = (repo.clone(), query.query.clone(), query.genre.clone(), query.difficulty.clone());
+    let vector_ids: Vec<i64> = vector_ranking.iter().map(|(id, _)| *id).collect();
+    let total = run_blocking(move || {
+        let conn = db.conn()?;
+        count_search_matches(&conn, &q, genre.as_deref(), difficulty.as_deref(), &vector_ids)
+    })
+    .await?;
+
+    let ranked = rrf_fuse(text_ranking, vector_ranking);
+
+    let page_hits: Vec<Fused> = ranked.into_iter().skip((page - 1) * per_page).take(per_page).collect();
+    let terms = query_terms(&query.query);
+    let repo = repo.clone();
+    let results = run_blocking(move || {
+        let conn = repo.conn()?;
+        let mut results = Vec::with_capacity(page_hits.len());
+        for hit in page_hits {
+            // the vector index can briefly know a record the archive already dropped
+            let record = match load_record(&conn, hit.id) {
+                Ok(record) => record,
+                Err(crate::error::Error::Database(rusqlite::Error::QueryReturnedNoRows)) => continue,
+                Err(e) => return Err(e),
+            };
+            let (matched_text, matched_vector) = (hit.text(), hit.vector());
+            let snippet = hit.snippet.unwrap_or_else(|| highlight(&record.summary, &terms));
+            results.push(SearchHit { record, score: hit.score, snippet, matched_text, matched_vector });
+        }
+        Ok(results)
+    })
+    .await?;
+
+    Ok(SearchPage { query: query.query, page, per_page, total, results })
+}
+
+
+// helper functions
+// reciprocal rank fusion of the text ranking (best first, with snippets) and the vector ranking
+// (nearest first, with distances): every list a candidate is in adds 1 / (RRF_K + its rank).
+// best first, ties going to the lower id so pages don't shuffle between requests
+pub(crate) fn rrf_fuse(text: Vec<(i64, String)>, vector: Vec<(i64, f32)>) -> Vec<Fused> {
+    fn candidate(fused: &mut HashMap<i64, Fused>, id: i64, rank: usize) -> &mut Fused {
+        let entry = fused.entry(id).or_insert(Fused { id, score: 0.0, snippet: None, distance: None });
+        entry.score += 1.0 / (RRF_K + rank as f64 + 1.0);
+        entry
+    }
+
+    let mut fused = HashMap::new();
+    for (rank, (id, snippet)) in text.into_iter().enumerate() {
+        candidate(&mut fused, id, rank).snippet = Some(snippet);
+    }
+    for (rank, (id, distance)) in vector.into_iter().enumerate() {
+        candidate(&mut fused, id, rank).distance = Some(distance);
+    }
+
+    let mut ranked: Vec<Fused> = fused.into_values().collect();
+    ranked.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.id.cmp(&b.id)));
+    ranked
+}
+
+fn field_matches(metadata: &serde_json::Value, field: &str, wanted: Option<&str>) -> bool {
+    match wanted {
+        None => true,
+        Some(wanted) => metadata
+            .get(field)
+            .and_then(|v| v.as_str())
+            .is_some_and(|v| v.eq_ignore_ascii_case(wanted)),
+    }
+}
+
+// for hits only the vector side found: the start of the text with query words marked the
+// same way fts5's snippet() marks them
+pub(crate) fn highlight(text: &str, terms: &[String]) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut out: Vec<String> = words
+        .iter()
+        .take(32)
+        .map(|word| {
+            let bare: String = word.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase();
+            if !bare.is_empty() && terms.iter().any(|t| bare.starts_with(t.as_str())) {
+                format!("<mark>{}</mark>", word)
+            } else {
+                word.to_string()
+            }
+        })
+        .collect();
+    if words.len() > 32 {
+        out.push("…".to_string());
+    }
+    out.join(" ")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::extract::{DocumentFormat, ExtractedDocument};
+    use crate::nlp::engine::{ExtractedMetaData, FileRecord};
+    use crate::solana::solana::MemoReceipt;
+    use crate::vector::embedder::HashEmbedder;
+    use std::sync::Arc;
+
+    fn text(ids: &[i64]) -> Vec<(i64, String)> {
+        ids.iter().map(|id| (*id, format!("snippet {}", id))).collect()
+    }
+
+    fn vector(ids: &[i64]) -> Vec<(i64, f32)> {
+        ids.iter().enumerate().map(|(rank, id)| (*id, 0.1 * rank as f32)).collect()
+    }
+
+    // n records about botany, every third one a Science record, and an empty vector index
+    fn archive(n: usize) -> (ArchiveRepository, SemanticIndex, std::path::PathBuf) {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        for i in 0..n {
+            let metadata = ExtractedMetaData {
+                title: format!("Botany volume {}", i),
+                difficulty: "Beginner".to_string(),
+                genre: if i % 3 == 0 { "Science" } else { "History" }.to_string(),
+                summary: "plants and how they grow".to_string(),
+                resource_type: "Textbook".to_string(),
+                keywords: vec!["botany".to_string()],
+                topics: Vec::new(),
+                language: "en".to_string(),
+                authors: Vec::new(),
+            };
+            let file = FileRecord { file_hash: format!("hash-{}", i), file_cid: format!("cid-{}", i) };
+            let anchor = MemoReceipt { signature: format!("sig-{}", i), slot: None, block_time: None };
+            let document = ExtractedDocument { format: DocumentFormat::Txt, pages: vec!["text".to_string()], paged: false };
+            repo.insert(&metadata, &file, &anchor, &document, None).unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("hybrid-test-{}.json", uuid::Uuid::new_v4()));
+        let index = SemanticIndex::open(&path, Arc::new(HashEmbedder::new(64))).unwrap();
+        (repo, index, path)
+    }
+
+    fn query(q: &str, genre: Option<&str>, page: usize, per_page: usize) -> HybridQuery {
+        HybridQuery { query: q.to_string(), genre: genre.map(str::to_string), difficulty: None, page, per_page }
+    }
+
+    #[test]
+    fn both_lists_beat_either_one() {
+        let ranked = rrf_fuse(text(&[1, 2, 3]), vector(&[3, 4]));
+        let ids: Vec<i64> = ranked.iter().map(|hit| hit.id).collect();
+        // 3 is in both lists, 1 tops one; 2 and 4 are both second in one and tie
+        assert_eq!(ids, vec![3, 1, 2, 4]);
+
+        assert!(ranked[0].text() && ranked[0].vector());
+        assert_eq!(ranked[0].snippet.as_deref(), Some("snippet 3"));
+        assert_eq!(ranked[0].distance, Some(0.0));
+        assert!((ranked[0].score - (1.0 / 63.0 + 1.0 / 61.0)).abs() < 1e-12);
+        assert!(ranked[2].text() && !ranked[2].vector());
+        assert!(!ranked[3].text() && ranked[3].vector());
+    }
+
+    #[test]
+    fn ties_go_to_the_lower_id() {
+        let ranked = rrf_fuse(text(&[7]), vector(&[5]));
+        assert_eq!(ranked.iter().map(|hit| hit.id).collect::<Vec<_>>(), vec![5, 7]);
+        assert_eq!(ranked[0].score, ranked[1].score);
+        assert!(rrf_fuse(Vec::new(), Vec::new()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn pages_through_every_match() {
+        let (repo, index, path) = archive(7);
+
+        let first = hybrid_search(&repo, &index, query("botany", None, 1, 3)).await.unwrap();
+        let third = hybrid_search(&repo, &index, query("botany", None, 3, 3)).await.unwrap();
+        let past = hybrid_search(&repo, &index, query("botany", None, 4, 3)).await.unwrap();
+        assert_eq!((first.total, first.results.len()), (7, 3));
+        assert_eq!((third.total, third.results.len()), (7, 1));
+        assert_eq!((past.total, past.results.len()), (7, 0));
+
+        // no record turns up on two pages
+        let second = hybrid_search(&repo, &index, query("botany", None, 2, 3)).await.unwrap();
+        let mut seen: Vec<i64> = [&first, &second, &third].iter().flat_map(|page| page.results.iter().map(|hit| hit.record.id)).collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 7);
+        assert!(first.results.iter().all(|hit| hit.matched_text && !hit.matched_vector));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn filters_count_towards_the_total() {
+        let (repo, index, path) = archive(7);
+
+        let science = hybrid_search(&repo, &index, query("botany", Some("science"), 1, 10)).await.unwrap();
+        assert_eq!(science.total, 3);
+        assert!(science.results.iter().all(|hit| hit.record.genre == "Science"));
+
+        let nothing = hybrid_search(&repo, &index, query("zoology", None, 1, 10)).await.unwrap();
+        assert_eq!((nothing.total, nothing.results.len()), (0, 0));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn total_counts_past_the_candidates() {
+        let n = MIN_CANDIDATES + 12;
+        let (repo, index, path) = archive(n);
+
+        let first = hybrid_search(&repo, &index, query("botany", None, 1, 10)).await.unwrap();
+        let last = hybrid_search(&repo, &index, query("botany", None, 7, 10)).await.unwrap();
+        assert_eq!((first.total, first.results.len()), (n, 10));
+        assert_eq!((last.total, last.results.len()), (n, 2));
+
+        let science = hybrid_search(&repo, &index, query("botany", Some("science"), 1, 5)).await.unwrap();
+        assert_eq!(science.total, n.div_ceil(3));
+
+        // vector hits count once, whether or not the text found them too, and only under the filters
+        let conn = repo.conn().unwrap();
+        assert_eq!(count_search_matches(&conn, "botany", None, None, &[1, 2]).unwrap(), n);
+        assert_eq!(count_search_matches(&conn, "zoology", None, None, &[1, 2, 9999]).unwrap(), 2);
+        assert_eq!(count_search_matches(&conn, "zoology", Some("science"), None, &[1, 2]).unwrap(), 1);
+        assert_eq!(count_search_matches(&conn, "", None, None, &[3]).unwrap(), 1);
+
+        let _ = std::fs::remove_file(path);
+    }
+}
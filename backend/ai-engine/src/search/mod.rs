@@ -0,0 +1,3 @@
+pub mod hybrid;
+pub mod passages;
+pub mod ask;
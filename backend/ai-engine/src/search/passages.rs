@@ -0,0 +1,101 @@
+// passages.rs: search inside documents, answering with the matching passage and its page
+use rusqlite::OptionalExtension;
+use serde::Serialize;
+
+use crate::database::database::{load_passage, passage_text_search, query_terms};
+use crate::database::repository::ArchiveRepository;
+use crate::error::{run_blocking, Error, Result};
+use crate::search::hybrid::{highlight, rrf_fuse, MAX_VECTOR_DISTANCE};
+use crate::vector::passages::PassageIndex;
+
+
+const MIN_CANDIDATES: usize = 50;
+pub const MAX_PASSAGES: usize = 50;
+
+#[derive(Debug, Clone)]
+pub struct PassageQuery {
+    pub query: String,
+    pub record_id: Option<i64>, // only search inside this record
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PassageHit {
+    pub passage_id: i64,
+    pub record_id: i64,
+    pub title: String,
+    pub file_cid: String,
+    pub page: Option<i64>, // 1 based, pdfs only
+    pub position: i64,
+    pub text: String,
+    pub snippet: String,
+    pub score: f64,
+    pub matched_text: bool,
+    pub matched_vector: bool,
+    pub distance: Option<f32>, // vector distance (1 - cosine similarity), if the vector side found it
+}
+
+// the same rank fusion as hybrid_search, over passages instead of records
+pub async fn passage_search(repo: &ArchiveRepository, passages: &PassageIndex, query: PassageQuery) -> Result<Vec<PassageHit>> {
+    let limit = query.limit.clamp(1, MAX_PASSAGES);
+    let candidates = (limit * 4).max(MIN_CANDIDATES);
+
+    let (db, q, record_id) = (repo.clone(), query.query.clone(), query.record_id);
+    let text_matches = run_blocking(move || {
+        let conn = db.conn()?;
+        passage_text_search(&conn, &q, record_id, candidates)
+    })
+    .await?;
+    let text_ranking = text_matches.into_iter().map(|hit| (hit.passage_id, hit.snippet)).collect();
+
+    let mut vector_ranking = Vec::new();
+    match passages.search(&query.query, candidates, query.record_id).await {
+        Ok(nearest) => {
+            vector_ranking = nearest.into_iter().take_while(|(_, distance)| *distance <= MAX_VECTOR_DISTANCE).collect();
+        }
+        Err(e) => println!("Passage search without vectors, the index failed: {}", e),
+    }
+
+    let mut ranked = rrf_fuse(text_ranking, vector_ranking);
+    ranked.truncate(limit);
+
+    let terms = query_terms(&query.query);
+    let repo = repo.clone();
+    run_blocking(move || {
+        let conn = repo.conn()?;
+        let mut hits = Vec::with_capacity(ranked.len());
+        for hit in ranked {
+            // the vector index can briefly know passages that were re-cut since
+            let passage = match load_passage(&conn, hit.id) {
+                Ok(passage) => passage,
+                Err(Error::Database(rusqlite::Error::QueryReturnedNoRows)) => continue,
+                Err(e) => return Err(e),
+            };
+            let record: Option<(String, String)> = conn
+                .query_row("SELECT title, file_cid FROM archive WHERE id = ?1 AND deleted_at IS NULL", [passage.archive_id], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })
+                .optional()?;
+            let Some((title, file_cid)) = record else { continue };
+
+            let (matched_text, matched_vector) = (hit.text(), hit.vector());
+            let snippet = hit.snippet.unwrap_or_else(|| highlight(&passage.text, &terms));
+            hits.push(PassageHit {
+                passage_id: passage.id,
+                record_id: passage.archive_id,
+                title,
+                file_cid,
+                page: passage.page,
+                position: passage.position,
+                text: passage.text,
+                snippet,
+                score: hit.score,
+                matched_text,
+                matched_vector,
+                distance: hit.distance,
+            });
+        }
+        Ok(hits)
+    })
+    .await
+}
@@ -1,63 +1,344 @@
 // solana.rs: This file defines all the utilties for uploading the hash and the cid to the solana blockchain
 use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_commitment_config::CommitmentConfig;
+use solana_transaction_status_client_types::UiTransactionEncoding;
+use serde::Serialize;
 use solana_sdk::{
-    signature::{ Signer, Keypair},
+    signature::{ Signer, Keypair, Signature, read_keypair_file, write_keypair_file},
     transaction::Transaction,
     pubkey::Pubkey,
-    instruction::Instruction,
+    instruction::{AccountMeta, Instruction},
 
 };
+use std::env;
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
+use dotenv::dotenv;
 
+use crate::error::{Error, Result};
 
-// store hash+cid to Solana returns the transaction signature
-pub fn send_memo(hash: &str, cid: &str) -> anyhow::Result<String> {
-    // 1. Connect to cluster (devnet for testing)
-    let rpc = RpcClient::new("http://localhost:8899".to_string());
 
-    // 2. creates a new key pair, for testing purposes airdrops some sol in it
-    let keypair = Keypair::new();
-    println!("Pubkey: {}", keypair.pubkey());
+pub const MEMO_PROGRAM_ID: &str = "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr";
+
+// below this balance we ask for an airdrop (when airdrops are allowed at all)
+const MIN_BALANCE_LAMPORTS: u64 = 10_000_000;
+const AIRDROP_LAMPORTS: u64 = 1_000_000_000;
+
+// where and as whom we post memos, read from the environment (.env works too)
+//
+//   SOLANA_RPC_URL     default http://localhost:8899
+//   SOLANA_COMMITMENT  processed | confirmed | finalized   (default confirmed)
+//   SOLANA_KEYPAIR     path to the archive's payer keypair (default ./archive-keypair.json)
+//   SOLANA_AIRDROP     "true" to request airdrops when the payer runs low; local validators only
+#[derive(Debug, Clone)]
+pub struct SolanaConfig {
+    pub rpc_url: String,
+    pub commitment: CommitmentConfig,
+    pub keypair_path: PathBuf,
+    pub airdrop: bool,
+}
+
+impl SolanaConfig {
+    pub fn from_env() -> Result<Self> {
+        // load the dotenv variables
+        dotenv().ok();
+
+        let rpc_url = env::var("SOLANA_RPC_URL").unwrap_or_else(|_| "http://localhost:8899".to_string());
+
+        let commitment = match env::var("SOLANA_COMMITMENT").as_deref().unwrap_or("confirmed") {
+            "processed" => CommitmentConfig::processed(),
+            "confirmed" => CommitmentConfig::confirmed(),
+            "finalized" => CommitmentConfig::finalized(),
+            other => {
+                return Err(Error::Config(format!(
+                    "SOLANA_COMMITMENT '{}' must be processed, confirmed or finalized",
+                    other
+                )))
+            }
+        };
+
+        let keypair_path = PathBuf::from(env::var("SOLANA_KEYPAIR").unwrap_or_else(|_| "archive-keypair.json".to_string()));
+
+        let airdrop = matches!(env::var("SOLANA_AIRDROP").as_deref(), Ok("true") | Ok("1"));
+
+        Ok(SolanaConfig { rpc_url, commitment, keypair_path, airdrop })
+    }
+}
+
+// what we keep about a posted memo so the record can be verified later
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoReceipt {
+    pub signature: String,
+    pub slot: Option<u64>,
+    pub block_time: Option<i64>, // unix seconds
+}
+
+// a memo transaction as the chain reports it
+#[derive(Debug, Clone, Serialize)]
+pub struct OnChainMemo {
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub succeeded: bool,
+    pub memo: Option<String>,
+    pub signers: Vec<String>, // accounts the memo instruction lists as signers
+}
+
+// the archive's on-chain identity: one payer keypair that signs every memo we post
+pub struct MemoSender {
+    rpc: RpcClient,
+    payer: Keypair,
+    airdrop: bool,
+    commitment: CommitmentConfig,
+}
+
+impl MemoSender {
+    // loads the payer keypair from disk. a missing keypair is only generated when airdrops are on,
+    // since a fresh key on devnet/mainnet has no funds and would just fail later
+    pub fn new(config: &SolanaConfig) -> Result<Self> {
+        let payer = if config.keypair_path.exists() {
+            read_keypair_file(&config.keypair_path)
+                .map_err(|e| Error::Config(format!("could not read keypair {:?}: {}", config.keypair_path, e)))?
+        } else if config.airdrop {
+            create_keypair_file(&config.keypair_path)?
+        } else {
+            return Err(Error::Config(format!(
+                "keypair {:?} does not exist; create one with `solana-keygen new -o {}` and fund it, \n \
+                 or set SOLANA_AIRDROP=true on a local validator",
+                config.keypair_path,
+                config.keypair_path.display()
+            )));
+        };
 
+        let rpc = RpcClient::new_with_commitment(config.rpc_url.clone(), config.commitment);
+        Ok(MemoSender { rpc, payer, airdrop: config.airdrop, commitment: config.commitment })
+    }
+
+    // the pubkey every memo is attributable to
+    pub fn pubkey(&self) -> Pubkey {
+        self.payer.pubkey()
+    }
+
+    // store hash+cid to Solana returns the transaction signature plus where it landed
+    pub fn send_memo(&self, hash: &str, cid: &str) -> Result<MemoReceipt> {
+        self.post_memo(format_memo(hash, cid))
+    }
+
+    // posts a memo saying the anchor in original_signature no longer stands, the file was withdrawn
+    // from the archive. the original memo stays on chain, this one just points at it
+    pub fn send_revocation(&self, hash: &str, original_signature: &str) -> Result<MemoReceipt> {
+        self.post_memo(format_revocation(hash, original_signature))
+    }
 
-    println!("Requesting airdrop...");
+    // any memo text, signed by the payer
+    fn post_memo(&self, memo_text: String) -> Result<MemoReceipt> {
+        if self.airdrop {
+            self.top_up()?;
+        }
+
+        // Build memo instruction; listing the payer as a signer makes the memo program check
+        // the signature, so the memo is provably posted by the archive
+        let memo_ix = Instruction {
+            program_id: MEMO_PROGRAM_ID
+                .parse::<Pubkey>()
+                .map_err(|e| Error::Solana(format!("bad memo program id: {}", e)))?,
+            accounts: vec![AccountMeta::new_readonly(self.payer.pubkey(), true)],
+            data: memo_text.into_bytes(),
+        };
+
+        // Create & sign transaction
+        let recent_blockhash = self.rpc.get_latest_blockhash()
+            .map_err(|e| Error::Solana(format!("could not fetch a recent blockhash: {}", e)))?;
+        let tx = Transaction::new_signed_with_payer(
+            &[memo_ix],
+            Some(&self.payer.pubkey()),
+            &[&self.payer],
+            recent_blockhash,
+        );
+
+        // Send
+        let sig = self.rpc.send_and_confirm_transaction(&tx)
+            .map_err(|e| Error::Solana(format!("memo transaction failed: {}", e)))?;
+
+        // the memo is already on chain at this point, so a failed lookup only costs us slot/time
+        let (slot, block_time) = match self.fetch_memo(&sig.to_string()) {
+            Ok(onchain) => (Some(onchain.slot), onchain.block_time),
+            Err(e) => {
+                println!("Memo {} sent but its slot could not be fetched: {}", sig, e);
+                (None, None)
+            }
+        };
+
+        Ok(MemoReceipt { signature: sig.to_string(), slot, block_time })
+    }
 
-    // Poll until the account actually has lamports
-    for _ in 0..20 {
-        rpc.request_airdrop(&keypair.pubkey(), 1_000_000_000)?;
-        if let Ok(bal) = rpc.get_balance(&keypair.pubkey()) {
-            if bal > 0 {
+    // looks a memo transaction up on chain and pulls out the memo text and its signers
+    pub fn fetch_memo(&self, signature: &str) -> Result<OnChainMemo> {
+        let sig = signature
+            .parse::<Signature>()
+            .map_err(|e| Error::Solana(format!("'{}' is not a transaction signature: {}", signature, e)))?;
+
+        // getTransaction doesn't accept "processed"
+        let commitment = if self.commitment.is_at_least_confirmed() {
+            self.commitment
+        } else {
+            CommitmentConfig::confirmed()
+        };
+
+        let fetched = self.rpc
+            .get_transaction_with_config(&sig, RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(commitment),
+                max_supported_transaction_version: Some(0),
+            })
+            .map_err(|e| Error::Solana(format!("could not fetch transaction {}: {}", signature, e)))?;
+
+        let succeeded = fetched.transaction.meta.as_ref().map(|meta| meta.err.is_none()).unwrap_or(false);
+        let tx = fetched
+            .transaction
+            .transaction
+            .decode()
+            .ok_or_else(|| Error::Solana(format!("could not decode transaction {}", signature)))?;
+
+        let memo_program = MEMO_PROGRAM_ID
+            .parse::<Pubkey>()
+            .map_err(|e| Error::Solana(format!("bad memo program id: {}", e)))?;
+        let keys = tx.message.static_account_keys();
+
+        let mut memo = None;
+        let mut signers = Vec::new();
+        for ix in tx.message.instructions() {
+            if keys.get(ix.program_id_index as usize) != Some(&memo_program) {
+                continue;
+            }
+            memo = Some(String::from_utf8_lossy(&ix.data).to_string());
+            signers = ix
+                .accounts
+                .iter()
+                .map(|&i| i as usize)
+                .filter(|&i| tx.message.is_signer(i))
+                .filter_map(|i| keys.get(i).map(|key| key.to_string()))
+                .collect();
+            break;
+        }
+
+        Ok(OnChainMemo {
+            signature: signature.to_string(),
+            slot: fetched.slot,
+            block_time: fetched.block_time,
+            succeeded,
+            memo,
+            signers,
+        })
+    }
+
+    // local validator only: make sure the payer can cover the fee
+    fn top_up(&self) -> Result<()> {
+        let pubkey = self.payer.pubkey();
+        let balance = self.rpc.get_balance(&pubkey)
+            .map_err(|e| Error::Solana(format!("could not read payer balance: {}", e)))?;
+        if balance >= MIN_BALANCE_LAMPORTS {
+            return Ok(());
+        }
+
+        println!("Payer {} has {} lamports, requesting airdrop...", pubkey, balance);
+        self.rpc.request_airdrop(&pubkey, AIRDROP_LAMPORTS)
+            .map_err(|e| Error::Solana(format!("airdrop failed: {}", e)))?;
+
+        // Poll until the account actually has lamports
+        for _ in 0..20 {
+            if let Ok(bal) = self.rpc.get_balance(&pubkey)
+                && bal >= MIN_BALANCE_LAMPORTS
+            {
                 println!("Airdrop confirmed: {} lamports", bal);
-                break;
+                return Ok(());
             }
+            thread::sleep(Duration::from_millis(500));
         }
-        thread::sleep(Duration::from_secs(2));
+
+        Err(Error::Solana(format!("airdrop to {} never arrived", pubkey)))
     }
+}
 
 
+// the memo format every anchor uses
+pub fn format_memo(hash: &str, cid: &str) -> String {
+    format!("book_hash:{};ipfs_cid:{}", hash, cid)
+}
 
-    // 3. Compose memo text
-    let memo_text = format!("book_hash:{};ipfs_cid:{}", hash, cid);
+// the memo a revocation posts; parse_memo doesn't take it for an anchor
+pub fn format_revocation(hash: &str, original_signature: &str) -> String {
+    format!("revoke:{};book_hash:{}", original_signature, hash)
+}
 
-    // 4. Build memo instruction
-    let memo_ix = Instruction {
-        program_id: "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr"
-            .parse::<Pubkey>()?,
-        accounts: vec![],
-        data: memo_text.into_bytes(),
-    };
+// the inverse of format_memo: (hash, cid)
+pub fn parse_memo(memo: &str) -> Option<(String, String)> {
+    let mut hash = None;
+    let mut cid = None;
+    for part in memo.trim().split(';') {
+        match part.split_once(':') {
+            Some(("book_hash", value)) => hash = Some(value.to_string()),
+            Some(("ipfs_cid", value)) => cid = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    // an empty field is as good as a missing one
+    Some((hash.filter(|h| !h.is_empty())?, cid.filter(|c| !c.is_empty())?))
+}
+
+
+// helper functions
+fn create_keypair_file(path: &Path) -> Result<Keypair> {
+    let keypair = Keypair::new();
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    write_keypair_file(&keypair, path)
+        .map_err(|e| Error::Config(format!("could not write keypair {:?}: {}", path, e)))?;
+    println!("Created new archive keypair {} at {:?}", keypair.pubkey(), path);
+    Ok(keypair)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // 5. Create & sign transaction
-    let recent_blockhash = rpc.get_latest_blockhash()?;
-    let tx = Transaction::new_signed_with_payer(
-        &[memo_ix],
-        Some(&keypair.pubkey()),
-        &[&keypair],
-        recent_blockhash,
-    );
+    const HASH: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+    const CID: &str = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e";
 
-    // 6. Send
-    let sig = rpc.send_and_confirm_transaction(&tx)?;
-    Ok(sig.to_string()) // returns the transaction signature
-}
\ No newline at end of file
+    #[test]
+    fn memos_round_trip() {
+        let memo = format_memo(HASH, CID);
+        assert_eq!(memo, format!("book_hash:{};ipfs_cid:{}", HASH, CID));
+        assert_eq!(parse_memo(&memo), Some((HASH.to_string(), CID.to_string())));
+
+        // field order and surrounding whitespace don't matter, unknown fields are ignored
+        let reordered = format!("  ipfs_cid:{};note:x;book_hash:{}\n", CID, HASH);
+        assert_eq!(parse_memo(&reordered), Some((HASH.to_string(), CID.to_string())));
+    }
+
+    #[test]
+    fn revocations_are_not_anchors() {
+        let memo = format_revocation(HASH, "5sig");
+        assert_eq!(memo, format!("revoke:5sig;book_hash:{}", HASH));
+        assert_eq!(parse_memo(&memo), None);
+    }
+
+    #[test]
+    fn malformed_memos_parse_to_none() {
+        for memo in [
+            "",
+            "hello world",
+            &format!("book_hash:{}", HASH),
+            &format!("ipfs_cid:{}", CID),
+            &format!("book_hash {};ipfs_cid {}", HASH, CID),
+            &format!("book_hash:;ipfs_cid:{}", CID),
+            &format!("book_hash:{};ipfs_cid:", HASH),
+        ] {
+            assert_eq!(parse_memo(memo), None, "{:?}", memo);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+// cid.rs: works out a file's CIDv1 without a daemon, the same one `ipfs add --cid-version=1` prints
+// (256 KiB chunks, raw leaves, balanced dag-pb tree of up to 174 links per node)
+use sha2::{Digest, Sha256};
+use std::io::Read;
+
+
+// kubo's default chunker and the most links it puts in one node
+pub const CHUNK_SIZE: usize = 256 * 1024;
+pub const MAX_LINKS: usize = 174;
+
+// multicodec codes
+const RAW: u64 = 0x55;
+const DAG_PB: u64 = 0x70;
+const SHA2_256: u64 = 0x12;
+
+// the CIDv1 of a whole file, base32 encoded ("bafy..." or "bafk..." for files that fit in one chunk)
+pub fn cid_v1(bytes: &[u8]) -> String {
+    cid_v1_of_reader(bytes).expect("reading from memory doesn't fail")
+}
+
+// the same for a file read from disk (or anywhere), one chunk in memory at a time
+pub fn cid_v1_of_reader<R: Read>(mut reader: R) -> std::io::Result<String> {
+    let mut level: Vec<Node> = Vec::new();
+    loop {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        reader.by_ref().take(CHUNK_SIZE as u64).read_to_end(&mut chunk)?;
+        // a file of one chunk (an empty one too) is just that raw block
+        if level.is_empty() && chunk.len() < CHUNK_SIZE {
+            return Ok(encode_cid(RAW, &chunk));
+        }
+        if chunk.is_empty() {
+            break;
+        }
+        level.push(Node { cid: cid_bytes(RAW, &chunk), tsize: chunk.len() as u64, filesize: chunk.len() as u64 });
+    }
+    if level.len() == 1 {
+        return Ok(format!("b{}", base32(&level[0].cid)));
+    }
+
+    // every level groups the one below it, left to right, until a single root is left
+    loop {
+        level = level.chunks(MAX_LINKS).map(parent).collect();
+        if level.len() == 1 {
+            return Ok(format!("b{}", base32(&level[0].cid)));
+        }
+    }
+}
+
+// true for strings that at least look like a cid we could have handed out (v0 "Qm..." or base32 v1)
+pub fn looks_like_cid(cid: &str) -> bool {
+    let v0 = cid.len() == 46 && cid.starts_with("Qm") && cid.chars().all(|c| c.is_ascii_alphanumeric());
+    let v1 = cid.len() > 8 && cid.starts_with('b') && cid[1..].chars().all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c));
+    v0 || v1
+}
+
+
+// helper functions
+// one block of the tree as its parent links to it
+struct Node {
+    cid: Vec<u8>,
+    tsize: u64,    // every encoded byte under the link, the block itself included
+    filesize: u64, // file bytes under the link
+}
+
+// a dag-pb node linking to `children`, its unixfs data saying how much of the file each one holds
+fn parent(children: &[Node]) -> Node {
+    let mut unixfs = Vec::new();
+    put_varint_field(&mut unixfs, 1, 2); // Type = File
+    let filesize: u64 = children.iter().map(|child| child.filesize).sum();
+    put_varint_field(&mut unixfs, 3, filesize);
+    for child in children {
+        put_varint_field(&mut unixfs, 4, child.filesize); // blocksizes
+    }
+
+    // dag-pb puts the links (field 2) before the data (field 1)
+    let mut block = Vec::new();
+    for child in children {
+        let mut link = Vec::new();
+        put_bytes_field(&mut link, 1, &child.cid); // Hash
+        put_bytes_field(&mut link, 2, b""); // Name, empty but always written by kubo
+        put_varint_field(&mut link, 3, child.tsize); // Tsize
+        put_bytes_field(&mut block, 2, &link);
+    }
+    put_bytes_field(&mut block, 1, &unixfs);
+
+    let tsize = block.len() as u64 + children.iter().map(|child| child.tsize).sum::<u64>();
+    Node { cid: cid_bytes(DAG_PB, &block), tsize, filesize }
+}
+
+fn encode_cid(codec: u64, block: &[u8]) -> String {
+    format!("b{}", base32(&cid_bytes(codec, block)))
+}
+
+// <version 1><codec><sha2-256 multihash of the block>
+fn cid_bytes(codec: u64, block: &[u8]) -> Vec<u8> {
+    let digest = Sha256::digest(block);
+    let mut cid = Vec::with_capacity(4 + digest.len());
+    put_varint(&mut cid, 1);
+    put_varint(&mut cid, codec);
+    put_varint(&mut cid, SHA2_256);
+    put_varint(&mut cid, digest.len() as u64);
+    cid.extend_from_slice(&digest);
+    cid
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// protobuf: wire type 0 for numbers, 2 for anything length delimited
+fn put_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(out, field << 3);
+    put_varint(out, value);
+}
+
+fn put_bytes_field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(out, (field << 3) | 2);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+// rfc 4648 base32, lowercase and unpadded, as multibase "b" wants it
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut out = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what `ipfs add --cid-version=1` prints for these
+    const EMPTY_CID: &str = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+    const HELLO_WORLD_CID: &str = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e";
+
+    #[test]
+    fn empty_file_is_an_empty_raw_block() {
+        assert_eq!(cid_v1(b""), EMPTY_CID);
+    }
+
+    #[test]
+    fn one_chunk_is_a_raw_block() {
+        assert_eq!(cid_v1(b"hello world"), HELLO_WORLD_CID);
+
+        // right up to the chunk size it stays a single raw block
+        let full = vec![7u8; CHUNK_SIZE];
+        assert!(cid_v1(&full).starts_with("bafkrei"));
+        assert_eq!(cid_v1(&full), encode_cid(RAW, &full));
+    }
+
+    #[test]
+    fn several_chunks_get_a_dag_pb_root() {
+        let mut bytes = vec![b'a'; CHUNK_SIZE];
+        bytes.extend_from_slice(b"bbbbbbbbbb");
+        let leaves = [cid_bytes(RAW, &bytes[..CHUNK_SIZE]), cid_bytes(RAW, &bytes[CHUNK_SIZE..])];
+
+        // the root as the dag-pb and unixfs specs lay it out, written out byte by byte:
+        // PBLink { Hash, Name: "", Tsize } per leaf, then PBNode.Data with the unixfs file
+        let mut root = Vec::new();
+        for (leaf, tsize) in leaves.iter().zip([&[0x80, 0x80, 0x10][..], &[0x0a][..]]) {
+            let mut link = vec![0x0a, leaf.len() as u8];
+            link.extend_from_slice(leaf);
+            link.extend_from_slice(&[0x12, 0x00, 0x18]);
+            link.extend_from_slice(tsize);
+            root.extend_from_slice(&[0x12, link.len() as u8]);
+            root.extend_from_slice(&link);
+        }
+        // Type = File, filesize = 262154, blocksizes = [262144, 10]
+        let data = [0x08, 0x02, 0x18, 0x8a, 0x80, 0x10, 0x20, 0x80, 0x80, 0x10, 0x20, 0x0a];
+        root.extend_from_slice(&[0x0a, data.len() as u8]);
+        root.extend_from_slice(&data);
+
+        let cid = cid_v1(&bytes);
+        assert!(cid.starts_with("bafybei"));
+        assert_eq!(cid, encode_cid(DAG_PB, &root));
+    }
+
+    // a reader that never hands over more than a few bytes at a time, like a slow disk or socket
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(1000);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn reading_in_pieces_gives_the_same_cid() {
+        for len in [0, 11, CHUNK_SIZE, CHUNK_SIZE + 1, CHUNK_SIZE * 3 + 5] {
+            let bytes: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            assert_eq!(cid_v1_of_reader(Trickle(&bytes)).unwrap(), cid_v1(&bytes), "{} bytes", len);
+        }
+        assert_eq!(cid_v1_of_reader(Trickle(b"hello world")).unwrap(), HELLO_WORLD_CID);
+    }
+
+    #[test]
+    fn a_full_node_gets_another_level() {
+        // 175 chunks don't fit under one node, so the root links to two others
+        let leaves: Vec<Node> = (0..MAX_LINKS + 1)
+            .map(|i| Node { cid: cid_bytes(RAW, &[i as u8]), tsize: 1, filesize: 1 })
+            .collect();
+        let level: Vec<Node> = leaves.chunks(MAX_LINKS).map(parent).collect();
+        assert_eq!(level.len(), 2);
+        assert_eq!(level[0].filesize, MAX_LINKS as u64);
+        assert_eq!(level[1].filesize, 1);
+
+        let root = parent(&level);
+        assert_eq!(root.filesize, MAX_LINKS as u64 + 1);
+        assert!(root.tsize > root.filesize);
+    }
+
+    #[test]
+    fn base32_matches_rfc_4648() {
+        assert_eq!(base32(b""), "");
+        assert_eq!(base32(b"f"), "my");
+        assert_eq!(base32(b"foobar"), "mzxw6ytboi");
+    }
+
+    #[test]
+    fn recognises_cids() {
+        assert!(looks_like_cid(EMPTY_CID));
+        assert!(looks_like_cid("QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR"));
+        assert!(!looks_like_cid("../../etc/passwd"));
+        assert!(!looks_like_cid("bafy"));
+    }
+}
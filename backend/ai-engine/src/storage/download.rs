@@ -0,0 +1,474 @@
+// download.rs: hands an archived file back out: the bytes, checked against the hash on the record,
+// and what the response headers need to say about them
+use futures::stream::{self, StreamExt};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::database::database::ArchivedFile;
+use crate::error::{Error, Result};
+use crate::extract::extract::{sniff_file_format, DocumentFormat};
+use crate::hash::Sha256Stream;
+use crate::storage::store::{ContentStore, ContentStream, READ_CHUNK};
+
+
+// for files whose format can't be worked out (nothing we archive, but the header needs something)
+const FALLBACK_CONTENT_TYPE: &str = "application/octet-stream";
+
+// part of a file, both ends included, as a Range header counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    // the Content-Range value for this part of a `total` byte file
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+// what to answer a request with, going by its Range and If-Range headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeRequest {
+    Full,
+    Partial(ByteRange),
+    Unsatisfiable, // 416, Content-Range: bytes */total
+}
+
+// a verified copy of an archived file in a temp file, so a big one is never held in memory. the
+// temp file is removed when the last handle to it is dropped: when the cache lets go of it and
+// every response reading it has been sent
+pub struct SpooledFile {
+    path: PathBuf,
+    size: u64,
+}
+
+impl SpooledFile {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    // the bytes in `range`, or all of them, read off disk as they're sent
+    pub async fn into_stream(self: Arc<Self>, range: Option<ByteRange>) -> Result<ContentStream> {
+        let (start, len) = match range {
+            Some(range) => (range.start, range.end - range.start + 1),
+            None => (0, self.size),
+        };
+        let mut file = tokio::fs::File::open(&self.path).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+
+        // the spool rides along with the reader so the temp file outlives the last read
+        Ok(stream::unfold(Some((file.take(len), self)), |state| async move {
+            let (mut reader, spool) = state?;
+            let mut chunk = vec![0; READ_CHUNK];
+            match reader.read(&mut chunk).await {
+                Ok(0) => None,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    Some((Ok(chunk), Some((reader, spool))))
+                }
+                Err(e) => Some((Err(Error::Io(e)), None)),
+            }
+        })
+        .boxed())
+    }
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// the file the store holds under the record's cid, hashed as it streams into a spool file and
+// refused unless it hashes to what was archived; a store (or a gateway behind it) handing out other
+// bytes is the store's fault, not the client's
+pub async fn fetch_verified(store: &dyn ContentStore, file: &ArchivedFile) -> Result<SpooledFile> {
+    let mut pieces = store.get_stream(&file.file_cid).await?;
+
+    // created before anything is written, so an error part way through still cleans up
+    let mut spool = SpooledFile { path: std::env::temp_dir().join(format!("download-{}.spool", Uuid::new_v4())), size: 0 };
+    let mut out = tokio::fs::File::create(&spool.path).await?;
+    let mut hasher = Sha256Stream::default();
+    while let Some(piece) = pieces.next().await {
+        let piece = piece?;
+        hasher.update(&piece);
+        out.write_all(&piece).await?;
+        spool.size += piece.len() as u64;
+    }
+    out.flush().await?;
+
+    let file_hash = hasher.finish();
+    if file_hash != file.file_hash {
+        return Err(Error::Ipfs(format!(
+            "the content store returned a file hashing to {} for {}, record {} was archived as {}",
+            file_hash, file.file_cid, file.record_id, file.file_hash
+        )));
+    }
+    Ok(spool)
+}
+
+// verified spools kept by cid, so a file is fetched and hashed once and the requests after it (every
+// Range request of a pdf viewer, say) are read straight off disk. the least recently used go once
+// the spools add up to more than max_bytes; a file bigger than that on its own is never kept
+pub struct DownloadCache {
+    max_bytes: u64,
+    entries: Mutex<CachedSpools>,
+}
+
+#[derive(Default)]
+struct CachedSpools {
+    spools: HashMap<String, CachedSpool>, // cid -> spool
+    bytes: u64,
+    clock: u64, // bumped on every use, for the eviction order
+}
+
+struct CachedSpool {
+    file_hash: String,
+    spool: Arc<SpooledFile>,
+    last_used: u64,
+}
+
+impl DownloadCache {
+    pub fn new(max_bytes: u64) -> Self {
+        DownloadCache { max_bytes, entries: Mutex::new(CachedSpools::default()) }
+    }
+
+    // fetch_verified, unless an earlier request already did. two requests for a file nobody has
+    // asked for yet may both fetch it; the second copy replaces the first
+    pub async fn fetch(&self, store: &dyn ContentStore, file: &ArchivedFile) -> Result<Arc<SpooledFile>> {
+        if let Some(spool) = self.lookup(file) {
+            return Ok(spool);
+        }
+        let spool = Arc::new(fetch_verified(store, file).await?);
+        self.keep(file, spool.clone());
+        Ok(spool)
+    }
+
+    // what the cache holds, in bytes
+    pub fn size(&self) -> u64 {
+        self.lock().bytes
+    }
+
+    fn lookup(&self, file: &ArchivedFile) -> Option<Arc<SpooledFile>> {
+        let mut entries = self.lock();
+        entries.clock += 1;
+        let clock = entries.clock;
+        let cached = entries.spools.get_mut(&file.file_cid)?;
+        // a temp directory cleaner may have been at it
+        if cached.file_hash != file.file_hash || !cached.spool.path.exists() {
+            let stale = entries.spools.remove(&file.file_cid)?;
+            entries.bytes -= stale.spool.size;
+            return None;
+        }
+        cached.last_used = clock;
+        Some(cached.spool.clone())
+    }
+
+    fn keep(&self, file: &ArchivedFile, spool: Arc<SpooledFile>) {
+        if spool.size > self.max_bytes {
+            return;
+        }
+        let mut entries = self.lock();
+        entries.clock += 1;
+        entries.bytes += spool.size;
+        let cached = CachedSpool { file_hash: file.file_hash.clone(), spool, last_used: entries.clock };
+        if let Some(replaced) = entries.spools.insert(file.file_cid.clone(), cached) {
+            entries.bytes -= replaced.spool.size;
+        }
+
+        while entries.bytes > self.max_bytes {
+            let Some(oldest) = entries.spools.iter().min_by_key(|(_, cached)| cached.last_used).map(|(cid, _)| cid.clone())
+            else {
+                break;
+            };
+            if let Some(evicted) = entries.spools.remove(&oldest) {
+                entries.bytes -= evicted.spool.size;
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CachedSpools> {
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// the sha-256 of what the store hands out for a cid, worked out a piece at a time
+pub async fn stored_sha256(store: &dyn ContentStore, cid: &str) -> Result<String> {
+    let mut pieces = store.get_stream(cid).await?;
+    let mut hasher = Sha256Stream::default();
+    while let Some(piece) = pieces.next().await {
+        hasher.update(&piece?);
+    }
+    Ok(hasher.finish())
+}
+
+// the format noted when the text was extracted; records archived before the text was kept get
+// their spooled copy sniffed
+pub async fn content_format(file: &ArchivedFile, spool: &SpooledFile) -> Option<DocumentFormat> {
+    if let Some(format) = file.format.as_deref().and_then(DocumentFormat::from_name) {
+        return Some(format);
+    }
+    let (path, name) = (spool.path.clone(), file.original_filename.clone());
+    tokio::task::spawn_blocking(move || sniff_file_format(&path, name.as_deref()).ok())
+        .await
+        .ok()
+        .flatten()
+}
+
+pub fn content_type(format: Option<DocumentFormat>) -> &'static str {
+    format.map(|format| format.mime_type()).unwrap_or(FALLBACK_CONTENT_TYPE)
+}
+
+// the name the file is offered under: what it was uploaded as, minus any folder it had in a zip,
+// or else its cid with the format's extension
+pub fn download_filename(file: &ArchivedFile, format: Option<DocumentFormat>) -> String {
+    let uploaded = file
+        .original_filename
+        .as_deref()
+        .and_then(|name| Path::new(name).file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .filter(|name| !name.trim().is_empty());
+
+    match (uploaded, format) {
+        (Some(name), _) => name,
+        (None, Some(format)) => format!("{}.{}", file.file_cid, extension(format)),
+        (None, None) => file.file_cid.clone(),
+    }
+}
+
+// a strong etag: the bytes under a cid never change, and neither does their hash
+pub fn etag(file: &ArchivedFile) -> String {
+    format!("\"{}\"", file.file_hash)
+}
+
+// whether an If-None-Match header already names this file ("*", or any of its tags, weak or not)
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+// works out which bytes a request wants. only a single range is served: a list of them, another
+// unit or a header that doesn't parse gets the whole file, which http allows. so does an If-Range
+// naming another version of the file
+pub fn parse_range(range: Option<&str>, if_range: Option<&str>, etag: &str, total: u64) -> RangeRequest {
+    let Some(spec) = range.and_then(|range| range.trim().strip_prefix("bytes=")) else {
+        return RangeRequest::Full;
+    };
+    if if_range.is_some_and(|tag| tag.trim() != etag) || spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+
+    let range = match (start.trim(), end.trim()) {
+        // the last `end` bytes
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return RangeRequest::Unsatisfiable,
+            Ok(suffix) if total > 0 => ByteRange { start: total.saturating_sub(suffix), end: total - 1 },
+            Ok(_) => return RangeRequest::Unsatisfiable,
+            Err(_) => return RangeRequest::Full,
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            let end = match end {
+                "" => u64::MAX,
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return RangeRequest::Full,
+                },
+            };
+            if start >= total {
+                return RangeRequest::Unsatisfiable;
+            }
+            ByteRange { start, end: end.min(total - 1) }
+        }
+    };
+    RangeRequest::Partial(range)
+}
+
+
+// helper functions
+fn extension(format: DocumentFormat) -> &'static str {
+    match format {
+        DocumentFormat::Markdown => "md",
+        other => other.as_str(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::compute_sha256;
+    use crate::storage::store::MemoryStore;
+
+    const TAG: &str = "\"abc\"";
+
+    fn range(header: &str) -> RangeRequest {
+        parse_range(Some(header), None, TAG, 1000)
+    }
+
+    fn archived(bytes: &[u8], cid: &str, format: Option<&str>) -> ArchivedFile {
+        ArchivedFile {
+            record_id: 1,
+            file_hash: compute_sha256(bytes),
+            file_cid: cid.to_string(),
+            original_filename: Some("notes.txt".to_string()),
+            format: format.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn parses_closed_and_open_ended_ranges() {
+        assert_eq!(range("bytes=0-99"), RangeRequest::Partial(ByteRange { start: 0, end: 99 }));
+        assert_eq!(range("bytes=900-"), RangeRequest::Partial(ByteRange { start: 900, end: 999 }));
+        // an end past the file is cut to it
+        assert_eq!(range("bytes=500-5000"), RangeRequest::Partial(ByteRange { start: 500, end: 999 }));
+        assert_eq!(ByteRange { start: 0, end: 99 }.content_range(1000), "bytes 0-99/1000");
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(range("bytes=-100"), RangeRequest::Partial(ByteRange { start: 900, end: 999 }));
+        // more than the whole file is the whole file
+        assert_eq!(range("bytes=-5000"), RangeRequest::Partial(ByteRange { start: 0, end: 999 }));
+        assert_eq!(range("bytes=-0"), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-10"), None, TAG, 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn serves_the_whole_file_for_lists_and_nonsense() {
+        assert_eq!(range("bytes=0-9,20-29"), RangeRequest::Full);
+        assert_eq!(range("items=0-9"), RangeRequest::Full);
+        assert_eq!(range("bytes=abc"), RangeRequest::Full);
+        assert_eq!(range("bytes=9-0"), RangeRequest::Full);
+        assert_eq!(parse_range(None, None, TAG, 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_unsatisfiable() {
+        assert_eq!(range("bytes=1000-"), RangeRequest::Unsatisfiable);
+        assert_eq!(range("bytes=2000-2999"), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn if_range_must_name_this_file() {
+        let partial = RangeRequest::Partial(ByteRange { start: 0, end: 9 });
+        assert_eq!(parse_range(Some("bytes=0-9"), Some(TAG), TAG, 1000), partial);
+        assert_eq!(parse_range(Some("bytes=0-9"), Some("\"other\""), TAG, 1000), RangeRequest::Full);
+        assert_eq!(parse_range(Some("bytes=0-9"), Some("Tue, 15 Nov 1994 08:12:31 GMT"), TAG, 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn matches_etags() {
+        assert!(etag_matches("*", TAG));
+        assert!(etag_matches("\"x\", W/\"abc\"", TAG));
+        assert!(!etag_matches("\"x\"", TAG));
+    }
+
+    #[tokio::test]
+    async fn spools_and_streams_verified_bytes() {
+        let store = MemoryStore::default();
+        let bytes: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let cid = store.put(bytes.clone(), "notes.txt").await.unwrap();
+        let file = archived(&bytes, &cid, Some("txt"));
+
+        let spool = fetch_verified(&store, &file).await.unwrap();
+        assert_eq!(spool.size(), bytes.len() as u64);
+        assert_eq!(content_format(&file, &spool).await, Some(DocumentFormat::Txt));
+
+        let path = spool.path.clone();
+        let mut sent = Vec::new();
+        let mut body = Arc::new(spool).into_stream(Some(ByteRange { start: 70_000, end: 140_000 })).await.unwrap();
+        while let Some(piece) = body.next().await {
+            sent.extend(piece.unwrap());
+        }
+        assert_eq!(sent, &bytes[70_000..=140_000]);
+
+        // the temp file goes with the finished body
+        drop(body);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn refuses_bytes_that_hash_differently() {
+        let store = MemoryStore::default();
+        let cid = store.put(b"what the store has".to_vec(), "a.txt").await.unwrap();
+        let file = archived(b"what was archived", &cid, None);
+
+        assert!(matches!(fetch_verified(&store, &file).await, Err(Error::Ipfs(_))));
+    }
+
+    #[tokio::test]
+    async fn sniffs_records_archived_without_a_format() {
+        let store = MemoryStore::default();
+        let bytes = b"%PDF-1.4 not much of a pdf".to_vec();
+        let cid = store.put(bytes.clone(), "a.pdf").await.unwrap();
+        let file = archived(&bytes, &cid, None);
+
+        let spool = fetch_verified(&store, &file).await.unwrap();
+        assert_eq!(content_format(&file, &spool).await, Some(DocumentFormat::Pdf));
+    }
+
+    #[tokio::test]
+    async fn the_cache_verifies_each_file_once() {
+        let store = MemoryStore::default();
+        let cid = store.put(b"range after range".to_vec(), "a.txt").await.unwrap();
+        let file = archived(b"range after range", &cid, None);
+        let cache = DownloadCache::new(1024);
+
+        let first = cache.fetch(&store, &file).await.unwrap();
+        // the store isn't asked again
+        store.unpin(&cid).await.unwrap();
+        let second = cache.fetch(&store, &file).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.size(), 17);
+
+        // a record claiming other bytes under the same cid doesn't get the cached copy
+        let other = archived(b"something else", &cid, None);
+        assert!(matches!(cache.fetch(&store, &other).await, Err(Error::NotFound(_))));
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[tokio::test]
+    async fn the_cache_lets_the_least_recently_used_go() {
+        let store = MemoryStore::default();
+        let mut files = Vec::new();
+        for fill in [b'a', b'b', b'c', b'd'] {
+            let bytes = vec![fill; if fill == b'd' { 300 } else { 100 }];
+            let cid = store.put(bytes.clone(), "f.txt").await.unwrap();
+            files.push(archived(&bytes, &cid, None));
+        }
+        let cache = DownloadCache::new(250);
+
+        let a = cache.fetch(&store, &files[0]).await.unwrap();
+        let b_path = cache.fetch(&store, &files[1]).await.unwrap().path.clone();
+        cache.fetch(&store, &files[0]).await.unwrap();
+        cache.fetch(&store, &files[2]).await.unwrap();
+        assert_eq!(cache.size(), 200);
+        // nothing holds b's spool any more, so its file went with it
+        assert!(!b_path.exists());
+
+        // too big to keep at all
+        let d = cache.fetch(&store, &files[3]).await.unwrap();
+        assert_eq!((d.size(), cache.size()), (300, 200));
+
+        for file in &files {
+            store.unpin(&file.file_cid).await.unwrap();
+        }
+        assert!(Arc::ptr_eq(&a, &cache.fetch(&store, &files[0]).await.unwrap()));
+        assert!(cache.fetch(&store, &files[2]).await.is_ok());
+        assert!(matches!(cache.fetch(&store, &files[1]).await, Err(Error::NotFound(_))));
+    }
+}
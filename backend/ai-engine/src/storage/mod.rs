@@ -0,0 +1,3 @@
+pub mod cid;
+pub mod download;
+pub mod store;
@@ -0,0 +1,598 @@
+// store.rs: where archived files live, addressed by cid: a kubo daemon, a directory, or memory
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use reqwest::Client;
+use reqwest::multipart::{Form, Part};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use dotenv::dotenv;
+use tokio::io::AsyncReadExt;
+
+use crate::config::env_u64;
+use crate::error::{Error, Result};
+use crate::storage::cid::{cid_v1, cid_v1_of_reader, looks_like_cid};
+
+
+// how much of a file get_stream hands over at a time
+pub(crate) const READ_CHUNK: usize = 64 * 1024;
+
+// a stored file as it's read, piece by piece
+pub type ContentStream = BoxStream<'static, Result<Vec<u8>>>;
+
+// anything that can keep files by content address. put pins what it stores, like `ipfs add` does;
+// putting the same bytes twice gives the same cid
+#[async_trait]
+pub trait ContentStore: Send + Sync {
+    async fn put(&self, bytes: Vec<u8>, filename: &str) -> Result<String>;
+
+    // put for a file on disk, streamed from it so a big upload is never in memory whole
+    async fn put_file(&self, path: &Path, filename: &str) -> Result<String>;
+
+    // Error::NotFound when the store doesn't hold the cid
+    async fn get(&self, cid: &str) -> Result<Vec<u8>>;
+
+    // the same bytes as get without holding them all at once; errors the same way before the
+    // first piece, and the stream fails if the store does part way through
+    async fn get_stream(&self, cid: &str) -> Result<ContentStream>;
+
+    async fn pin(&self, cid: &str) -> Result<()>;
+
+    // content nothing pins any more may be dropped
+    async fn unpin(&self, cid: &str) -> Result<()>;
+
+    // None when the store doesn't hold the cid; never goes to the network for it
+    async fn stat(&self, cid: &str) -> Result<Option<ContentStat>>;
+
+    // short label for logs and /health style output
+    fn name(&self) -> String;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContentStat {
+    pub cid: String,
+    pub size: u64,
+    pub pinned: bool,
+}
+
+// which store the server should use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentStoreKind {
+    Kubo,
+    Local,
+    Memory,
+}
+
+// where files go, read from the environment (.env works too)
+//
+//   CONTENT_STORE       kubo | local | memory   (default: kubo)
+//   IPFS_API_URL        kubo's rpc api (default http://127.0.0.1:5001)
+//   CONTENT_STORE_PATH  directory the local store keeps files in (default ./content)
+//   DOWNLOAD_CACHE_MB   verified copies of downloaded files kept on disk, 0 turns it off (default 1024)
+#[derive(Debug, Clone)]
+pub struct ContentStoreConfig {
+    pub kind: ContentStoreKind,
+    pub ipfs_api_url: String,
+    pub path: PathBuf,
+    pub download_cache_bytes: u64,
+}
+
+impl ContentStoreConfig {
+    pub fn from_env() -> Result<Self> {
+        // load the dotenv variables
+        dotenv().ok();
+
+        let kind = match env::var("CONTENT_STORE").as_deref().map(str::trim) {
+            Err(_) | Ok("kubo") | Ok("ipfs") => ContentStoreKind::Kubo,
+            Ok("local") | Ok("fs") => ContentStoreKind::Local,
+            Ok("memory") => ContentStoreKind::Memory,
+            Ok(other) => return Err(Error::Config(format!("unknown CONTENT_STORE '{}'", other))),
+        };
+        let ipfs_api_url = env::var("IPFS_API_URL")
+            .unwrap_or_else(|_| "http://127.0.0.1:5001".to_string())
+            .trim_end_matches('/')
+            .to_string();
+        let path = PathBuf::from(env::var("CONTENT_STORE_PATH").unwrap_or_else(|_| "content".to_string()));
+        let download_cache_bytes = env_u64("DOWNLOAD_CACHE_MB", 1024, 0)?.saturating_mul(1024 * 1024);
+
+        Ok(ContentStoreConfig { kind, ipfs_api_url, path, download_cache_bytes })
+    }
+}
+
+// picks the implementation for the configured store
+pub fn build_content_store(config: &ContentStoreConfig) -> Result<Arc<dyn ContentStore>> {
+    Ok(match config.kind {
+        ContentStoreKind::Kubo => Arc::new(KuboStore::new(&config.ipfs_api_url)),
+        ContentStoreKind::Local => Arc::new(LocalStore::open(&config.path)?),
+        ContentStoreKind::Memory => Arc::new(MemoryStore::default()),
+    })
+}
+
+
+// what /api/v0/add is called with. cid-version=1 turns on raw leaves too, so kubo hands out the
+// same cid for a file as cid_v1 and the other stores do
+const KUBO_ADD_OPTIONS: &[(&str, &str)] = &[("cid-version", "1"), ("pin", "true")];
+
+// a kubo (go-ipfs) daemon over its http rpc api
+pub struct KuboStore {
+    client: Client,
+    api_url: String,
+}
+
+impl KuboStore {
+    pub fn new(api_url: &str) -> Self {
+        KuboStore { client: Client::new(), api_url: api_url.trim_end_matches('/').to_string() }
+    }
+
+    // every rpc call is a POST; a non 2xx answer becomes an error carrying kubo's message
+    async fn call(&self, command: &str, query: &[(&str, &str)]) -> Result<reqwest::Response> {
+        let resp = self
+            .client
+            .post(format!("{}/api/v0/{}", self.api_url, command))
+            .query(query)
+            .send()
+            .await
+            .map_err(|e| Error::Ipfs(format!("could not reach the ipfs daemon: {}", e)))?;
+        check_status(command, resp).await
+    }
+
+    // /api/v0/add with one file part; kubo answers with the cid under "Hash"
+    async fn add(&self, form: Form) -> Result<String> {
+        // send request to the ipfs daemon
+        let resp = self
+            .client
+            .post(format!("{}/api/v0/add", self.api_url))
+            .query(KUBO_ADD_OPTIONS)
+            .multipart(form)
+            .send()
+            .await
+            .map_err(|e| Error::Ipfs(format!("could not reach the ipfs daemon: {}", e)))?;
+        let resp_text = check_status("add", resp)
+            .await?
+            .text()
+            .await
+            .map_err(|e| Error::Ipfs(format!("could not read the ipfs response: {}", e)))?;
+
+        // parse JSON and return "Hash"
+        let v: Value = serde_json::from_str(&resp_text)
+            .map_err(|e| Error::Ipfs(format!("ipfs response is not json: {}", e)))?;
+        let cid = v
+            .get("Hash")
+            .and_then(|h| h.as_str())
+            .ok_or_else(|| Error::Ipfs("ipfs response missing 'Hash' field".to_string()))?;
+        Ok(cid.to_string())
+    }
+}
+
+#[async_trait]
+impl ContentStore for KuboStore {
+    async fn put(&self, bytes: Vec<u8>, filename: &str) -> Result<String> {
+        // build multipart form
+        let part = Part::bytes(bytes).file_name(filename.to_string());
+        self.add(Form::new().part("file", part)).await
+    }
+
+    async fn put_file(&self, path: &Path, filename: &str) -> Result<String> {
+        let file = tokio::fs::File::open(path).await?;
+        let size = file.metadata().await?.len();
+        let body = reqwest::Body::wrap_stream(file_chunks(file));
+        let part = Part::stream_with_length(body, size).file_name(filename.to_string());
+        self.add(Form::new().part("file", part)).await
+    }
+
+    async fn get(&self, cid: &str) -> Result<Vec<u8>> {
+        let bytes = self
+            .call("cat", &[("arg", cid)])
+            .await?
+            .bytes()
+            .await
+            .map_err(|e| Error::Ipfs(format!("could not read {} from ipfs: {}", cid, e)))?;
+        Ok(bytes.to_vec())
+    }
+
+    async fn get_stream(&self, cid: &str) -> Result<ContentStream> {
+        let resp = self.call("cat", &[("arg", cid)]).await?;
+        let cid = cid.to_string();
+        Ok(stream::unfold(Some(resp), move |resp| {
+            let cid = cid.clone();
+            async move {
+                let mut resp = resp?;
+                match resp.chunk().await {
+                    Ok(Some(chunk)) => Some((Ok(chunk.to_vec()), Some(resp))),
+                    Ok(None) => None,
+                    Err(e) => Some((Err(Error::Ipfs(format!("could not read {} from ipfs: {}", cid, e))), None)),
+                }
+            }
+        })
+        .boxed())
+    }
+
+    async fn pin(&self, cid: &str) -> Result<()> {
+        self.call("pin/add", &[("arg", cid)]).await?;
+        Ok(())
+    }
+
+    async fn unpin(&self, cid: &str) -> Result<()> {
+        self.call("pin/rm", &[("arg", cid)]).await?;
+        Ok(())
+    }
+
+    async fn stat(&self, cid: &str) -> Result<Option<ContentStat>> {
+        // offline, so a cid the node doesn't have is an answer instead of a network search
+        let path = format!("/ipfs/{}", cid);
+        let stat: Value = match self.call("files/stat", &[("arg", &path), ("offline", "true")]).await {
+            Ok(resp) => resp
+                .json()
+                .await
+                .map_err(|e| Error::Ipfs(format!("ipfs response is not json: {}", e)))?,
+            Err(Error::NotFound(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let size = stat.get("Size").and_then(Value::as_u64).unwrap_or(0);
+
+        let pinned = match self.call("pin/ls", &[("arg", cid), ("type", "recursive"), ("offline", "true")]).await {
+            Ok(_) => true,
+            Err(Error::NotFound(_)) => false,
+            Err(e) => return Err(e),
+        };
+        Ok(Some(ContentStat { cid: cid.to_string(), size, pinned }))
+    }
+
+    fn name(&self) -> String {
+        format!("kubo ({})", self.api_url)
+    }
+}
+
+
+// a plain directory: every file under its cid in blobs/, and an empty marker in pins/ while it's
+// pinned. the cids are the ones kubo would give the same bytes with --cid-version=1, so the files
+// can be moved onto ipfs later without changing a record
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(root.join("blobs"))?;
+        std::fs::create_dir_all(root.join("pins"))?;
+        Ok(LocalStore { root })
+    }
+
+    // cids are checked before they're used as file names, so one can't point outside the store
+    fn blob_path(&self, cid: &str) -> Result<PathBuf> {
+        checked_cid(cid)?;
+        Ok(self.root.join("blobs").join(cid))
+    }
+
+    fn pin_path(&self, cid: &str) -> Result<PathBuf> {
+        checked_cid(cid)?;
+        Ok(self.root.join("pins").join(cid))
+    }
+}
+
+#[async_trait]
+impl ContentStore for LocalStore {
+    async fn put(&self, bytes: Vec<u8>, _filename: &str) -> Result<String> {
+        let (cid, bytes) = tokio::task::spawn_blocking(move || (cid_v1(&bytes), bytes))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(format!("cid task failed: {}", e))))?;
+
+        // written next to its final name first, so a crash never leaves half a file under a cid
+        let blob = self.blob_path(&cid)?;
+        if !tokio::fs::try_exists(&blob).await? {
+            let partial = blob.with_extension("partial");
+            tokio::fs::write(&partial, &bytes).await?;
+            tokio::fs::rename(&partial, &blob).await?;
+        }
+        tokio::fs::write(self.pin_path(&cid)?, b"").await?;
+        Ok(cid)
+    }
+
+    // the cid is worked out a chunk at a time, then the file is copied in under it
+    async fn put_file(&self, path: &Path, _filename: &str) -> Result<String> {
+        let source = path.to_path_buf();
+        let cid = tokio::task::spawn_blocking(move || cid_v1_of_reader(std::io::BufReader::new(std::fs::File::open(source)?)))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(format!("cid task failed: {}", e))))??;
+
+        let blob = self.blob_path(&cid)?;
+        if !tokio::fs::try_exists(&blob).await? {
+            let partial = blob.with_extension("partial");
+            tokio::fs::copy(path, &partial).await?;
+            tokio::fs::rename(&partial, &blob).await?;
+        }
+        tokio::fs::write(self.pin_path(&cid)?, b"").await?;
+        Ok(cid)
+    }
+
+    async fn get(&self, cid: &str) -> Result<Vec<u8>> {
+        match tokio::fs::read(self.blob_path(cid)?).await {
+            Ok(bytes) => Ok(bytes),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(not_stored(cid)),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    async fn get_stream(&self, cid: &str) -> Result<ContentStream> {
+        let file = match tokio::fs::File::open(self.blob_path(cid)?).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(not_stored(cid)),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        Ok(file_chunks(file))
+    }
+
+    async fn pin(&self, cid: &str) -> Result<()> {
+        if !tokio::fs::try_exists(self.blob_path(cid)?).await? {
+            return Err(not_stored(cid));
+        }
+        tokio::fs::write(self.pin_path(cid)?, b"").await?;
+        Ok(())
+    }
+
+    // there's no garbage collector here, so unpinned content goes straight away
+    async fn unpin(&self, cid: &str) -> Result<()> {
+        let (blob, pin) = (self.blob_path(cid)?, self.pin_path(cid)?);
+        if !tokio::fs::try_exists(&blob).await? {
+            return Err(not_stored(cid));
+        }
+        let _ = tokio::fs::remove_file(pin).await;
+        tokio::fs::remove_file(blob).await?;
+        Ok(())
+    }
+
+    async fn stat(&self, cid: &str) -> Result<Option<ContentStat>> {
+        let metadata = match tokio::fs::metadata(self.blob_path(cid)?).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        let pinned = tokio::fs::try_exists(self.pin_path(cid)?).await?;
+        Ok(Some(ContentStat { cid: cid.to_string(), size: metadata.len(), pinned }))
+    }
+
+    fn name(&self) -> String {
+        format!("local ({})", self.root.display())
+    }
+}
+
+
+// everything in a map, gone when the process ends; for tests and trying the pipeline offline
+#[derive(Default)]
+pub struct MemoryStore {
+    blobs: Mutex<HashMap<String, (Vec<u8>, bool)>>, // cid -> (bytes, pinned)
+}
+
+#[async_trait]
+impl ContentStore for MemoryStore {
+    async fn put(&self, bytes: Vec<u8>, _filename: &str) -> Result<String> {
+        let cid = cid_v1(&bytes);
+        self.lock().insert(cid.clone(), (bytes, true));
+        Ok(cid)
+    }
+
+    // it all ends up in memory anyway
+    async fn put_file(&self, path: &Path, filename: &str) -> Result<String> {
+        self.put(tokio::fs::read(path).await?, filename).await
+    }
+
+    async fn get(&self, cid: &str) -> Result<Vec<u8>> {
+        self.lock().get(cid).map(|(bytes, _)| bytes.clone()).ok_or_else(|| not_stored(cid))
+    }
+
+    async fn get_stream(&self, cid: &str) -> Result<ContentStream> {
+        let bytes = self.get(cid).await?;
+        let chunks: Vec<Result<Vec<u8>>> = bytes.chunks(READ_CHUNK).map(|chunk| Ok(chunk.to_vec())).collect();
+        Ok(stream::iter(chunks).boxed())
+    }
+
+    async fn pin(&self, cid: &str) -> Result<()> {
+        let mut blobs = self.lock();
+        let (_, pinned) = blobs.get_mut(cid).ok_or_else(|| not_stored(cid))?;
+        *pinned = true;
+        Ok(())
+    }
+
+    async fn unpin(&self, cid: &str) -> Result<()> {
+        self.lock().remove(cid).map(|_| ()).ok_or_else(|| not_stored(cid))
+    }
+
+    async fn stat(&self, cid: &str) -> Result<Option<ContentStat>> {
+        Ok(self
+            .lock()
+            .get(cid)
+            .map(|(bytes, pinned)| ContentStat { cid: cid.to_string(), size: bytes.len() as u64, pinned: *pinned }))
+    }
+
+    fn name(&self) -> String {
+        "memory".to_string()
+    }
+}
+
+impl MemoryStore {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, (Vec<u8>, bool)>> {
+        self.blobs.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+
+// helper functions
+// an open file as a stream of READ_CHUNK pieces
+fn file_chunks(file: tokio::fs::File) -> ContentStream {
+    stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut chunk = vec![0; READ_CHUNK];
+        match file.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(n) => {
+                chunk.truncate(n);
+                Some((Ok(chunk), Some(file)))
+            }
+            Err(e) => Some((Err(Error::Io(e)), None)),
+        }
+    })
+    .boxed()
+}
+
+// kubo answers 500 with a message for most failures, including content it doesn't have
+async fn check_status(command: &str, resp: reqwest::Response) -> Result<reqwest::Response> {
+    if resp.status().is_success() {
+        return Ok(resp);
+    }
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|v| v.get("Message").and_then(Value::as_str).map(str::to_string))
+        .unwrap_or_else(|| body.trim().to_string());
+
+    let missing = ["not found", "not pinned", "no link named", "block was not found locally"];
+    if missing.iter().any(|m| message.to_ascii_lowercase().contains(m)) {
+        return Err(Error::NotFound(format!("{}: {}", command, message)));
+    }
+    Err(Error::Ipfs(format!("{} returned {}: {}", command, status, message)))
+}
+
+fn checked_cid(cid: &str) -> Result<()> {
+    if looks_like_cid(cid) {
+        Ok(())
+    } else {
+        Err(Error::InvalidInput(format!("'{}' is not a cid", cid)))
+    }
+}
+
+fn not_stored(cid: &str) -> Error {
+    Error::NotFound(format!("{} is not in the content store", cid))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_store_round_trips() {
+        let store = MemoryStore::default();
+        let cid = store.put(b"hello world".to_vec(), "hello.txt").await.unwrap();
+        assert_eq!(cid, cid_v1(b"hello world"));
+
+        assert_eq!(store.get(&cid).await.unwrap(), b"hello world");
+        let stat = store.stat(&cid).await.unwrap().unwrap();
+        assert_eq!((stat.cid.as_str(), stat.size, stat.pinned), (cid.as_str(), 11, true));
+
+        store.unpin(&cid).await.unwrap();
+        assert!(store.stat(&cid).await.unwrap().is_none());
+        assert!(matches!(store.get(&cid).await, Err(Error::NotFound(_))));
+        assert!(matches!(store.unpin(&cid).await, Err(Error::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn memory_store_puts_are_idempotent() {
+        let store = MemoryStore::default();
+        let first = store.put(b"same".to_vec(), "a.txt").await.unwrap();
+        let second = store.put(b"same".to_vec(), "b.txt").await.unwrap();
+        assert_eq!(first, second);
+        store.pin(&first).await.unwrap();
+        assert!(matches!(store.pin("bafkreimissing").await, Err(Error::NotFound(_))));
+    }
+
+    // a one-shot http server standing in for kubo: it answers add with the hash of "hello world"
+    // and hands back the whole request it got
+    async fn fake_kubo() -> (String, tokio::task::JoinHandle<String>) {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            // headers first, then as much body as they announce
+            let header_end = loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break end + 4;
+                }
+            };
+            let headers = String::from_utf8_lossy(&request[..header_end]).to_ascii_lowercase();
+            let length: usize = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map(|value| value.trim().parse().unwrap())
+                .unwrap_or(0);
+            while request.len() < header_end + length {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+
+            let body = format!(r#"{{"Name":"hello.txt","Hash":"{}","Size":"11"}}"#, cid_v1(b"hello world"));
+            let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn kubo_add_asks_for_cid_v1() {
+        let (url, server) = fake_kubo().await;
+        let cid = KuboStore::new(&url).put(b"hello world".to_vec(), "hello.txt").await.unwrap();
+        assert_eq!(cid, cid_v1(b"hello world"));
+        let request = server.await.unwrap();
+        let request_line = request.lines().next().unwrap();
+        assert!(request_line.starts_with("POST /api/v0/add?"), "{}", request_line);
+        assert!(request_line.contains("cid-version=1"), "{}", request_line);
+    }
+
+    #[tokio::test]
+    async fn kubo_gets_files_streamed_with_their_length() {
+        let path = std::env::temp_dir().join(format!("kubo-put-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"hello world").unwrap();
+
+        let (url, server) = fake_kubo().await;
+        let cid = KuboStore::new(&url).put_file(&path, "hello.txt").await.unwrap();
+        assert_eq!(cid, cid_v1(b"hello world"));
+        let request = server.await.unwrap();
+        assert!(request.to_ascii_lowercase().contains("content-length:"), "{}", request);
+        assert!(request.contains("filename=\"hello.txt\"") && request.contains("\r\n\r\nhello world\r\n"), "{}", request);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_store_refuses_paths() {
+        let root = std::env::temp_dir().join(format!("content-store-{}", uuid::Uuid::new_v4()));
+        let store = LocalStore::open(&root).unwrap();
+
+        let cid = store.put(b"hello world".to_vec(), "hello.txt").await.unwrap();
+        assert_eq!(store.get(&cid).await.unwrap(), b"hello world");
+        assert!(matches!(store.get("../../etc/passwd").await, Err(Error::InvalidInput(_))));
+        store.unpin(&cid).await.unwrap();
+        assert!(matches!(store.get(&cid).await, Err(Error::NotFound(_))));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_store_streams_in_pieces() {
+        let root = std::env::temp_dir().join(format!("content-store-{}", uuid::Uuid::new_v4()));
+        let store = LocalStore::open(&root).unwrap();
+        let bytes: Vec<u8> = (0..READ_CHUNK * 2 + 10).map(|i| i as u8).collect();
+        let path = root.join("big.bin");
+        std::fs::write(&path, &bytes).unwrap();
+        let cid = store.put_file(&path, "big.bin").await.unwrap();
+        assert_eq!(cid, cid_v1(&bytes));
+
+        let pieces: Vec<Vec<u8>> = store.get_stream(&cid).await.unwrap().map(|piece| piece.unwrap()).collect().await;
+        assert_eq!(pieces.len(), 3);
+        assert_eq!(pieces.concat(), bytes);
+        assert!(matches!(store.get_stream(&cid_v1(b"not stored")).await, Err(Error::NotFound(_))));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,476 @@
+// batch.rs: turns the files of one upload request (zips unpacked) into queued jobs, each with a result of its own
+use actix_web::ResponseError;
+use dotenv::dotenv;
+use futures::stream::{self, StreamExt};
+use sanitize_filename::sanitize;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek};
+use tokio::io::AsyncReadExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+use zip::ZipArchive;
+
+use crate::database::database::ArchiveRecord;
+use crate::database::repository::ArchiveRepository;
+use crate::error::{run_blocking, Error, Result};
+use crate::extract::extract::sniff_file_format;
+use crate::hash::{compute_sha256, compute_sha256_hex};
+use crate::jobs::queue::JobKind;
+use crate::config::env_u64;
+use crate::jobs::worker::JobQueue;
+use crate::upload::receive::{check_declared_format, megabytes};
+
+
+// where uploaded files wait for their job
+pub const UPLOAD_DIR: &str = "./uploads";
+
+const MEGABYTE: u64 = 1024 * 1024;
+
+// limits for /api/upload and /api/uploads, read from the environment (.env works too)
+//
+//   UPLOAD_CONCURRENCY       files of one request checked and queued at the same time (default 4)
+//   MAX_BATCH_FILES          files per request, counting every document inside a zip (default 100)
+//   MAX_FILE_SIZE_MB         biggest single file, a document inside a zip included (default 50)
+//   MAX_REQUEST_SIZE_MB      biggest request body, and the most one zip may unpack to (default 200)
+//   MAX_RESUMABLE_SIZE_MB    biggest file sent a piece at a time through /api/uploads (default 1024)
+//   UPLOAD_SESSION_TTL_SECS  how long a resumable upload may sit idle before it's thrown away (default 86400)
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    pub concurrency: usize,
+    pub max_batch_files: usize,
+    pub max_file_bytes: u64,
+    pub max_request_bytes: u64,
+    pub max_session_bytes: u64,
+    pub session_ttl: Duration,
+}
+
+impl UploadConfig {
+    pub fn from_env() -> Result<Self> {
+        // load the dotenv variables
+        dotenv().ok();
+
+        Ok(UploadConfig {
+            concurrency: env_u64("UPLOAD_CONCURRENCY", 4, 1)? as usize,
+            max_batch_files: env_u64("MAX_BATCH_FILES", 100, 1)? as usize,
+            max_file_bytes: env_u64("MAX_FILE_SIZE_MB", 50, 1)?.saturating_mul(MEGABYTE),
+            max_request_bytes: env_u64("MAX_REQUEST_SIZE_MB", 200, 1)?.saturating_mul(MEGABYTE),
+            max_session_bytes: env_u64("MAX_RESUMABLE_SIZE_MB", 1024, 1)?.saturating_mul(MEGABYTE),
+            session_ttl: Duration::from_secs(env_u64("UPLOAD_SESSION_TTL_SECS", 86400, 1)?),
+        })
+    }
+}
+
+// one file of the request, already written to UPLOAD_DIR
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub path: PathBuf,
+    pub server_filename: String,
+    pub original_filename: Option<String>,
+    pub archive: Option<String>, // the zip it was unpacked from
+    pub content_type: Option<String>, // what the client said it is
+    pub file_hash: Option<String>, // sha-256, worked out while the file was written
+}
+
+impl StoredFile {
+    // a fresh, unguessable place in UPLOAD_DIR that keeps the original extension
+    pub fn new(original_filename: Option<String>, archive: Option<String>) -> Self {
+        let uuid = Uuid::new_v4().to_string();
+        let server_filename = original_filename
+            .as_deref()
+            .map(sanitize)
+            .and_then(|name| Path::new(&name).extension().map(|ext| format!("{}.{}", uuid, ext.to_string_lossy())))
+            .unwrap_or(uuid);
+        let path = Path::new(UPLOAD_DIR).join(&server_filename);
+        StoredFile { path, server_filename, original_filename, archive, content_type: None, file_hash: None }
+    }
+}
+
+// a file as it came out of the request: stored, or already turned away (and not kept)
+pub type Received = std::result::Result<StoredFile, (StoredFile, Error)>;
+
+// why one file of a batch was turned away, in the shape of ai_engine::Error's json body
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileError {
+    pub error: String,
+    pub message: String,
+    pub http_status: u16, // what a request with only this file would have got
+}
+
+// what happened to one file: accepted (job_id to follow), duplicate (record is the copy we already
+// hold) or failed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileResult {
+    pub index: usize,
+    pub original_filename: Option<String>,
+    pub archive: Option<String>,
+    pub server_filename: String,
+    pub status: String,
+    pub job_id: Option<String>,
+    pub kind: Option<String>,
+    pub record_id: Option<i64>,
+    pub record: Option<ArchiveRecord>,
+    pub error: Option<FileError>,
+}
+
+// every file of a request: zips are replaced by the documents inside them, then each file is
+// checked and queued, `concurrency` at a time. results come back in upload order, and a file that
+// fails never stops the others
+pub async fn admit_batch(
+    files: Vec<Received>,
+    force: bool,
+    config: &UploadConfig,
+    jobs: &JobQueue,
+    repo: &ArchiveRepository,
+) -> Vec<FileResult> {
+    let mut entries: Vec<Received> = Vec::new();
+    for file in files {
+        let file = match file {
+            Ok(file) => file,
+            Err(rejected) => {
+                entries.push(Err(rejected));
+                continue;
+            }
+        };
+        match unpack_if_archive(&file, config).await {
+            Ok(Some(inner)) => entries.extend(inner),
+            Ok(None) => entries.push(Ok(file)),
+            Err(e) => entries.push(Err((file, e))),
+        }
+    }
+
+    // the limit counts files across the whole request, zips and all
+    let mut admitted = 0;
+    for entry in entries.iter_mut() {
+        let Ok(file) = entry else { continue };
+        if admitted == config.max_batch_files {
+            discard(file).await;
+            *entry = Err((file.clone(), too_many(config)));
+        } else {
+            admitted += 1;
+        }
+    }
+
+    stream::iter(entries.into_iter().enumerate())
+        .map(|(index, entry)| async move {
+            match entry {
+                Ok(file) => admit(index, file, force, jobs, repo).await,
+                Err((file, e)) => failed(index, file, e),
+            }
+        })
+        .buffered(config.concurrency)
+        .collect()
+        .await
+}
+
+
+// helper functions
+// one stored file: rejected if we can't read it, handed back if we already hold it, queued otherwise
+async fn admit(index: usize, file: StoredFile, force: bool, jobs: &JobQueue, repo: &ArchiveRepository) -> FileResult {
+    match try_admit(index, &file, force, jobs, repo).await {
+        Ok(result) => result,
+        Err(e) => {
+            discard(&file).await;
+            failed(index, file, e)
+        }
+    }
+}
+
+async fn try_admit(index: usize, file: &StoredFile, force: bool, jobs: &JobQueue, repo: &ArchiveRepository) -> Result<FileResult> {
+    // reject anything we can't pull text out of before spending an llm call on it; only the head
+    // of the file is read (and a zip's directory), the job reads the rest
+    let (path, name) = (file.path.clone(), file.server_filename.clone());
+    let format = run_blocking(move || Ok(sniff_file_format(&path, Some(&name))?)).await?;
+    check_declared_format(file.content_type.as_deref(), format)?;
+
+    // a file we already hold costs nothing; hand back the existing record instead of paying for
+    // another llm call, content store put and memo. the hash was taken while the file was written,
+    // and goes on the job so the store stage doesn't take it again
+    let file_hash = match &file.file_hash {
+        Some(file_hash) => file_hash.clone(),
+        None => compute_sha256_hex(&file.path).await?,
+    };
+    let (lookup, hash) = (repo.clone(), file_hash.clone());
+    let existing = run_blocking(move || lookup.find_by_hash(&hash)).await?;
+
+    let mut result = FileResult {
+        index,
+        original_filename: file.original_filename.clone(),
+        archive: file.archive.clone(),
+        server_filename: file.server_filename.clone(),
+        status: String::new(),
+        job_id: None,
+        kind: None,
+        record_id: existing,
+        record: None,
+        error: None,
+    };
+
+    let kind = match existing {
+        // only re-extract when asked; the bytes are identical, so hash, cid and anchor still hold
+        Some(_) if force => JobKind::Reextract,
+        Some(existing_id) => {
+            discard(file).await;
+            let reader = repo.clone();
+            result.record = run_blocking(move || reader.get(existing_id)).await?;
+            result.status = "duplicate".to_string();
+            return Ok(result);
+        }
+        None => JobKind::Upload,
+    };
+
+    // everything else (extraction, the content store, the memo, the insert) runs on the job workers
+    let path = file.path.to_string_lossy().into_owned();
+    result.job_id = Some(jobs.enqueue(kind, path, Some(file_hash), file.original_filename.clone(), existing).await?);
+    result.kind = Some(kind.as_str().to_string());
+    result.status = "accepted".to_string();
+    Ok(result)
+}
+
+fn failed(index: usize, file: StoredFile, e: Error) -> FileResult {
+    FileResult {
+        index,
+        original_filename: file.original_filename,
+        archive: file.archive,
+        server_filename: file.server_filename,
+        status: "failed".to_string(),
+        job_id: None,
+        kind: None,
+        record_id: None,
+        record: None,
+        error: Some(FileError { error: e.kind().to_string(), message: e.to_string(), http_status: e.status_code().as_u16() }),
+    }
+}
+
+// a zip that isn't itself a document (docx and epub are zips too) is a batch: its documents are
+// written next to it as files of their own and the zip goes. None for anything else
+async fn unpack_if_archive(file: &StoredFile, config: &UploadConfig) -> Result<Option<Vec<Received>>> {
+    // only a zip gets past the magic, and it's read from the file through its directory, never whole
+    let mut magic = [0u8; 4];
+    let mut handle = tokio::fs::File::open(&file.path).await?;
+    if handle.read_exact(&mut magic).await.is_err() || &magic != b"PK\x03\x04" {
+        return Ok(None);
+    }
+    let (path, name) = (file.path.clone(), file.server_filename.clone());
+    if run_blocking(move || Ok(sniff_file_format(&path, Some(&name)).is_ok())).await? {
+        return Ok(None);
+    }
+
+    let archive_name = file.original_filename.clone().unwrap_or_else(|| file.server_filename.clone());
+    let (path, config) = (file.path.clone(), config.clone());
+    let unpacked = run_blocking(move || unpack_zip(std::fs::File::open(&path)?, &archive_name, &config)).await;
+    discard(file).await;
+
+    let unpacked = unpacked?;
+    if unpacked.is_empty() {
+        return Err(Error::InvalidInput("the zip archive holds no documents".to_string()));
+    }
+    Ok(Some(unpacked))
+}
+
+// every document of the zip, each held to the per-file limit; the zip as a whole can't unpack to
+// more than a request may carry, and nothing past the batch limit is written at all
+fn unpack_zip<R: Read + Seek>(reader: R, archive_name: &str, config: &UploadConfig) -> Result<Vec<Received>> {
+    let mut archive = ZipArchive::new(reader)
+        .map_err(|e| Error::InvalidInput(format!("{} is not a readable zip archive: {}", archive_name, e)))?;
+
+    let mut files: Vec<Received> = Vec::new();
+    let (mut written, mut unpacked_bytes) = (0, 0u64);
+    for i in 0..archive.len() {
+        let mut entry = match archive.by_index(i) {
+            Ok(entry) => entry,
+            Err(e) => {
+                // the documents already written go with the zip
+                for file in files.iter().flatten() {
+                    let _ = std::fs::remove_file(&file.path);
+                }
+                return Err(Error::InvalidInput(format!("{} is not a readable zip archive: {}", archive_name, e)));
+            }
+        };
+        if entry.is_dir() {
+            continue;
+        }
+        // names that would escape the directory are skipped, and so is the clutter macos adds
+        let Some(name) = entry.enclosed_name() else { continue };
+        let hidden = name
+            .components()
+            .any(|part| part.as_os_str().to_string_lossy().starts_with('.') || part.as_os_str() == "__MACOSX");
+        if hidden {
+            continue;
+        }
+
+        let mut stored = StoredFile::new(Some(name.to_string_lossy().into_owned()), Some(archive_name.to_string()));
+        if written == config.max_batch_files {
+            files.push(Err((stored, too_many(config))));
+            continue;
+        }
+
+        // the sizes in the zip's directory can lie, so stop reading one byte past the limit
+        let mut content = Vec::new();
+        if let Err(e) = (&mut entry).take(config.max_file_bytes + 1).read_to_end(&mut content) {
+            let message = format!("could not unpack {} from {}: {}", name.display(), archive_name, e);
+            files.push(Err((stored, Error::InvalidInput(message))));
+            continue;
+        }
+        if content.len() as u64 > config.max_file_bytes {
+            let message = format!("{} is over the {} limit for one file", name.display(), megabytes(config.max_file_bytes));
+            files.push(Err((stored, Error::TooLarge(message))));
+            continue;
+        }
+        if unpacked_bytes + content.len() as u64 > config.max_request_bytes {
+            let message = format!("{} unpacks to more than {}", archive_name, megabytes(config.max_request_bytes));
+            files.push(Err((stored, Error::TooLarge(message))));
+            continue;
+        }
+
+        unpacked_bytes += content.len() as u64;
+        written += 1;
+        stored.file_hash = Some(compute_sha256(&content));
+        match std::fs::write(&stored.path, content) {
+            Ok(()) => files.push(Ok(stored)),
+            Err(e) => files.push(Err((stored, Error::Io(e)))),
+        }
+    }
+    Ok(files)
+}
+
+fn too_many(config: &UploadConfig) -> Error {
+    Error::InvalidInput(format!("more than {} files in one upload, send the rest separately", config.max_batch_files))
+}
+
+// every file of a request that won't go ahead after all
+pub async fn discard_received(files: &[Received]) {
+    for file in files.iter().flatten() {
+        discard(file).await;
+    }
+}
+
+// a file we're done with; a leftover costs only disk space, so failing to remove it isn't an error
+pub(crate) async fn discard(file: &StoredFile) {
+    let _ = tokio::fs::remove_file(&file.path).await;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::worker::tests::queue;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+
+    fn config() -> UploadConfig {
+        UploadConfig {
+            concurrency: 2,
+            max_batch_files: 3,
+            max_file_bytes: 16,
+            max_request_bytes: 40,
+            max_session_bytes: 1024,
+            session_ttl: Duration::from_secs(60),
+        }
+    }
+
+    fn zip_of(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn unpack(entries: &[(&str, &[u8])], config: &UploadConfig) -> Vec<Received> {
+        std::fs::create_dir_all(UPLOAD_DIR).unwrap();
+        unpack_zip(Cursor::new(zip_of(entries)), "batch.zip", config).unwrap()
+    }
+
+    // (name, error kind) for every entry, and nothing left on disk
+    fn outcomes(files: Vec<Received>) -> Vec<(String, Option<&'static str>)> {
+        files
+            .into_iter()
+            .map(|file| match file {
+                Ok(file) => {
+                    std::fs::remove_file(&file.path).unwrap();
+                    (file.original_filename.unwrap(), None)
+                }
+                Err((file, e)) => (file.original_filename.unwrap(), Some(e.kind())),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skips_escaping_and_hidden_entries() {
+        let files = unpack(
+            &[
+                ("notes.txt", b"plant notes"),
+                ("../escape.txt", b"outside"),
+                ("__MACOSX/._notes.txt", b"resource fork"),
+                (".DS_Store", b"finder"),
+                ("chapter/.hidden.md", b"# hidden"),
+                ("chapter/one.md", b"# one"),
+            ],
+            &config(),
+        );
+        let hashes: Vec<Option<String>> = files.iter().flatten().map(|file| file.file_hash.clone()).collect();
+        assert_eq!(hashes, [Some(compute_sha256(b"plant notes")), Some(compute_sha256(b"# one"))]);
+        assert_eq!(outcomes(files), [("notes.txt".to_string(), None), ("chapter/one.md".to_string(), None)]);
+    }
+
+    #[test]
+    fn holds_each_entry_and_the_whole_zip_to_the_limits() {
+        let files = unpack(
+            &[
+                ("exact.txt", &[b'a'; 16]),
+                ("over.txt", &[b'b'; 17]),
+                ("second.txt", &[b'c'; 16]),
+                ("past-total.txt", &[b'd'; 16]),
+                ("small.txt", &[b'e'; 8]),
+            ],
+            &config(),
+        );
+        assert_eq!(outcomes(files), [
+            ("exact.txt".to_string(), None),
+            ("over.txt".to_string(), Some("too_large")),
+            ("second.txt".to_string(), None),
+            ("past-total.txt".to_string(), Some("too_large")),
+            ("small.txt".to_string(), None),
+        ]);
+    }
+
+    #[test]
+    fn refuses_what_is_not_a_zip() {
+        assert!(matches!(unpack_zip(Cursor::new(b"PK\x03\x04 and then junk"), "broken.zip", &config()), Err(Error::InvalidInput(_))));
+    }
+
+    #[tokio::test]
+    async fn the_file_limit_counts_across_zips() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut fixtures = Vec::new();
+        let jobs = queue(&repo, &mut fixtures);
+        std::fs::create_dir_all(UPLOAD_DIR).unwrap();
+
+        let mut files = Vec::new();
+        for (zip_name, entries) in [("first.zip", ["a.txt", "b.txt"]), ("second.zip", ["c.txt", "d.txt"])] {
+            let entries: Vec<(&str, &[u8])> = entries.iter().map(|name| (*name, name.as_bytes())).collect();
+            let stored = StoredFile::new(Some(zip_name.to_string()), None);
+            std::fs::write(&stored.path, zip_of(&entries)).unwrap();
+            files.push(Ok(stored));
+        }
+
+        let results = admit_batch(files, false, &config(), &jobs, &repo).await;
+        let statuses: Vec<(&str, &str)> =
+            results.iter().map(|r| (r.original_filename.as_deref().unwrap(), r.status.as_str())).collect();
+        assert_eq!(statuses, [("a.txt", "accepted"), ("b.txt", "accepted"), ("c.txt", "accepted"), ("d.txt", "failed")]);
+        assert_eq!(results[3].archive.as_deref(), Some("second.zip"));
+        assert_eq!(results[3].error.as_ref().unwrap().error, "bad_request");
+
+        // the zips are gone, so is the file past the limit; the queued ones wait for their jobs
+        for result in &results {
+            let path = Path::new(UPLOAD_DIR).join(&result.server_filename);
+            assert_eq!(path.exists(), result.status == "accepted");
+            let _ = std::fs::remove_file(path);
+        }
+        for fixture in fixtures {
+            let _ = std::fs::remove_file(fixture);
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub mod batch;
+pub mod receive;
+pub mod session;
@@ -0,0 +1,249 @@
+// receive.rs: writes an uploaded file to disk as it streams in, inside the size limits, hashing it on the way
+use futures::{Stream, StreamExt};
+use std::fmt::Display;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use crate::error::{Error, Result};
+use crate::extract::extract::{DocumentFormat, ExtractError};
+use crate::hash::Sha256Stream;
+use crate::upload::batch::{discard, Received, StoredFile, UploadConfig};
+
+
+// content types that say nothing about the file, so the bytes decide
+const GENERIC_CONTENT_TYPES: [&str; 2] = ["application/octet-stream", "binary/octet-stream"];
+
+// a zip of documents, unpacked into a batch of its own
+const ZIP_CONTENT_TYPES: [&str; 3] = ["application/zip", "application/x-zip-compressed", "application/x-zip"];
+
+// one multipart field, written to UPLOAD_DIR chunk by chunk. `received` is what the request has
+// carried so far, across its fields. the outer error fails the whole request (over
+// max_request_bytes, or the body broke off), the inner one only this file (a content type we can't
+// extract, or over max_file_bytes). a rejected file is still read to the end so the next field can
+// be reached, nothing more of it is written
+pub async fn receive_file<S, B, E>(
+    chunks: S,
+    original_filename: Option<String>,
+    content_type: Option<String>,
+    config: &UploadConfig,
+    received: &mut u64,
+) -> Result<Received>
+where
+    S: Stream<Item = std::result::Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: Display,
+{
+    let mut stored = StoredFile::new(original_filename, None);
+    stored.content_type = content_type;
+
+    match write_chunks(chunks, &stored, config, received).await {
+        Ok(Ok(file_hash)) => {
+            stored.file_hash = Some(file_hash);
+            Ok(Ok(stored))
+        }
+        Ok(Err(e)) => {
+            discard(&stored).await;
+            Ok(Err((stored, e)))
+        }
+        Err(e) => {
+            discard(&stored).await;
+            Err(e)
+        }
+    }
+}
+
+// turns away a declared content type none of the extractors (or the zip unpacking) can take;
+// a missing or generic one is left to the sniffing
+pub fn check_content_type(content_type: Option<&str>) -> Result<()> {
+    let Some(content_type) = content_type else {
+        return Ok(());
+    };
+    let lowered = content_type.trim().to_ascii_lowercase();
+    if GENERIC_CONTENT_TYPES.contains(&lowered.as_str())
+        || ZIP_CONTENT_TYPES.contains(&lowered.as_str())
+        || DocumentFormat::from_mime(&lowered).is_some()
+    {
+        return Ok(());
+    }
+    Err(Error::Extraction(ExtractError::Unsupported(format!(
+        "content type {} (pdf, docx, epub, plain text, markdown, html or a zip of them)",
+        content_type
+    ))))
+}
+
+// a file whose content turned out to be another format than the one it was declared as. the text
+// formats only differ by name, so any text type passes for any of them
+pub fn check_declared_format(content_type: Option<&str>, format: DocumentFormat) -> Result<()> {
+    let Some(declared) = content_type.and_then(DocumentFormat::from_mime) else {
+        return Ok(());
+    };
+    if declared == format || (is_text(declared) && is_text(format)) {
+        return Ok(());
+    }
+    Err(Error::Extraction(ExtractError::Unsupported(format!(
+        "declared as {} but the content is {}",
+        declared.mime_type(),
+        format.as_str()
+    ))))
+}
+
+// the limit in the unit it's configured in
+pub fn megabytes(bytes: u64) -> String {
+    format!("{} MB", bytes / (1024 * 1024))
+}
+
+
+// helper functions
+// the file's hash, or why the file was rejected; an error for the whole request comes out of the outer result
+async fn write_chunks<S, B, E>(
+    mut chunks: S,
+    stored: &StoredFile,
+    config: &UploadConfig,
+    received: &mut u64,
+) -> Result<Result<String>>
+where
+    S: Stream<Item = std::result::Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: Display,
+{
+    let name = stored.original_filename.clone().unwrap_or_else(|| stored.server_filename.clone());
+    let mut rejection = check_content_type(stored.content_type.as_deref()).err();
+    let mut file = match rejection {
+        None => Some(File::create(&stored.path).await?),
+        Some(_) => None,
+    };
+    let mut hasher = Sha256Stream::default();
+    let mut size = 0u64;
+
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|e| Error::InvalidInput(format!("Chunk read error: {}", e)))?;
+        let chunk = chunk.as_ref();
+
+        *received += chunk.len() as u64;
+        if *received > config.max_request_bytes {
+            return Err(Error::TooLarge(format!(
+                "the upload is over the {} limit for one request",
+                megabytes(config.max_request_bytes)
+            )));
+        }
+
+        size += chunk.len() as u64;
+        if rejection.is_none() && size > config.max_file_bytes {
+            rejection = Some(Error::TooLarge(format!(
+                "{} is over the {} limit for one file",
+                name,
+                megabytes(config.max_file_bytes)
+            )));
+            file = None;
+        }
+
+        if let Some(file) = file.as_mut() {
+            file.write_all(chunk).await?;
+            hasher.update(chunk);
+        }
+    }
+
+    if let Some(e) = rejection {
+        return Ok(Err(e));
+    }
+    if let Some(mut file) = file {
+        file.flush().await?;
+    }
+    Ok(Ok(hasher.finish()))
+}
+
+fn is_text(format: DocumentFormat) -> bool {
+    matches!(format, DocumentFormat::Txt | DocumentFormat::Markdown | DocumentFormat::Html)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::repository::ArchiveRepository;
+    use crate::hash::compute_sha256;
+    use crate::jobs::worker::tests::queue;
+    use crate::upload::batch::{admit_batch, UPLOAD_DIR};
+    use futures::stream;
+    use std::time::Duration;
+
+    fn config() -> UploadConfig {
+        UploadConfig {
+            concurrency: 1,
+            max_batch_files: 10,
+            max_file_bytes: 16,
+            max_request_bytes: 40,
+            max_session_bytes: 1024,
+            session_ttl: Duration::from_secs(60),
+        }
+    }
+
+    // a body that arrives in these pieces
+    fn chunks(pieces: &[&'static [u8]]) -> impl Stream<Item = std::result::Result<&'static [u8], String>> + Unpin {
+        stream::iter(pieces.iter().map(|piece| Ok(*piece)).collect::<Vec<_>>())
+    }
+
+    async fn receive(pieces: &[&'static [u8]], content_type: Option<&str>, received: &mut u64) -> Result<Received> {
+        std::fs::create_dir_all(UPLOAD_DIR).unwrap();
+        receive_file(chunks(pieces), Some("notes.txt".to_string()), content_type.map(str::to_string), &config(), received).await
+    }
+
+    #[tokio::test]
+    async fn hashes_the_file_as_it_is_written() {
+        let mut received = 0;
+        let stored = receive(&[b"plant ", b"notes ", b"here"], Some("text/plain"), &mut received).await.unwrap().unwrap();
+        assert_eq!(received, 16);
+        assert_eq!(std::fs::read(&stored.path).unwrap(), b"plant notes here");
+        assert_eq!(stored.file_hash, Some(compute_sha256(b"plant notes here")));
+        std::fs::remove_file(&stored.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_file_over_its_limit_is_turned_away_alone() {
+        let mut received = 0;
+        let (stored, e) = receive(&[b"0123456789", b"0123456789"], None, &mut received).await.unwrap().unwrap_err();
+        assert!(matches!(e, Error::TooLarge(_)), "{:?}", e);
+        assert!(!stored.path.exists());
+
+        // it was read to the end, and counts against the request
+        assert_eq!(received, 20);
+        let next = receive(&[b"short"], None, &mut received).await.unwrap().unwrap();
+        std::fs::remove_file(&next.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_request_limit_fails_the_whole_request() {
+        let mut received = 30;
+        let e = receive(&[b"0123456789", b"x"], None, &mut received).await.unwrap_err();
+        assert!(matches!(e, Error::TooLarge(_)), "{:?}", e);
+        assert!(e.to_string().contains("one request"));
+    }
+
+    #[tokio::test]
+    async fn a_content_type_we_cannot_extract_is_not_written() {
+        let mut received = 0;
+        let (stored, e) = receive(&[b"GIF89a"], Some("image/gif"), &mut received).await.unwrap().unwrap_err();
+        assert!(matches!(e, Error::Extraction(ExtractError::Unsupported(_))), "{:?}", e);
+        assert!(!stored.path.exists());
+        assert_eq!(received, 6);
+    }
+
+    #[tokio::test]
+    async fn the_job_gets_the_streamed_hash() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut fixtures = Vec::new();
+        let jobs = queue(&repo, &mut fixtures);
+
+        let mut received = 0;
+        let stored = receive(&[b"Leaves ", b"are green"], None, &mut received).await.unwrap();
+        let path = stored.as_ref().unwrap().path.clone();
+        let results = admit_batch(vec![stored], false, &config(), &jobs, &repo).await;
+        let report = jobs.report(results[0].job_id.clone().unwrap()).await.unwrap().unwrap();
+        assert_eq!(report.file_hash, Some(compute_sha256(b"Leaves are green")));
+
+        std::fs::remove_file(path).unwrap();
+        for fixture in fixtures {
+            let _ = std::fs::remove_file(fixture);
+        }
+    }
+}
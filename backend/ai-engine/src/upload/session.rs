@@ -0,0 +1,602 @@
+// session.rs: resumable uploads for files too big to trust to one request. the client opens a
+// session, appends the file a piece at a time, asks for the offset after a disconnect, and
+// completes the session to queue the job
+use futures::{Stream, StreamExt};
+use rusqlite::{Connection, OptionalExtension, Row};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::database::database::unix_now;
+use crate::database::repository::ArchiveRepository;
+use crate::error::{run_blocking, Error, Result};
+use crate::hash::compute_sha256_hex;
+use crate::jobs::worker::JobQueue;
+use crate::upload::batch::{admit_batch, FileResult, StoredFile, UploadConfig, UPLOAD_DIR};
+use crate::upload::receive::{check_content_type, megabytes};
+
+
+// longest we wait between two looks for expired sessions
+const SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+
+// one resumable upload as the client sees it
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadSession {
+    pub id: String,
+    pub original_filename: Option<String>,
+    pub content_type: Option<String>,
+    pub size: Option<u64>, // declared when the session was opened, if the client knew it
+    pub offset: u64,       // bytes safely on disk; the next append starts here
+    pub status: String,    // open | completing | completed
+    pub results: Option<Vec<FileResult>>, // what completing it produced, same shape as /api/upload
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub expires_at: i64, // pushed back by every append
+}
+
+// the sessions behind /api/uploads. only one append or completion runs per session at a time, a
+// second one gets a conflict instead of interleaving its bytes with the first
+pub struct UploadSessions {
+    repo: ArchiveRepository,
+    config: UploadConfig,
+    busy: Mutex<HashSet<String>>,
+}
+
+impl UploadSessions {
+    pub fn new(repo: ArchiveRepository, config: UploadConfig) -> Self {
+        UploadSessions { repo, config, busy: Mutex::new(HashSet::new()) }
+    }
+
+    // a new, empty session; the size is optional, but when it's given the file has to match it
+    pub async fn open(
+        &self,
+        original_filename: Option<String>,
+        content_type: Option<String>,
+        size: Option<u64>,
+    ) -> Result<UploadSession> {
+        check_content_type(content_type.as_deref())?;
+        if size.is_some_and(|size| size > self.config.max_session_bytes) {
+            return Err(Error::TooLarge(format!(
+                "the file is over the {} limit for resumable uploads",
+                megabytes(self.config.max_session_bytes)
+            )));
+        }
+
+        let id = Uuid::new_v4().to_string();
+        tokio::fs::create_dir_all(UPLOAD_DIR).await?;
+        tokio::fs::File::create(part_path(&id)).await?;
+
+        let ttl = self.ttl();
+        let session_id = id.clone();
+        let session = self
+            .with_db(move |conn| {
+                let now = unix_now();
+                conn.execute(
+                    "INSERT INTO upload_session (id, original_filename, content_type, size, received, status,
+                                                 created_at, updated_at, expires_at)
+                     VALUES (?1, ?2, ?3, ?4, 0, 'open', ?5, ?5, ?6)",
+                    (&session_id, &original_filename, &content_type, size.map(|s| s as i64), now, now + ttl),
+                )?;
+                load_session(conn, &session_id)
+            })
+            .await;
+        match session {
+            Ok(Some(session)) => Ok(session),
+            Ok(None) => Err(Error::Database(rusqlite::Error::QueryReturnedNoRows)),
+            Err(e) => {
+                let _ = tokio::fs::remove_file(part_path(&id)).await;
+                Err(e)
+            }
+        }
+    }
+
+    // None once the session has expired, even before the sweep gets to it
+    pub async fn get(&self, id: &str) -> Result<Option<UploadSession>> {
+        let id = id.to_string();
+        self.with_db(move |conn| load_session(conn, &id)).await
+    }
+
+    // writes the next piece of the file. offset has to be where the session is, so a client that
+    // lost track resends from the offset it gets back. whatever arrived before the body broke off
+    // is kept, the error still comes back
+    pub async fn append<S, B, E>(&self, id: &str, offset: u64, mut chunks: S) -> Result<Option<UploadSession>>
+    where
+        S: Stream<Item = std::result::Result<B, E>> + Unpin,
+        B: AsRef<[u8]>,
+        E: Display,
+    {
+        let _claim = self.claim(id)?;
+        let Some(session) = self.get(id).await? else { return Ok(None) };
+        if session.status != "open" {
+            return Err(Error::Conflict(format!("upload {} is already {}", id, session.status)));
+        }
+        if offset != session.offset {
+            return Err(Error::Conflict(format!("upload {} is at offset {}, not {}", id, session.offset, offset)));
+        }
+
+        // bytes past the offset are from an append that never got recorded, they go
+        let mut file = open_part(id).await?;
+        file.set_len(offset).await?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+
+        let limit = session.size.unwrap_or(self.config.max_session_bytes).min(self.config.max_session_bytes);
+        let mut written = 0u64;
+        let mut failure = None;
+        while let Some(chunk) = chunks.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    failure = Some(Error::InvalidInput(format!("Chunk read error: {}", e)));
+                    break;
+                }
+            };
+            let chunk = chunk.as_ref();
+            if offset + written + chunk.len() as u64 > limit {
+                failure = Some(match session.size {
+                    Some(size) if size <= self.config.max_session_bytes => {
+                        Error::TooLarge(format!("more than the {} bytes upload {} was opened with", size, id))
+                    }
+                    _ => Error::TooLarge(format!(
+                        "the file is over the {} limit for resumable uploads",
+                        megabytes(self.config.max_session_bytes)
+                    )),
+                });
+                break;
+            }
+            if let Err(e) = file.write_all(chunk).await {
+                failure = Some(Error::Io(e));
+                break;
+            }
+            written += chunk.len() as u64;
+        }
+        // a failed write may have put part of a chunk on disk; the next append cuts it off
+        file.flush().await?;
+
+        let (session_id, ttl) = (id.to_string(), self.ttl());
+        let session = self
+            .with_db(move |conn| {
+                let now = unix_now();
+                conn.execute(
+                    "UPDATE upload_session SET received = ?1, updated_at = ?2, expires_at = ?3
+                     WHERE id = ?4 AND received = ?5",
+                    ((offset + written) as i64, now, now + ttl, &session_id, offset as i64),
+                )?;
+                load_session(conn, &session_id)
+            })
+            .await?;
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(session),
+        }
+    }
+
+    // hands the finished file to the same checks as /api/upload and queues it. completing twice
+    // gives back the first results
+    pub async fn complete(&self, id: &str, force: bool, jobs: &JobQueue) -> Result<Option<UploadSession>> {
+        let _claim = self.claim(id)?;
+        let Some(session) = self.get(id).await? else { return Ok(None) };
+        if session.status == "completed" {
+            return Ok(Some(session));
+        }
+        // the last try got as far as moving the file out and then died; whether its job was queued
+        // can't be told from here, so the client starts over rather than risk a second copy
+        if session.status == "completing" {
+            return Err(Error::Conflict(format!("upload {} was interrupted while completing, open a new one", id)));
+        }
+        if session.offset == 0 {
+            return Err(Error::InvalidInput(format!("nothing has been uploaded to {} yet", id)));
+        }
+        if let Some(size) = session.size.filter(|size| *size != session.offset) {
+            return Err(Error::Conflict(format!("only {} of {} bytes of upload {} have arrived", session.offset, size, id)));
+        }
+
+        // the file leaves the session under the name a direct upload would have got
+        let mut stored = StoredFile::new(session.original_filename.clone(), None);
+        stored.content_type = session.content_type.clone();
+        // cut off anything past the offset an unrecorded append left behind
+        let file = open_part(id).await?;
+        file.set_len(session.offset).await?;
+        drop(file);
+        // read back from disk a piece at a time; the pieces came in separate requests, so there's
+        // no one stream to hash them on the way in
+        stored.file_hash = Some(compute_sha256_hex(part_path(id)).await?);
+
+        // from here on the session has no .part file; the status and the file's new place are
+        // recorded before the rename happens, for the sweep
+        let (session_id, target) = (id.to_string(), stored.path.to_string_lossy().into_owned());
+        self.with_db(move |conn| {
+            conn.execute(
+                "UPDATE upload_session SET status = 'completing', file_path = ?1, updated_at = ?2
+                 WHERE id = ?3 AND status = 'open'",
+                (&target, unix_now(), &session_id),
+            )?;
+            Ok(())
+        })
+        .await?;
+        if let Err(e) = tokio::fs::rename(part_path(id), &stored.path).await {
+            let session_id = id.to_string();
+            self.with_db(move |conn| {
+                conn.execute("UPDATE upload_session SET status = 'open', file_path = NULL WHERE id = ?1", [&session_id])?;
+                Ok(())
+            })
+            .await?;
+            return Err(Error::Io(e));
+        }
+
+        let results = admit_batch(vec![Ok(stored)], force, &self.config, jobs, &self.repo).await;
+        let saved = serde_json::to_string(&results)
+            .map_err(|e| Error::Io(std::io::Error::other(format!("could not save the upload results: {}", e))))?;
+
+        let (session_id, ttl) = (id.to_string(), self.ttl());
+        self.with_db(move |conn| {
+            let now = unix_now();
+            conn.execute(
+                "UPDATE upload_session SET status = 'completed', result = ?1, updated_at = ?2, expires_at = ?3
+                 WHERE id = ?4",
+                (&saved, now, now + ttl, &session_id),
+            )?;
+            load_session(conn, &session_id)
+        })
+        .await
+    }
+
+    // gives up on a session and throws away what arrived; false if there was none
+    pub async fn cancel(&self, id: &str) -> Result<bool> {
+        let _claim = self.claim(id)?;
+        let session_id = id.to_string();
+        let removed = self
+            .with_db(move |conn| {
+                let stranded = stranded_file(conn, &session_id)?;
+                let removed = conn.execute("DELETE FROM upload_session WHERE id = ?1", [&session_id])?;
+                Ok((removed, stranded))
+            })
+            .await?;
+        remove_files(id, removed.1).await;
+        Ok(removed.0 > 0)
+    }
+
+    // deletes every session that sat idle past its expiry, and its file with it: the partial one,
+    // or the one a completion that never finished moved out. sessions with an append in flight
+    // are left for the next sweep
+    pub async fn sweep(&self) -> Result<usize> {
+        let now = unix_now();
+        let expired: Vec<String> = self
+            .with_db(move |conn| {
+                let mut stmt = conn.prepare("SELECT id FROM upload_session WHERE expires_at <= ?1")?;
+                let ids = stmt.query_map([now], |row| row.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+                Ok(ids)
+            })
+            .await?;
+
+        let mut swept = 0;
+        for id in expired {
+            let Ok(_claim) = self.claim(&id) else { continue };
+            let session_id = id.clone();
+            let (removed, stranded) = self
+                .with_db(move |conn| {
+                    let stranded = stranded_file(conn, &session_id)?;
+                    let removed = conn.execute(
+                        "DELETE FROM upload_session WHERE id = ?1 AND expires_at <= ?2",
+                        (&session_id, now),
+                    )?;
+                    Ok((removed, stranded))
+                })
+                .await?;
+            if removed > 0 {
+                remove_files(&id, stranded).await;
+                swept += 1;
+            }
+        }
+        Ok(swept)
+    }
+
+    // the expiry sweep; errors are logged and the loop carries on, never fatal
+    pub async fn run(&self) {
+        let interval = self.config.session_ttl.min(SWEEP_INTERVAL);
+        loop {
+            match self.sweep().await {
+                Ok(0) => {}
+                Ok(swept) => println!("Removed {} expired upload sessions", swept),
+                Err(e) => println!("Upload session sweep failed: {}", e),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    fn ttl(&self) -> i64 {
+        self.config.session_ttl.as_secs() as i64
+    }
+
+    // marks the session busy until the claim is dropped
+    fn claim(&self, id: &str) -> Result<Claim<'_>> {
+        let mut busy = self.busy.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !busy.insert(id.to_string()) {
+            return Err(Error::Conflict(format!("upload {} is already being written to", id)));
+        }
+        Ok(Claim { busy: &self.busy, id: id.to_string() })
+    }
+
+    // runs a closure against a pooled connection on the blocking pool
+    async fn with_db<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let repo = self.repo.clone();
+        run_blocking(move || {
+            let mut conn = repo.conn()?;
+            f(&mut conn)
+        })
+        .await
+    }
+}
+
+struct Claim<'a> {
+    busy: &'a Mutex<HashSet<String>>,
+    id: String,
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        let mut busy = self.busy.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        busy.remove(&self.id);
+    }
+}
+
+
+// helper functions
+// where a session's bytes wait until it's completed
+fn part_path(id: &str) -> PathBuf {
+    Path::new(UPLOAD_DIR).join(format!("{}.part", id))
+}
+
+// the part file of an open session; one that's gone (cleared out by hand, or a crash) is a
+// session that can't go on
+async fn open_part(id: &str) -> Result<tokio::fs::File> {
+    match tokio::fs::OpenOptions::new().write(true).open(part_path(id)).await {
+        Ok(file) => Ok(file),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(Error::NotFound(format!("the data uploaded to {} is gone, open a new upload", id)))
+        }
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+// the file of a session left completing that no job ever took over; once a job has it, it's the
+// job's to remove
+fn stranded_file(conn: &Connection, id: &str) -> Result<Option<String>> {
+    let file_path: Option<String> = conn
+        .query_row(
+            "SELECT file_path FROM upload_session WHERE id = ?1 AND status = 'completing'",
+            [id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    let Some(file_path) = file_path else { return Ok(None) };
+    let queued: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM job WHERE file_path = ?1)", [&file_path], |row| row.get(0))?;
+    Ok((!queued).then_some(file_path))
+}
+
+// a session's leftovers; one that's already gone isn't an error
+async fn remove_files(id: &str, stranded: Option<String>) {
+    let _ = tokio::fs::remove_file(part_path(id)).await;
+    if let Some(file_path) = stranded {
+        let _ = tokio::fs::remove_file(file_path).await;
+    }
+}
+
+fn load_session(conn: &Connection, id: &str) -> Result<Option<UploadSession>> {
+    let session = conn
+        .query_row(
+            "SELECT id, original_filename, content_type, size, received, status, result,
+                    created_at, updated_at, expires_at
+             FROM upload_session WHERE id = ?1 AND expires_at > ?2",
+            (id, unix_now()),
+            session_from_row,
+        )
+        .optional()?;
+    Ok(session)
+}
+
+fn session_from_row(row: &Row) -> rusqlite::Result<UploadSession> {
+    let size: Option<i64> = row.get(3)?;
+    let received: i64 = row.get(4)?;
+    let result: Option<String> = row.get(6)?;
+    Ok(UploadSession {
+        id: row.get(0)?,
+        original_filename: row.get(1)?,
+        content_type: row.get(2)?,
+        size: size.map(|s| s as u64),
+        offset: received as u64,
+        status: row.get(5)?,
+        // saved by complete(); one we can't read back is as good as none
+        results: result.and_then(|json| serde_json::from_str(&json).ok()),
+        created_at: row.get(7)?,
+        updated_at: row.get(8)?,
+        expires_at: row.get(9)?,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::compute_sha256;
+    use crate::jobs::queue::JobKind;
+    use crate::jobs::worker::tests::queue;
+
+    const NOTES: &[u8] = b"Photosynthesis in Plants\n\nChlorophyll absorbs light.";
+
+    fn sessions(repo: &ArchiveRepository) -> UploadSessions {
+        let config = UploadConfig {
+            concurrency: 1,
+            max_batch_files: 10,
+            max_file_bytes: 1024,
+            max_request_bytes: 4096,
+            max_session_bytes: 1024,
+            session_ttl: Duration::from_secs(60),
+        };
+        UploadSessions::new(repo.clone(), config)
+    }
+
+    fn pieces(chunks: &[&'static [u8]]) -> impl Stream<Item = std::result::Result<&'static [u8], String>> + Unpin {
+        futures::stream::iter(chunks.iter().map(|chunk| Ok(*chunk)).collect::<Vec<_>>())
+    }
+
+    #[tokio::test]
+    async fn append_has_to_start_at_the_offset() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let sessions = sessions(&repo);
+        let session = sessions.open(Some("notes.txt".to_string()), None, None).await.unwrap();
+
+        let after = sessions.append(&session.id, 0, pieces(&[&NOTES[..10]])).await.unwrap().unwrap();
+        assert_eq!(after.offset, 10);
+
+        // resending the first piece is refused and changes nothing
+        let err = sessions.append(&session.id, 0, pieces(&[&NOTES[..10]])).await.unwrap_err();
+        assert!(matches!(err, Error::Conflict(_)));
+        let after = sessions.append(&session.id, 10, pieces(&[&NOTES[10..]])).await.unwrap().unwrap();
+        assert_eq!(after.offset, NOTES.len() as u64);
+        assert_eq!(std::fs::read(part_path(&session.id)).unwrap(), NOTES);
+
+        assert!(sessions.cancel(&session.id).await.unwrap());
+        assert!(!part_path(&session.id).exists());
+    }
+
+    #[tokio::test]
+    async fn append_stops_at_the_declared_size() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let sessions = sessions(&repo);
+        assert!(matches!(sessions.open(None, None, Some(4096)).await, Err(Error::TooLarge(_))));
+
+        let session = sessions.open(Some("notes.txt".to_string()), None, Some(10)).await.unwrap();
+        let err = sessions.append(&session.id, 0, pieces(&[&NOTES[..8], &NOTES[8..16]])).await.unwrap_err();
+        assert!(matches!(err, Error::TooLarge(_)));
+
+        // the piece that fit is kept
+        let after = sessions.get(&session.id).await.unwrap().unwrap();
+        assert_eq!(after.offset, 8);
+        sessions.cancel(&session.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn completing_twice_gives_the_first_results() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut files = Vec::new();
+        let jobs = queue(&repo, &mut files);
+        let sessions = sessions(&repo);
+        let session = sessions.open(Some("notes.txt".to_string()), Some("text/plain".to_string()), None).await.unwrap();
+
+        // nothing to complete yet
+        assert!(matches!(sessions.complete(&session.id, false, &jobs).await, Err(Error::InvalidInput(_))));
+        sessions.append(&session.id, 0, pieces(&[NOTES])).await.unwrap();
+
+        let first = sessions.complete(&session.id, false, &jobs).await.unwrap().unwrap();
+        assert_eq!(first.status, "completed");
+        let results = first.results.clone().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].status, "accepted");
+        assert!(!part_path(&session.id).exists());
+        let report = jobs.report(results[0].job_id.clone().unwrap()).await.unwrap().unwrap();
+        assert_eq!(report.file_hash, Some(compute_sha256(NOTES)));
+
+        let second = sessions.complete(&session.id, false, &jobs).await.unwrap().unwrap();
+        assert_eq!(second.results.unwrap()[0].job_id, results[0].job_id);
+        assert!(matches!(sessions.append(&session.id, first.offset, pieces(&[b"more"])).await, Err(Error::Conflict(_))));
+
+        let _ = std::fs::remove_file(Path::new(UPLOAD_DIR).join(&results[0].server_filename));
+        sessions.cancel(&session.id).await.unwrap();
+        for file in files {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    #[tokio::test]
+    async fn interrupted_or_lost_uploads_cannot_go_on() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut files = Vec::new();
+        let jobs = queue(&repo, &mut files);
+        let sessions = sessions(&repo);
+
+        // the part file went missing under an open session
+        let lost = sessions.open(None, None, None).await.unwrap();
+        std::fs::remove_file(part_path(&lost.id)).unwrap();
+        assert!(matches!(sessions.append(&lost.id, 0, pieces(&[NOTES])).await, Err(Error::NotFound(_))));
+
+        // a completion that died after the rename
+        let stuck = sessions.open(None, None, None).await.unwrap();
+        sessions.append(&stuck.id, 0, pieces(&[NOTES])).await.unwrap();
+        repo.conn().unwrap().execute("UPDATE upload_session SET status = 'completing' WHERE id = ?1", [&stuck.id]).unwrap();
+        assert!(matches!(sessions.complete(&stuck.id, false, &jobs).await, Err(Error::Conflict(_))));
+        assert!(matches!(sessions.append(&stuck.id, NOTES.len() as u64, pieces(&[NOTES])).await, Err(Error::Conflict(_))));
+
+        sessions.cancel(&lost.id).await.unwrap();
+        sessions.cancel(&stuck.id).await.unwrap();
+        for file in files {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    #[tokio::test]
+    async fn sweep_removes_only_expired_sessions() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let sessions = sessions(&repo);
+        let expired = sessions.open(None, None, None).await.unwrap();
+        let live = sessions.open(None, None, None).await.unwrap();
+        repo.conn().unwrap().execute("UPDATE upload_session SET expires_at = 0 WHERE id = ?1", [&expired.id]).unwrap();
+
+        // expired sessions are hidden before the sweep gets to them
+        assert!(sessions.get(&expired.id).await.unwrap().is_none());
+        assert_eq!(sessions.sweep().await.unwrap(), 1);
+        assert!(!part_path(&expired.id).exists());
+        assert!(sessions.get(&live.id).await.unwrap().is_some());
+        assert!(part_path(&live.id).exists());
+
+        sessions.cancel(&live.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sweep_removes_what_an_interrupted_completion_moved_out() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut files = Vec::new();
+        let jobs = queue(&repo, &mut files);
+        let sessions = sessions(&repo);
+
+        // both died after the rename, the second one after its job was queued too
+        let mut moved = Vec::new();
+        for queued in [false, true] {
+            let session = sessions.open(Some("notes.txt".to_string()), None, None).await.unwrap();
+            sessions.append(&session.id, 0, pieces(&[NOTES])).await.unwrap();
+            let target = StoredFile::new(Some("notes.txt".to_string()), None).path;
+            std::fs::rename(part_path(&session.id), &target).unwrap();
+            let file_path = target.to_string_lossy().into_owned();
+            repo.conn()
+                .unwrap()
+                .execute(
+                    "UPDATE upload_session SET status = 'completing', file_path = ?1, expires_at = 0 WHERE id = ?2",
+                    (&file_path, &session.id),
+                )
+                .unwrap();
+            if queued {
+                jobs.enqueue(JobKind::Upload, file_path, None, None, None).await.unwrap();
+            }
+            moved.push(target);
+        }
+
+        assert_eq!(sessions.sweep().await.unwrap(), 2);
+        assert!(!moved[0].exists());
+        // that one is the job's now
+        assert!(moved[1].exists());
+
+        std::fs::remove_file(&moved[1]).unwrap();
+        for file in files {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
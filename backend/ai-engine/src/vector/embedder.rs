@@ -0,0 +1,283 @@
+// embedder.rs: turns text into vectors for semantic search, either locally or through an embeddings api
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use dotenv::dotenv;
+
+use crate::config::env_u64;
+use crate::error::{Error, Result};
+
+
+// anything that can embed a batch of texts; every vector it returns has dimensions() entries
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>>;
+
+    fn dimensions(&self) -> usize;
+
+    // short label stored with the index, so vectors from different models never get mixed
+    fn name(&self) -> String;
+}
+
+// which embedder the server should use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddingProvider {
+    Hash,
+    Http,
+}
+
+// everything needed to build an embedder and find the index, read from the environment (.env works too)
+//
+//   EMBEDDING_PROVIDER    hash | http   (default: hash, needs no model at all)
+//   EMBEDDING_BASE_URL    openai style embeddings url (default http://localhost:11434/v1/embeddings, ollama)
+//   EMBEDDING_MODEL       model name (default nomic-embed-text)
+//   EMBEDDING_API_KEY     only if the api wants one
+//   EMBEDDING_DIMENSIONS  vector size; required by the hash embedder (default 384), checked against http results
+//   VECTOR_INDEX_PATH     where the index lives (default ./archive.vectors.json, next to archive.db)
+//   INDEX_SYNC_INTERVAL_SECS  how often the outbox is polled when nobody pokes the sync task (default 5)
+#[derive(Debug, Clone)]
+pub struct EmbeddingConfig {
+    pub provider: EmbeddingProvider,
+    pub base_url: String,
+    pub model: String,
+    pub api_key: Option<String>,
+    pub dimensions: usize,
+    pub index_path: PathBuf,
+    pub sync_interval: Duration,
+}
+
+impl EmbeddingConfig {
+    pub fn from_env() -> Result<Self> {
+        // load the dotenv variables
+        dotenv().ok();
+
+        let provider = match env::var("EMBEDDING_PROVIDER").as_deref().map(str::trim) {
+            Err(_) | Ok("hash") => EmbeddingProvider::Hash,
+            Ok("http") | Ok("openai") | Ok("ollama") => EmbeddingProvider::Http,
+            Ok(other) => return Err(Error::Config(format!("unknown EMBEDDING_PROVIDER '{}'", other))),
+        };
+
+        let base_url = env::var("EMBEDDING_BASE_URL")
+            .unwrap_or_else(|_| "http://localhost:11434/v1/embeddings".to_string());
+        let model = env::var("EMBEDDING_MODEL").unwrap_or_else(|_| "nomic-embed-text".to_string());
+        let api_key = env::var("EMBEDDING_API_KEY").ok();
+
+        let dimensions = env_u64("EMBEDDING_DIMENSIONS", 384, 1)? as usize;
+
+        let index_path = PathBuf::from(env::var("VECTOR_INDEX_PATH").unwrap_or_else(|_| "archive.vectors.json".to_string()));
+
+        let sync_interval = Duration::from_secs(env_u64("INDEX_SYNC_INTERVAL_SECS", 5, 1)?);
+
+        Ok(EmbeddingConfig { provider, base_url, model, api_key, dimensions, index_path, sync_interval })
+    }
+}
+
+// picks the implementation for the configured provider
+pub fn build_embedder(config: &EmbeddingConfig) -> Arc<dyn Embedder> {
+    match config.provider {
+        EmbeddingProvider::Hash => Arc::new(HashEmbedder::new(config.dimensions)),
+        EmbeddingProvider::Http => Arc::new(HttpEmbedder::new(config.clone())),
+    }
+}
+
+
+// no model, no network: hashes words and character trigrams into a fixed size vector.
+// deterministic, so it's what the tests and a fresh checkout use; it matches shared vocabulary,
+// not meaning
+pub struct HashEmbedder {
+    dimensions: usize,
+}
+
+impl HashEmbedder {
+    pub fn new(dimensions: usize) -> Self {
+        HashEmbedder { dimensions: dimensions.max(1) }
+    }
+
+    fn embed_one(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0f32; self.dimensions];
+        let lowered = text.to_lowercase();
+
+        for word in lowered.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+            self.add_feature(&mut vector, word.as_bytes(), 1.0);
+
+            // trigrams let "network" and "networks" land close to each other
+            let padded: Vec<char> = format!("#{}#", word).chars().collect();
+            for gram in padded.windows(3) {
+                let gram: String = gram.iter().collect();
+                self.add_feature(&mut vector, gram.as_bytes(), 0.5);
+            }
+        }
+
+        normalise(&mut vector);
+        vector
+    }
+
+    // the low bits pick the slot, the top bit the sign, so collisions tend to cancel out
+    fn add_feature(&self, vector: &mut [f32], feature: &[u8], weight: f32) {
+        let hash = fnv1a(feature);
+        let slot = (hash % self.dimensions as u64) as usize;
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[slot] += sign * weight;
+    }
+}
+
+#[async_trait]
+impl Embedder for HashEmbedder {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        Ok(texts.iter().map(|text| self.embed_one(text)).collect())
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn name(&self) -> String {
+        format!("hash/{}", self.dimensions)
+    }
+}
+
+
+// openai, ollama (/v1), llama.cpp and most hosted apis share the same embeddings endpoint
+pub struct HttpEmbedder {
+    client: Client,
+    config: EmbeddingConfig,
+}
+
+impl HttpEmbedder {
+    pub fn new(config: EmbeddingConfig) -> Self {
+        HttpEmbedder { client: Client::new(), config }
+    }
+}
+
+#[async_trait]
+impl Embedder for HttpEmbedder {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let body = json!({
+            "model": self.config.model,
+            "input": texts,
+        });
+
+        let mut req = self.client.post(&self.config.base_url).json(&body);
+        if let Some(key) = &self.config.api_key {
+            req = req.bearer_auth(key);
+        }
+
+        let resp = req.send().await.map_err(|e| Error::Embedding(format!("request failed: {}", e)))?;
+        let status = resp.status();
+        let text = resp.text().await.map_err(|e| Error::Embedding(format!("could not read response: {}", e)))?;
+        if !status.is_success() {
+            return Err(Error::Embedding(format!("api returned {}: {}", status.as_u16(), text)));
+        }
+
+        let parsed: EmbeddingResponse = serde_json::from_str(&text)
+            .map_err(|e| Error::Embedding(format!("not an embeddings response: {}", e)))?;
+
+        // the api may answer out of order, index says where each one goes
+        let mut data = parsed.data;
+        data.sort_by_key(|item| item.index);
+        if data.len() != texts.len() {
+            return Err(Error::Embedding(format!("asked for {} embeddings, got {}", texts.len(), data.len())));
+        }
+
+        let mut vectors = Vec::with_capacity(data.len());
+        for item in data {
+            let mut vector = item.embedding;
+            if vector.len() != self.config.dimensions {
+                return Err(Error::Embedding(format!(
+                    "model returned {} dimensions, EMBEDDING_DIMENSIONS is {}",
+                    vector.len(),
+                    self.config.dimensions
+                )));
+            }
+            normalise(&mut vector);
+            vectors.push(vector);
+        }
+        Ok(vectors)
+    }
+
+    fn dimensions(&self) -> usize {
+        self.config.dimensions
+    }
+
+    fn name(&self) -> String {
+        format!("http/{}/{}", self.config.model, self.config.dimensions)
+    }
+}
+
+
+// just the parts of an embeddings response we read
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingItem {
+    #[serde(default)]
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+
+// helper functions
+// unit length, so the dot product of two vectors is their cosine similarity
+pub(crate) fn normalise(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+// std's hasher isn't guaranteed to stay the same between releases, and the index outlives the binary
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cosine(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(x, y)| x * y).sum()
+    }
+
+    async fn embed(embedder: &HashEmbedder, texts: &[&str]) -> Vec<Vec<f32>> {
+        embedder.embed(&texts.iter().map(|text| text.to_string()).collect::<Vec<_>>()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn hash_vectors_are_unit_length_and_repeatable() {
+        let embedder = HashEmbedder::new(64);
+        let vectors = embed(&embedder, &["Neural networks", "neural NETWORKS", ""]).await;
+        assert_eq!(vectors.len(), 3);
+        assert!(vectors.iter().all(|vector| vector.len() == 64));
+        assert!((cosine(&vectors[0], &vectors[0]) - 1.0).abs() < 1e-5);
+        // case doesn't matter, and no words is the zero vector rather than NaNs
+        assert_eq!(vectors[0], vectors[1]);
+        assert!(vectors[2].iter().all(|x| *x == 0.0));
+        assert_eq!((embedder.name(), embedder.dimensions()), ("hash/64".to_string(), 64));
+        assert_eq!(HashEmbedder::new(0).dimensions(), 1);
+    }
+
+    #[tokio::test]
+    async fn shared_words_land_closer_than_unrelated_ones() {
+        let embedder = HashEmbedder::new(256);
+        let vectors = embed(&embedder, &["training neural networks", "a neural network", "medieval pottery glazes"]).await;
+        assert!(cosine(&vectors[0], &vectors[1]) > cosine(&vectors[0], &vectors[2]));
+    }
+}
@@ -0,0 +1,465 @@
+// index.rs: the on-disk vector index behind /ai-search, one embedding per archive record
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::error::{Error, Result};
+use crate::vector::embedder::Embedder;
+
+
+// texts sent to the embedder per request while (re)indexing
+pub(crate) const EMBED_BATCH_SIZE: usize = 32;
+const KMEANS_ITERATIONS: usize = 25;
+
+// what gets indexed for one record: the text we embed and the metadata we hand back on a hit
+#[derive(Debug, Clone)]
+pub struct IndexedDocument {
+    pub id: i64,
+    pub document: String,
+    pub metadata: Value,
+}
+
+// same shape the old chroma service answered with: one inner list per query
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct VectorSearchResult {
+    pub ids: Vec<Vec<String>>,
+    pub documents: Vec<Vec<String>>,
+    pub metadatas: Vec<Vec<Value>>,
+    pub distances: Vec<Vec<f32>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub id: i64,
+    pub document: String,
+    pub metadata: Value,
+    embedding: Vec<f32>,
+}
+
+// the file on disk; embedder is the name() of whatever produced the vectors
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexFile {
+    embedder: String,
+    dimensions: usize,
+    entries: Vec<IndexEntry>,
+}
+
+// brute force cosine search over everything in memory; an archive of documents is small enough
+// that this beats keeping an ann structure in sync
+pub struct SemanticIndex {
+    embedder: Arc<dyn Embedder>,
+    path: PathBuf,
+    entries: RwLock<BTreeMap<i64, IndexEntry>>, // by id
+    // two saves must never interleave on disk
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl SemanticIndex {
+    // loads the index at path; an index built by a different embedder is dropped, the caller
+    // re-indexes whatever is missing
+    pub fn open(path: impl AsRef<Path>, embedder: Arc<dyn Embedder>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let entries = if path.exists() {
+            let raw = std::fs::read(&path)?;
+            let file: IndexFile = serde_json::from_slice(&raw).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("vector index {:?} is corrupt: {}", path, e))
+            })?;
+
+            if file.embedder == embedder.name() && file.dimensions == embedder.dimensions() {
+                file.entries.into_iter().map(|entry| (entry.id, entry)).collect()
+            } else {
+                println!(
+                    "Vector index {:?} was built with {}, now using {}; starting over",
+                    path,
+                    file.embedder,
+                    embedder.name()
+                );
+                BTreeMap::new()
+            }
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(SemanticIndex {
+            embedder,
+            path,
+            entries: RwLock::new(entries),
+            save_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn embedder_name(&self) -> String {
+        self.embedder.name()
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // ids of every record that has a vector
+    pub fn ids(&self) -> HashSet<i64> {
+        self.read().keys().copied().collect()
+    }
+
+    // embeds and stores the documents (replacing older vectors for the same ids), then saves
+    pub async fn index_documents(&self, documents: Vec<IndexedDocument>) -> Result<()> {
+        self.replace(|_| false, documents).await?;
+        Ok(())
+    }
+
+    // one batch of changes with a single save: drops every vector `remove` picks (looking at its id
+    // and metadata), then stores the documents. everything is embedded before anything changes, so
+    // a failing embedder leaves the index as it was. returns how many vectors were dropped
+    pub async fn replace(&self, remove: impl Fn(&IndexEntry) -> bool, documents: Vec<IndexedDocument>) -> Result<usize> {
+        let mut embedded = Vec::with_capacity(documents.len());
+        for batch in documents.chunks(EMBED_BATCH_SIZE) {
+            let texts: Vec<String> = batch.iter().map(|doc| doc.document.clone()).collect();
+            let embeddings = self.embedder.embed(&texts).await?;
+            if embeddings.len() != batch.len() {
+                return Err(Error::Embedding(format!(
+                    "asked for {} embeddings, got {}",
+                    batch.len(),
+                    embeddings.len()
+                )));
+            }
+            embedded.extend(batch.iter().zip(embeddings).map(|(doc, embedding)| IndexEntry {
+                id: doc.id,
+                document: doc.document.clone(),
+                metadata: doc.metadata.clone(),
+                embedding,
+            }));
+        }
+
+        let removed = {
+            let mut entries = self.write();
+            let before = entries.len();
+            entries.retain(|_, entry| !remove(entry));
+            let removed = before - entries.len();
+            entries.extend(embedded.into_iter().map(|entry| (entry.id, entry)));
+            removed
+        };
+        if removed > 0 || !documents.is_empty() {
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    // the distinct integer values of one metadata field, e.g. the records a passage index covers
+    pub fn metadata_ids(&self, field: &str) -> HashSet<i64> {
+        self.read().values().filter_map(|entry| entry.metadata.get(field).and_then(Value::as_i64)).collect()
+    }
+
+    // empties the index (on disk too), for rebuilds
+    pub async fn clear(&self) -> Result<()> {
+        self.write().clear();
+        self.save().await
+    }
+
+    // the k records closest to the query, nearest first; distance is 1 - cosine similarity
+    pub async fn search(&self, query: &str, k: usize) -> Result<VectorSearchResult> {
+        let query_vector = self
+            .embedder
+            .embed(&[query.to_string()])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Embedding("embedder returned nothing for the query".to_string()))?;
+
+        let entries = self.read();
+        let mut scored: Vec<(f32, &IndexEntry)> = entries
+            .values()
+            .map(|entry| (1.0 - dot(&query_vector, &entry.embedding), entry))
+            .collect();
+        scored.sort_by(|a, b| a.0.total_cmp(&b.0));
+        scored.truncate(k);
+
+        Ok(VectorSearchResult {
+            ids: vec![scored.iter().map(|(_, entry)| entry.id.to_string()).collect()],
+            documents: vec![scored.iter().map(|(_, entry)| entry.document.clone()).collect()],
+            metadatas: vec![scored.iter().map(|(_, entry)| entry.metadata.clone()).collect()],
+            distances: vec![scored.iter().map(|(distance, _)| *distance).collect()],
+        })
+    }
+
+    // k-means over the stored vectors, cluster number -> metadata of its members
+    pub fn clusters(&self, n: usize) -> BTreeMap<usize, Vec<Value>> {
+        let entries = self.read();
+        let vectors: Vec<&[f32]> = entries.values().map(|entry| entry.embedding.as_slice()).collect();
+
+        let mut clusters: BTreeMap<usize, Vec<Value>> = BTreeMap::new();
+        for (entry, label) in entries.values().zip(kmeans(&vectors, n)) {
+            clusters.entry(label).or_default().push(entry.metadata.clone());
+        }
+        clusters
+    }
+
+    // writes to a temp file and renames it over the index, so a crash never leaves half a file
+    pub async fn save(&self) -> Result<()> {
+        let _guard = self.save_lock.lock().await;
+
+        let bytes = {
+            let entries = self.read();
+            let file = IndexFile {
+                embedder: self.embedder.name(),
+                dimensions: self.embedder.dimensions(),
+                entries: entries.values().cloned().collect(),
+            };
+            serde_json::to_vec(&file).map_err(|e| Error::Io(std::io::Error::other(e)))?
+        };
+
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, bytes).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        Ok(())
+    }
+
+    // a panic while holding the lock doesn't make the vectors wrong, so keep going
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, BTreeMap<i64, IndexEntry>> {
+        self.entries.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, BTreeMap<i64, IndexEntry>> {
+        self.entries.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+
+// helper functions
+pub(crate) fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+// plain lloyd's with farthest-point seeding from the first vector, so the same index always gives
+// the same clusters
+fn kmeans(vectors: &[&[f32]], n: usize) -> Vec<usize> {
+    let n = n.clamp(1, vectors.len().max(1));
+    if vectors.is_empty() {
+        return Vec::new();
+    }
+
+    let mut centroids: Vec<Vec<f32>> = vec![vectors[0].to_vec()];
+    while centroids.len() < n {
+        let farthest = vectors
+            .iter()
+            .max_by(|a, b| {
+                let da = centroids.iter().map(|c| squared_distance(a, c)).fold(f32::INFINITY, f32::min);
+                let db = centroids.iter().map(|c| squared_distance(b, c)).fold(f32::INFINITY, f32::min);
+                da.total_cmp(&db)
+            })
+            .map(|v| v.to_vec())
+            .unwrap_or_default();
+        centroids.push(farthest);
+    }
+
+    let mut labels = vec![0; vectors.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut changed = false;
+        for (label, vector) in labels.iter_mut().zip(vectors) {
+            let nearest = centroids
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| squared_distance(vector, a).total_cmp(&squared_distance(vector, b)))
+                .map(|(i, _)| i)
+                .unwrap_or(0);
+            if *label != nearest {
+                *label = nearest;
+                changed = true;
+            }
+        }
+
+        // move every centroid to the mean of its members; an empty cluster keeps its old centroid
+        for (i, centroid) in centroids.iter_mut().enumerate() {
+            let members: Vec<&&[f32]> = vectors.iter().zip(&labels).filter(|(_, l)| **l == i).map(|(v, _)| v).collect();
+            if members.is_empty() {
+                continue;
+            }
+            centroid.iter_mut().for_each(|x| *x = 0.0);
+            for member in &members {
+                for (c, x) in centroid.iter_mut().zip(member.iter()) {
+                    *c += x / members.len() as f32;
+                }
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+    labels
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::embedder::HashEmbedder;
+    use async_trait::async_trait;
+    use serde_json::json;
+
+    // an embedder that is always down, going by the given name
+    struct Unreachable(&'static str);
+
+    #[async_trait]
+    impl Embedder for Unreachable {
+        async fn embed(&self, _texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            Err(Error::Embedding("connection refused".to_string()))
+        }
+
+        fn dimensions(&self) -> usize {
+            64
+        }
+
+        fn name(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("index-test-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    fn doc(id: i64, text: &str) -> IndexedDocument {
+        IndexedDocument { id, document: text.to_string(), metadata: json!({ "id": id, "archive_id": id / 10 }) }
+    }
+
+    fn hash(dimensions: usize) -> Arc<dyn Embedder> {
+        Arc::new(HashEmbedder::new(dimensions))
+    }
+
+    async fn filled(path: &Path) -> SemanticIndex {
+        let index = SemanticIndex::open(path, hash(64)).unwrap();
+        index
+            .index_documents(vec![
+                doc(10, "photosynthesis in green plants"),
+                doc(11, "the french revolution"),
+                doc(20, "plant cells and photosynthesis"),
+            ])
+            .await
+            .unwrap();
+        index
+    }
+
+    #[tokio::test]
+    async fn searches_nearest_first() {
+        let path = temp_path();
+        let index = filled(&path).await;
+
+        let result = index.search("photosynthesis in plants", 2).await.unwrap();
+        assert_eq!(result.ids[0].len(), 2);
+        assert!(result.ids[0].iter().all(|id| id != "11"));
+        assert!(result.distances[0][0] <= result.distances[0][1]);
+        assert_eq!(result.metadatas[0].len(), 2);
+
+        let everything = index.search("revolution", 10).await.unwrap();
+        assert_eq!(everything.ids[0].len(), 3);
+        assert_eq!(everything.ids[0][0], "11");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn upserts_by_id() {
+        let path = temp_path();
+        let index = filled(&path).await;
+        index.index_documents(vec![doc(11, "the industrial revolution")]).await.unwrap();
+
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.ids(), HashSet::from([10, 11, 20]));
+        assert_eq!(index.search("industrial", 1).await.unwrap().documents[0][0], "the industrial revolution");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replaces_a_batch_and_saves_it() {
+        let path = temp_path();
+        let index = filled(&path).await;
+
+        // everything under archive 1 goes, a new one comes in
+        let removed = index
+            .replace(|entry| entry.metadata["archive_id"] == json!(1), vec![doc(30, "volcanoes")])
+            .await
+            .unwrap();
+        assert_eq!(removed, 2);
+        assert_eq!(index.ids(), HashSet::from([20, 30]));
+        assert_eq!(index.metadata_ids("archive_id"), HashSet::from([2, 3]));
+
+        let reopened = SemanticIndex::open(&path, hash(64)).unwrap();
+        assert_eq!(reopened.ids(), HashSet::from([20, 30]));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_failing_embedder_changes_nothing() {
+        let path = temp_path();
+        filled(&path).await;
+
+        // the same name and size as the embedder that built it, so the vectors are kept
+        let index = SemanticIndex::open(&path, Arc::new(Unreachable("hash/64"))).unwrap();
+        assert_eq!(index.len(), 3);
+        let result = index.replace(|_| true, vec![doc(40, "anything")]).await;
+        assert!(matches!(result, Err(Error::Embedding(_))));
+        assert_eq!(index.len(), 3);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn another_embedder_starts_over() {
+        let path = temp_path();
+        filled(&path).await;
+
+        // other dimensions, or another model with the same ones
+        assert!(SemanticIndex::open(&path, hash(32)).unwrap().is_empty());
+        assert!(SemanticIndex::open(&path, Arc::new(Unreachable("remote/64"))).unwrap().is_empty());
+        assert_eq!(SemanticIndex::open(&path, hash(64)).unwrap().len(), 3);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_corrupt_index_is_an_error() {
+        let path = temp_path();
+        std::fs::write(&path, b"{\"embedder\": \"hash/64\", \"entries\": [").unwrap();
+
+        match SemanticIndex::open(&path, hash(64)) {
+            Err(Error::Io(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+                assert!(e.to_string().contains("corrupt"));
+            }
+            Err(e) => panic!("expected an io error, got {}", e),
+            Ok(_) => panic!("a corrupt index opened"),
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn clusters_keep_similar_records_together() {
+        let path = temp_path();
+        let index = filled(&path).await;
+
+        let clusters = index.clusters(2);
+        assert_eq!(clusters.values().map(Vec::len).sum::<usize>(), 3);
+        let french = clusters.values().find(|members| members.iter().any(|m| m["id"] == json!(11))).unwrap();
+        assert_eq!(french.len(), 1);
+        assert!(index.clusters(5).len() <= 3);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,4 @@
+pub mod embedder;
+pub mod index;
+pub mod passages;
+pub mod sync;
@@ -0,0 +1,231 @@
+// passages.rs: the passage vectors, kept in sqlite on the passage row they belong to. a record's
+// passages are embedded once, when they're cut; re-cutting them deletes the rows, vectors and all
+use rusqlite::{Connection, TransactionBehavior};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::database::database::unix_now;
+use crate::database::repository::ArchiveRepository;
+use crate::error::{run_blocking, Error, Result};
+use crate::vector::embedder::Embedder;
+use crate::vector::index::{dot, EMBED_BATCH_SIZE};
+
+
+// brute force cosine search over the passage table; nothing but the ids and distances of one
+// query's candidates is ever held in memory
+pub struct PassageIndex {
+    embedder: Arc<dyn Embedder>,
+    repo: ArchiveRepository,
+}
+
+impl PassageIndex {
+    pub fn new(repo: ArchiveRepository, embedder: Arc<dyn Embedder>) -> Self {
+        PassageIndex { embedder, repo }
+    }
+
+    pub fn embedder_name(&self) -> String {
+        self.embedder.name()
+    }
+
+    // passages with a vector from the current embedder
+    pub async fn len(&self) -> Result<usize> {
+        let embedder = self.embedder.name();
+        self.with_db(move |conn| {
+            let count: i64 = conn.query_row("SELECT COUNT(*) FROM passage WHERE embedder = ?1", [embedder], |row| row.get(0))?;
+            Ok(count as usize)
+        })
+        .await
+    }
+
+    // records with a passage the current embedder hasn't seen yet: new ones, or every one after
+    // switching embedders
+    pub async fn unembedded_records(&self) -> Result<HashSet<i64>> {
+        let embedder = self.embedder.name();
+        self.with_db(move |conn| {
+            let mut stmt = conn.prepare("SELECT DISTINCT archive_id FROM passage WHERE embedder IS NOT ?1")?;
+            let ids = stmt.query_map([embedder], |row| row.get(0))?.collect::<rusqlite::Result<HashSet<i64>>>()?;
+            Ok(ids)
+        })
+        .await
+    }
+
+    // embeds every passage of these records that has no vector from the current embedder, so an
+    // edit that only touched the metadata costs nothing here. each batch is written as soon as it's
+    // embedded, and a retry picks up after the last one that made it. returns how many were embedded
+    pub async fn embed_records(&self, archive_ids: Vec<i64>) -> Result<usize> {
+        let embedder = self.embedder.name();
+        let pending: Vec<(i64, String)> = self
+            .with_db(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, text FROM passage WHERE archive_id = ?1 AND embedder IS NOT ?2 ORDER BY position",
+                )?;
+                let mut pending = Vec::new();
+                for id in archive_ids {
+                    let rows = stmt.query_map((id, &embedder), |row| Ok((row.get(0)?, row.get(1)?)))?;
+                    pending.extend(rows.collect::<rusqlite::Result<Vec<(i64, String)>>>()?);
+                }
+                Ok(pending)
+            })
+            .await?;
+
+        for batch in pending.chunks(EMBED_BATCH_SIZE) {
+            let texts: Vec<String> = batch.iter().map(|(_, text)| text.clone()).collect();
+            let embeddings = self.embedder.embed(&texts).await?;
+            if embeddings.len() != batch.len() {
+                return Err(Error::Embedding(format!("asked for {} embeddings, got {}", batch.len(), embeddings.len())));
+            }
+
+            let (embedder, ids) = (self.embedder.name(), batch.iter().map(|(id, _)| *id).collect::<Vec<_>>());
+            self.with_db(move |conn| {
+                let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                for (id, embedding) in ids.iter().zip(&embeddings) {
+                    tx.execute(
+                        "UPDATE passage SET embedding = ?1, embedder = ?2, embedded_at = ?3 WHERE id = ?4",
+                        (to_blob(embedding), &embedder, unix_now(), id),
+                    )?;
+                }
+                tx.commit()?;
+                Ok(())
+            })
+            .await?;
+        }
+        Ok(pending.len())
+    }
+
+    // forgets every passage vector, for rebuilds
+    pub async fn clear(&self) -> Result<()> {
+        self.with_db(|conn| {
+            conn.execute("UPDATE passage SET embedding = NULL, embedder = NULL, embedded_at = NULL WHERE embedding IS NOT NULL", ())?;
+            Ok(())
+        })
+        .await
+    }
+
+    // the k passages closest to the query, nearest first, as (passage id, distance); distance is
+    // 1 - cosine similarity. with a record id only that record's passages are looked at
+    pub async fn search(&self, query: &str, k: usize, record_id: Option<i64>) -> Result<Vec<(i64, f32)>> {
+        let query_vector = self
+            .embedder
+            .embed(&[query.to_string()])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Embedding("embedder returned nothing for the query".to_string()))?;
+
+        let embedder = self.embedder.name();
+        self.with_db(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, embedding FROM passage WHERE embedder = ?1 AND (?2 IS NULL OR archive_id = ?2)",
+            )?;
+            let mut rows = stmt.query((&embedder, record_id))?;
+            let mut scored: Vec<(i64, f32)> = Vec::new();
+            while let Some(row) = rows.next()? {
+                let blob = row.get_ref(1)?.as_blob().map_err(rusqlite::Error::from)?;
+                scored.push((row.get(0)?, 1.0 - dot(&query_vector, &from_blob(blob))));
+            }
+            scored.sort_by(|a, b| a.1.total_cmp(&b.1));
+            scored.truncate(k);
+            Ok(scored)
+        })
+        .await
+    }
+
+    // runs a closure against a pooled connection on the blocking pool
+    async fn with_db<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let repo = self.repo.clone();
+        run_blocking(move || {
+            let mut conn = repo.conn()?;
+            f(&mut conn)
+        })
+        .await
+    }
+}
+
+
+// helper functions
+// a vector as little endian f32s, the way the embedding column holds it
+fn to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4).map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::database::load_passages;
+    use crate::extract::extract::{DocumentFormat, ExtractedDocument};
+    use crate::nlp::engine::{ExtractedMetaData, FileRecord};
+    use crate::solana::solana::MemoReceipt;
+    use crate::vector::embedder::HashEmbedder;
+
+    fn insert(repo: &ArchiveRepository, n: usize, pages: &[&str]) -> i64 {
+        let metadata = ExtractedMetaData {
+            title: format!("Book {}", n),
+            difficulty: "Beginner".to_string(),
+            genre: "Science".to_string(),
+            summary: "a book".to_string(),
+            resource_type: "Textbook".to_string(),
+            keywords: vec!["science".to_string()],
+            topics: Vec::new(),
+            language: "en".to_string(),
+            authors: Vec::new(),
+        };
+        let file = FileRecord { file_hash: format!("hash-{}", n), file_cid: format!("cid-{}", n) };
+        let anchor = MemoReceipt { signature: format!("sig-{}", n), slot: None, block_time: None };
+        let document = ExtractedDocument { format: DocumentFormat::Pdf, pages: pages.iter().map(|p| p.to_string()).collect(), paged: true };
+        repo.insert(&metadata, &file, &anchor, &document, None).unwrap()
+    }
+
+    #[test]
+    fn blobs_round_trip() {
+        let vector = vec![0.5, -1.25, 3.0e-7, f32::MAX];
+        assert_eq!(from_blob(&to_blob(&vector)), vector);
+    }
+
+    #[tokio::test]
+    async fn embeds_each_passage_once_and_finds_it() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let index = PassageIndex::new(repo.clone(), Arc::new(HashEmbedder::new(256)));
+        let plants = insert(&repo, 1, &["Chlorophyll absorbs light in the leaves.", "Roots take up water."]);
+        let cells = insert(&repo, 2, &["Mitosis splits one cell into two."]);
+        assert_eq!(index.unembedded_records().await.unwrap(), HashSet::from([plants, cells]));
+
+        assert_eq!(index.embed_records(vec![plants, cells]).await.unwrap(), 3);
+        assert_eq!(index.embed_records(vec![plants, cells]).await.unwrap(), 0);
+        assert_eq!(index.len().await.unwrap(), 3);
+        assert!(index.unembedded_records().await.unwrap().is_empty());
+
+        let chlorophyll = load_passages(&repo.conn().unwrap(), plants).unwrap()[0].id;
+        let hits = index.search("chlorophyll light leaves", 2, None).await.unwrap();
+        assert_eq!(hits[0].0, chlorophyll);
+        assert!(hits[0].1 < hits[1].1);
+
+        let only_cells = index.search("chlorophyll light leaves", 5, Some(cells)).await.unwrap();
+        assert_eq!(only_cells.len(), 1);
+        assert_ne!(only_cells[0].0, chlorophyll);
+    }
+
+    #[tokio::test]
+    async fn another_embedder_starts_over() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let id = insert(&repo, 1, &["Chlorophyll absorbs light."]);
+        PassageIndex::new(repo.clone(), Arc::new(HashEmbedder::new(64))).embed_records(vec![id]).await.unwrap();
+
+        let switched = PassageIndex::new(repo.clone(), Arc::new(HashEmbedder::new(128)));
+        assert_eq!(switched.len().await.unwrap(), 0);
+        assert_eq!(switched.unembedded_records().await.unwrap(), HashSet::from([id]));
+        assert!(switched.search("chlorophyll", 5, None).await.unwrap().is_empty());
+
+        switched.embed_records(vec![id]).await.unwrap();
+        switched.clear().await.unwrap();
+        assert_eq!(switched.unembedded_records().await.unwrap(), HashSet::from([id]));
+    }
+}
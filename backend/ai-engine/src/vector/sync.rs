@@ -0,0 +1,353 @@
+// sync.rs: keeps the vector index in step with the archive by draining the index_outbox table
+use rusqlite::{Connection, TransactionBehavior};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::database::database::{
+    complete_index_changes, enqueue_full_reindex, enqueue_index_change, fail_index_changes,
+    get_index_state, index_state_counts, load_record, pending_index_changes, ArchiveRecord, IndexChange,
+    IndexOp, IndexState,
+};
+use crate::database::repository::ArchiveRepository;
+use crate::error::{run_blocking, Error, Result};
+use crate::vector::index::{IndexedDocument, SemanticIndex};
+use crate::vector::passages::PassageIndex;
+
+
+// outbox rows handled per round
+const SYNC_BATCH_SIZE: usize = 64;
+
+// overall picture for the admin endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexSummary {
+    pub embedder: String,
+    pub vectors: usize,
+    pub passages: usize,
+    pub records: BTreeMap<String, i64>, // status -> count
+}
+
+// owns index consistency: uploads and edits only write the outbox (inside their own transaction),
+// this pushes those changes into the record index and the passage index in the background
+pub struct IndexSync {
+    index: Arc<SemanticIndex>,
+    passages: Arc<PassageIndex>,
+    repo: ArchiveRepository,
+    interval: Duration,
+    wake: Notify,
+}
+
+impl IndexSync {
+    pub fn new(index: Arc<SemanticIndex>, passages: Arc<PassageIndex>, repo: ArchiveRepository, interval: Duration) -> Self {
+        IndexSync { index, passages, repo, interval, wake: Notify::new() }
+    }
+
+    pub fn index(&self) -> &SemanticIndex {
+        &self.index
+    }
+
+    // passage vectors, stored on the passage rows
+    pub fn passages(&self) -> &PassageIndex {
+        &self.passages
+    }
+
+    // something was written to the outbox, don't wait for the next tick
+    pub fn notify(&self) {
+        self.wake.notify_one();
+    }
+
+    // the background loop; errors are logged and retried on the next round, never fatal
+    pub async fn run(&self) {
+        loop {
+            if let Err(e) = self.sync_pending().await {
+                println!("Vector index sync failed: {}", e);
+            }
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep(self.interval) => {}
+            }
+        }
+    }
+
+    // drains the outbox of every change that's due; returns how many records were synced. a batch
+    // that fails is tried again a record at a time, so one record the embedder chokes on only
+    // holds itself back (for the retry delay), not the 63 that came with it
+    pub async fn sync_pending(&self) -> Result<usize> {
+        let mut synced = 0;
+        loop {
+            let changes = self.with_db(|conn| pending_index_changes(conn, SYNC_BATCH_SIZE)).await?;
+            if changes.is_empty() {
+                return Ok(synced);
+            }
+
+            if changes.len() > 1 && self.apply(&changes).await.is_ok() {
+                synced += changes.len();
+                self.complete(changes).await?;
+                continue;
+            }
+            for change in changes {
+                match self.apply(std::slice::from_ref(&change)).await {
+                    Ok(()) => {
+                        synced += 1;
+                        self.complete(vec![change]).await?;
+                    }
+                    Err(e) => {
+                        println!("Could not index record {}: {}", change.archive_id, e);
+                        let message = e.to_string();
+                        self.with_db(move |conn| fail_index_changes(conn, &[change], &message)).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    // queues whatever the indexes and the archive disagree on: records without a vector (e.g. after
+    // switching embedders) or without passage vectors, and vectors whose record is gone or deleted
+    pub async fn reconcile(&self) -> Result<usize> {
+        let indexed = self.index.ids();
+        let unembedded = self.passages.unembedded_records().await?;
+        let queued = self
+            .with_db(move |conn| {
+                let mut stmt = conn.prepare("SELECT id FROM archive WHERE deleted_at IS NULL")?;
+                let ids = stmt
+                    .query_map([], |row| row.get::<_, i64>(0))?
+                    .collect::<rusqlite::Result<HashSet<_>>>()?;
+                drop(stmt);
+
+                let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                let mut queued = 0;
+                for id in ids.iter().filter(|id| !indexed.contains(id) || unembedded.contains(id)) {
+                    enqueue_index_change(&tx, *id, IndexOp::Upsert)?;
+                    queued += 1;
+                }
+                for id in indexed.iter().filter(|id| !ids.contains(id)) {
+                    enqueue_index_change(&tx, *id, IndexOp::Delete)?;
+                    queued += 1;
+                }
+                tx.commit()?;
+                Ok(queued)
+            })
+            .await?;
+
+        if queued > 0 {
+            self.notify();
+        }
+        Ok(queued)
+    }
+
+    // throws the index away and queues every record in the archive again
+    pub async fn rebuild(&self) -> Result<usize> {
+        self.index.clear().await?;
+        self.passages.clear().await?;
+        let queued = self.with_db(enqueue_full_reindex).await?;
+        self.notify();
+        Ok(queued)
+    }
+
+    pub async fn status(&self, record_id: i64) -> Result<Option<IndexState>> {
+        self.with_db(move |conn| get_index_state(conn, record_id)).await
+    }
+
+    pub async fn summary(&self) -> Result<IndexSummary> {
+        let records = self.with_db(|conn| index_state_counts(conn)).await?;
+        Ok(IndexSummary {
+            embedder: self.index.embedder_name(),
+            vectors: self.index.len(),
+            passages: self.passages.len().await?,
+            records,
+        })
+    }
+
+    // one batch into the indexes: upserts are embedded together, deletes just drop the vectors.
+    // passage vectors go away with their rows when a record is deleted or re-cut, so only the
+    // passages still missing one get embedded
+    async fn apply(&self, changes: &[IndexChange]) -> Result<()> {
+        let upserts: Vec<i64> = changes.iter().filter(|c| c.op == IndexOp::Upsert).map(|c| c.archive_id).collect();
+        let records = self
+            .with_db(move |conn| {
+                let mut records = Vec::with_capacity(upserts.len());
+                for id in upserts {
+                    match load_record(conn, id) {
+                        Ok(record) => records.push(record),
+                        // deleted before we got to it, the delete op that follows handles the vector
+                        Err(Error::Database(rusqlite::Error::QueryReturnedNoRows)) => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(records)
+            })
+            .await?;
+
+        self.passages.embed_records(records.iter().map(|record| record.id).collect()).await?;
+
+        // one save for the whole batch
+        let deleted: HashSet<i64> =
+            changes.iter().filter(|c| c.op == IndexOp::Delete).map(|c| c.archive_id).collect();
+        self.index.replace(|entry| deleted.contains(&entry.id), records.iter().map(record_document).collect()).await?;
+        Ok(())
+    }
+
+    async fn complete(&self, changes: Vec<IndexChange>) -> Result<()> {
+        let embedder = self.index.embedder_name();
+        self.with_db(move |conn| complete_index_changes(conn, &changes, &embedder)).await
+    }
+
+    // runs a closure against a pooled connection on the blocking pool
+    async fn with_db<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let repo = self.repo.clone();
+        run_blocking(move || {
+            let mut conn = repo.conn()?;
+            f(&mut conn)
+        })
+        .await
+    }
+}
+
+// what the vector index keeps for a record: the text we embed and the record itself
+pub fn record_document(record: &ArchiveRecord) -> IndexedDocument {
+    let mut document = format!("{} - {} - {}\n{}", record.title, record.difficulty, record.genre, record.summary);
+    let terms: Vec<&str> = record.keywords.iter().chain(&record.topics).map(String::as_str).collect();
+    if !terms.is_empty() {
+        document.push_str(&format!("\n{}", terms.join(", ")));
+    }
+
+    IndexedDocument {
+        id: record.id,
+        document,
+        metadata: serde_json::to_value(record).unwrap_or_default(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::extract::{DocumentFormat, ExtractedDocument};
+    use crate::nlp::engine::{ExtractedMetaData, FileRecord};
+    use crate::solana::solana::MemoReceipt;
+    use crate::vector::embedder::{Embedder, HashEmbedder};
+    use async_trait::async_trait;
+    use std::path::PathBuf;
+
+    // the hash embedder, except it fails any batch with "poison" in it
+    struct PoisonEmbedder(HashEmbedder);
+
+    #[async_trait]
+    impl Embedder for PoisonEmbedder {
+        async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            if texts.iter().any(|text| text.to_lowercase().contains("poison")) {
+                return Err(Error::Embedding("the model refused".to_string()));
+            }
+            self.0.embed(texts).await
+        }
+
+        fn dimensions(&self) -> usize {
+            self.0.dimensions()
+        }
+
+        fn name(&self) -> String {
+            self.0.name()
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("index-sync-test-{}-{}", uuid::Uuid::new_v4(), name))
+    }
+
+    fn index_sync(repo: &ArchiveRepository, files: &mut Vec<PathBuf>) -> IndexSync {
+        let index = temp_path("index.json");
+        files.push(index.clone());
+        let embedder: Arc<dyn Embedder> = Arc::new(PoisonEmbedder(HashEmbedder::new(64)));
+        IndexSync::new(
+            Arc::new(SemanticIndex::open(&index, embedder.clone()).unwrap()),
+            Arc::new(PassageIndex::new(repo.clone(), embedder)),
+            repo.clone(),
+            Duration::from_secs(60),
+        )
+    }
+
+    fn insert(repo: &ArchiveRepository, n: usize, title: &str) -> i64 {
+        let metadata = ExtractedMetaData {
+            title: title.to_string(),
+            difficulty: "Beginner".to_string(),
+            genre: "Science".to_string(),
+            summary: format!("all about {}", title),
+            resource_type: "Textbook".to_string(),
+            keywords: vec!["science".to_string()],
+            topics: Vec::new(),
+            language: "en".to_string(),
+            authors: Vec::new(),
+        };
+        let file = FileRecord { file_hash: format!("hash-{}", n), file_cid: format!("cid-{}", n) };
+        let anchor = MemoReceipt { signature: format!("sig-{}", n), slot: None, block_time: None };
+        let document = ExtractedDocument { format: DocumentFormat::Txt, pages: vec![format!("{} text", title)], paged: false };
+        repo.insert(&metadata, &file, &anchor, &document, None).unwrap()
+    }
+
+    fn cleanup(files: Vec<PathBuf>) {
+        for file in files {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    #[tokio::test]
+    async fn one_bad_record_does_not_hold_back_the_batch() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut files = Vec::new();
+        let sync = index_sync(&repo, &mut files);
+        let good = insert(&repo, 1, "Photosynthesis");
+        let bad = insert(&repo, 2, "Poison ivy");
+        let other = insert(&repo, 3, "Cell division");
+
+        assert_eq!(sync.sync_pending().await.unwrap(), 2);
+        assert_eq!(sync.index().ids(), HashSet::from([good, other]));
+        assert_eq!(sync.passages().unembedded_records().await.unwrap(), HashSet::from([bad]));
+
+        let failed = sync.status(bad).await.unwrap().unwrap();
+        assert_eq!((failed.status.as_str(), failed.attempts), ("failed", 1));
+        assert_eq!(sync.status(good).await.unwrap().unwrap().status, "indexed");
+
+        // waiting out its retry delay, so the next round has nothing to do
+        assert_eq!(sync.sync_pending().await.unwrap(), 0);
+        cleanup(files);
+    }
+
+    #[tokio::test]
+    async fn reconcile_queues_what_the_index_is_missing() {
+        let repo = ArchiveRepository::in_memory().unwrap();
+        let mut files = Vec::new();
+        let sync = index_sync(&repo, &mut files);
+        let kept = insert(&repo, 1, "Photosynthesis");
+        let dropped = insert(&repo, 2, "Cell division");
+        sync.sync_pending().await.unwrap();
+        assert_eq!(sync.reconcile().await.unwrap(), 0);
+
+        // one vector lost, one left behind for a record that no longer exists
+        sync.index().replace(|entry| entry.id == dropped, Vec::new()).await.unwrap();
+        let stray = IndexedDocument { id: 99, document: "gone".to_string(), metadata: serde_json::json!({}) };
+        sync.index().index_documents(vec![stray]).await.unwrap();
+
+        assert_eq!(sync.reconcile().await.unwrap(), 2);
+        let changes = sync.with_db(|conn| pending_index_changes(conn, 64)).await.unwrap();
+        let queued: Vec<(i64, IndexOp)> = changes.iter().map(|c| (c.archive_id, c.op)).collect();
+        assert!(queued.contains(&(dropped, IndexOp::Upsert)) && queued.contains(&(99, IndexOp::Delete)), "{:?}", queued);
+
+        sync.sync_pending().await.unwrap();
+        assert_eq!(sync.index().ids(), HashSet::from([kept, dropped]));
+        assert_eq!(sync.reconcile().await.unwrap(), 0);
+
+        // passage vectors gone, e.g. after a rebuild that never finished
+        sync.passages().clear().await.unwrap();
+        assert_eq!(sync.reconcile().await.unwrap(), 2);
+        sync.sync_pending().await.unwrap();
+        assert_eq!(sync.passages().len().await.unwrap(), 2);
+        assert_eq!(sync.reconcile().await.unwrap(), 0);
+        cleanup(files);
+    }
+}
@@ -7,9 +7,6 @@ echo "[*] Running install.sh (dependencies setup)..."
 REPO_ROOT="$(cd "$(dirname "$0")/../.." && pwd)"
 FRONTEND_DIR="$REPO_ROOT/blockscribe-ai"
 BACKEND_DIR="$REPO_ROOT/blockscribe-ai/backend"
-VECTORED_DIR="$BACKEND_DIR/ai-engine/src/vectored"
-REQ_FILE="${VECTORED_DIR}/requirements.txt"
-VENV_DIR="$BACKEND_DIR/ai-engine/.venv"
 
 # --- Install system deps ---
 echo "[*] Installing system dependencies (apt)..."
@@ -36,28 +33,6 @@ else
     echo "    Solana CLI already installed."
 fi
 
-# --- Setup Python virtual environment ---
-if [[ ! -d "$VENV_DIR" ]]; then
-    echo "[*] Creating Python virtual environment..."
-    python3 -m venv "$VENV_DIR"
-fi
-source "$VENV_DIR/bin/activate"
-
-echo "[*] Upgrading pip..."
-python -m pip install --upgrade pip
-
-if [[ ! -f "$REQ_FILE" ]]; then
-    echo "[*] Creating default requirements.txt..."
-    cat > "$REQ_FILE" <<'REQ'
-fastapi
-uvicorn[standard]
-pydantic
-REQ
-fi
-
-echo "[*] Installing Python requirements..."
-python -m pip install -r "$REQ_FILE"
-
 # --- Install Node.js deps ---
 if [[ -d "$FRONTEND_DIR" ]]; then
     cd "$FRONTEND_DIR"
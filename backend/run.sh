@@ -4,7 +4,6 @@ set -euo pipefail
 # --- Config ---
 IPFS_LOG="ipfs.log"
 SOLANA_LOG="solana.log"
-PYTHON_LOG="vector_service.log"
 CARGO_LOG="cargo_server.log"
 NPM_LOG="npm_server.log"
 TAIL_PID=""
@@ -13,9 +12,6 @@ TAIL_PID=""
 REPO_ROOT="$(cd "$(dirname "$0")/../.." && pwd)"
 FRONTEND_DIR="$REPO_ROOT/blockscribe-ai"
 BACKEND_DIR="$REPO_ROOT/blockscribe-ai/backend"
-VECTORED_DIR="$BACKEND_DIR/ai-engine/src/vectored"
-VECTORED_PY="${VECTORED_DIR}/vector_service.py"
-VENV_DIR="$BACKEND_DIR/ai-engine/.venv"
 CARGO_PROJECT_DIR="$BACKEND_DIR/ai-engine/src/bin"
 
 # --- Cleanup ---
@@ -23,7 +19,6 @@ cleanup() {
     echo "[*] Stopping services..."
     pkill -f "ipfs daemon" || true
     pkill -f "solana-test-validator" || true
-    pkill -f "vector_service.py" || true
     pkill -f "cargo run" || true
     pkill -f "npm run dev" || true
     [[ -n "$TAIL_PID" ]] && kill "$TAIL_PID" 2>/dev/null || true
@@ -61,6 +56,8 @@ echo "    ✅ Solana ready"
 # --- Cargo server ---
 if [[ -d "$CARGO_PROJECT_DIR" ]]; then
     echo "[*] Starting Cargo server..."
+    # we always run against the local test validator here, so let the server fund its own keypair
+    export SOLANA_AIRDROP="${SOLANA_AIRDROP:-true}"
     (cd "$CARGO_PROJECT_DIR" && nohup cargo run >"../$CARGO_LOG" 2>&1 &)
     sleep 5
     curl -sSf http://127.0.0.1:8000/ >/dev/null && \
@@ -86,22 +83,9 @@ else
     cleanup
 fi
 
-# --- Python service ---
-if [[ -f "$VECTORED_PY" ]]; then
-    source "$VENV_DIR/bin/activate"
-    echo "[*] Starting Python vector service..."
-    nohup python "$VECTORED_PY" >"$PYTHON_LOG" 2>&1 &
-    sleep 3
-    curl -sSf http://127.0.0.1:8001/docs >/dev/null && \
-        echo "    ✅ Python service ready" || \
-        { echo "❌ Python service failed (check $PYTHON_LOG)"; cleanup; }
-else
-    echo "❌ Python service file not found at $VECTORED_PY."
-    cleanup
-fi
 # --- Tail logs ---
 echo "[*] Tailing logs..."
-: > "$IPFS_LOG" "$SOLANA_LOG" "$PYTHON_LOG" "$CARGO_LOG" "$NPM_LOG" || true
-tail -f "$IPFS_LOG" "$SOLANA_LOG" "$PYTHON_LOG" "$CARGO_LOG" "$NPM_LOG" &
+: > "$IPFS_LOG" "$SOLANA_LOG" "$CARGO_LOG" "$NPM_LOG" || true
+tail -f "$IPFS_LOG" "$SOLANA_LOG" "$CARGO_LOG" "$NPM_LOG" &
 TAIL_PID=$!
 wait
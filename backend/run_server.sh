@@ -4,7 +4,6 @@ set -euo pipefail
 # --- Config ---
 IPFS_LOG="ipfs.log"
 SOLANA_LOG="solana.log"
-PYTHON_LOG="vector_service.log"
 CARGO_LOG="cargo_server.log"
 NPM_LOG="npm_server.log"
 
@@ -14,10 +13,6 @@ TAIL_PID=""
 REPO_ROOT="$(cd "$(dirname "$0")/../.." && pwd)"
 FRONTEND_DIR="$REPO_ROOT/blockscribe-ai"
 BACKEND_DIR="$REPO_ROOT/blockscribe-ai/backend"
-VECTORED_DIR="$BACKEND_DIR/ai-engine/src/vectored"
-VECTORED_PY="${VECTORED_DIR}/vector_service.py"
-REQ_FILE="${VECTORED_DIR}/requirements.txt"
-VENV_DIR="$BACKEND_DIR/ai-engine/.venv"
 CARGO_PROJECT_DIR="$BACKEND_DIR/ai-engine/src/bin"
 
 # --- Cleanup ---
@@ -30,9 +25,6 @@ cleanup() {
     echo "    Stopping Solana validator..."
     pkill -f "solana-test-validator" || true
 
-    echo "    Stopping Python vector service..."
-    pkill -f "vector_service.py" || true
-
     echo "    Stopping Cargo server..."
     pkill -f "cargo run" || true
 
@@ -78,52 +70,11 @@ done
 echo "    ✅ Solana ready"
 
 
-# --- Python virtual environment setup ---
-if [[ ! -d "$VENV_DIR" ]]; then
-    echo "[*] Creating Python virtual environment at $VENV_DIR ..."
-    python3 -m venv "$VENV_DIR"
-fi
-source "$VENV_DIR/bin/activate"
-
-echo "[*] Upgrading pip..."
-python -m pip install --upgrade pip
-
-if [[ ! -f "$REQ_FILE" ]]; then
-    echo "[*] Creating default requirements.txt at $REQ_FILE ..."
-    cat > "$REQ_FILE" <<'REQ'
-fastapi
-uvicorn[standard]
-pydantic
-# add other deps needed by utils.py
-REQ
-fi
-
-echo "[*] Installing dependencies from $REQ_FILE ..."
-python -m pip install -r "$REQ_FILE"
-
-# --- Start Python vector service ---
-if [[ -f "$VECTORED_PY" ]]; then
-    echo "[*] Starting Python vector service ($VECTORED_PY) on port 8001..."
-    nohup python "$VECTORED_PY" >"$PYTHON_LOG" 2>&1 &
-    echo "    Python service running (log: $PYTHON_LOG)"
-
-    sleep 3
-    if curl -sS --fail http://127.0.0.1:8001/docs >/dev/null 2>&1; then
-        echo "    ✅ Python service ready at http://127.0.0.1:8001"
-    else
-        echo "❌ Could not verify Python service on port 8001 (check $PYTHON_LOG for details)"
-        cleanup
-        exit 1
-    fi
-else
-    echo "❌ Python service file not found at $VECTORED_PY."
-    cleanup
-    exit 1
-fi
-
 # --- Start Cargo server ---
 if [[ -d "$CARGO_PROJECT_DIR" ]]; then
     echo "[*] Starting Cargo server in $CARGO_PROJECT_DIR ..."
+    # we always run against the local test validator here, so let the server fund its own keypair
+    export SOLANA_AIRDROP="${SOLANA_AIRDROP:-true}"
     (cd "$CARGO_PROJECT_DIR" && nohup cargo run >"../$CARGO_LOG" 2>&1 &)
     echo "    Cargo server running (log: $CARGO_LOG)"
 
@@ -170,14 +121,13 @@ else
 fi
 
 # --- Tail logs ---
-echo "[*] Tailing logs (IPFS, Solana, Python, Cargo, NPM)..."
+echo "[*] Tailing logs (IPFS, Solana, Cargo, NPM)..."
 : > "$IPFS_LOG" || true
 : > "$SOLANA_LOG" || true
-: > "$PYTHON_LOG" || true
 : > "$CARGO_LOG" || true
 : > "$NPM_LOG" || true
 
-tail -f "$IPFS_LOG" "$SOLANA_LOG" "$PYTHON_LOG" "$CARGO_LOG" "$NPM_LOG" &
+tail -f "$IPFS_LOG" "$SOLANA_LOG" "$CARGO_LOG" "$NPM_LOG" &
 TAIL_PID=$!
 
 wait
\ No newline at end of file
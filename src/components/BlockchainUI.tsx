@@ -36,7 +36,7 @@ export const BlockchainUI = () => {
     queryFn: async () => {
       console.log('Fetching metadata from API...');
       try {
-        const data = await api.getAllMetadata();
+        const data = await api.getAllMetadata({ sort: 'created', order: 'desc', limit: 6 });
         console.log('Metadata fetched successfully:', data);
         return data;
       } catch (error) {
@@ -81,26 +81,15 @@ export const BlockchainUI = () => {
     retry: false,
   });
 
-  // Parse metadata into documents
-  const recentDocuments: ArchiveRecord[] = allMetadata?.slice(0, 6).map(row => {
-    console.log('Parsing document row:', row);
-    return {
-      id: parseInt(row[0]),
-      genre: row[1],
-      title: row[2],
-      difficulty: row[3],
-      summary: row[4],
-      file_hash: row[5]?.split('|')[0] || '',
-      file_cid: row[5]?.split('|')[1] || '',
-    };
-  }) || [];
+  // the newest documents
+  const recentDocuments: ArchiveRecord[] = allMetadata?.results || [];
 
   // Extract genres from data
   const genres = genreData ? Object.keys(genreData) : [];
   console.log('Available genres:', genres);
   
   // Calculate stats from real data
-  const totalDocuments = allMetadata?.length || 0;
+  const totalDocuments = allMetadata?.total || 0;
   console.log('Total documents:', totalDocuments);
 
   // Log any errors
@@ -522,7 +511,12 @@ export const BlockchainUI = () => {
                     </div>
                   
                   <div className="flex space-x-2 pt-2">
-                    <Button size="sm" variant="outline" className="w-full">
+                    <Button
+                      size="sm"
+                      variant="outline"
+                      className="w-full"
+                      onClick={() => window.open(api.documentUrl(doc.id), '_blank', 'noopener')}
+                    >
                       <Zap className="w-4 h-4 mr-1" />
                       View
                     </Button>
@@ -1,5 +1,10 @@
 const API_BASE_URL = "http://127.0.0.1:5000";
 
+// files bigger than this go through the resumable upload api, a piece at a time
+const RESUMABLE_THRESHOLD = 32 * 1024 * 1024;
+const UPLOAD_CHUNK_SIZE = 8 * 1024 * 1024;
+const MAX_CHUNK_RETRIES = 5;
+
 console.log('API Base URL:', API_BASE_URL);
 
 export interface ArchiveRecord {
@@ -10,6 +15,54 @@ export interface ArchiveRecord {
   summary: string;
   file_hash: string;
   file_cid: string;
+  resource_type?: string;
+  language?: string;
+  keywords?: string[];
+  topics?: string[];
+  authors?: string[];
+  solana_signature?: string | null;
+  created_at?: number | null;
+  updated_at?: number | null;
+}
+
+export interface RecordPage {
+  total: number;
+  offset: number;
+  limit: number;
+  next_offset: number | null;
+  results: ArchiveRecord[];
+}
+
+export interface ListParams {
+  genre?: string;
+  difficulty?: string;
+  sort?: 'id' | 'title' | 'created';
+  order?: 'asc' | 'desc';
+  offset?: number;
+  limit?: number;
+}
+
+export interface JobStage {
+  stage: string;
+  status: 'pending' | 'running' | 'done' | 'failed';
+  attempts: number;
+  started_at: number | null;
+  finished_at: number | null;
+  error: string | null;
+}
+
+export interface JobReport {
+  id: string;
+  kind: 'upload' | 'reextract';
+  status: 'queued' | 'running' | 'succeeded' | 'failed';
+  stage: string | null;
+  original_filename: string | null;
+  record_id: number | null;
+  duplicate: boolean;
+  attempts: number;
+  error: string | null;
+  retry_at: number | null;
+  stages: JobStage[];
 }
 
 export interface SearchResult {
@@ -20,11 +73,17 @@ export interface SearchResult {
 }
 
 export const api = {
-  // Get all metadata
-  getAllMetadata: async (): Promise<string[][]> => {
-    console.log('API Call: GET /metadata');
+  // Get one page of metadata
+  getAllMetadata: async (params: ListParams = {}): Promise<RecordPage> => {
+    const query = new URLSearchParams(
+      Object.entries(params)
+        .filter(([, value]) => value !== undefined && value !== '')
+        .map(([key, value]) => [key, String(value)])
+    ).toString();
+    const url = query ? `${API_BASE_URL}/metadata?${query}` : `${API_BASE_URL}/metadata`;
+    console.log('API Call: GET', url);
     try {
-      const response = await fetch(`${API_BASE_URL}/metadata`);
+      const response = await fetch(url);
       console.log('Response status:', response.status);
       if (!response.ok) throw new Error(`Failed to fetch metadata: ${response.status} ${response.statusText}`);
       const data = await response.json();
@@ -137,22 +196,113 @@ export const api = {
     }
   },
 
-  // Upload file
+  // Get the progress of an upload job
+  getJob: async (id: string): Promise<JobReport> => {
+    console.log('API Call: GET /jobs/' + id);
+    try {
+      const response = await fetch(`${API_BASE_URL}/jobs/${id}`);
+      if (!response.ok) throw new Error(`Failed to fetch job: ${response.status} ${response.statusText}`);
+      return await response.json();
+    } catch (error) {
+      console.error('getJob error:', error);
+      throw error;
+    }
+  },
+
+  // Where an archived file is served from; the server checks it against the record's hash on the way out
+  documentUrl: (id: number, download: boolean = false): string =>
+    `${API_BASE_URL}/documents/${id}/content${download ? '?download=true' : ''}`,
+
+  // Poll an upload job until it succeeds or fails
+  waitForJob: async (id: string, intervalMs: number = 2000): Promise<JobReport> => {
+    for (;;) {
+      const job = await api.getJob(id);
+      if (job.status === 'succeeded' || job.status === 'failed') return job;
+      await new Promise((resolve) => setTimeout(resolve, intervalMs));
+    }
+  },
+
+  // Send a big file in pieces; after a dropped connection it carries on from the offset the server has
+  uploadResumable: async (file: File, chunkSize: number = UPLOAD_CHUNK_SIZE): Promise<Response> => {
+    console.log('API Call: POST /api/uploads', { fileName: file.name, fileSize: file.size });
+    const opened = await fetch(`${API_BASE_URL}/api/uploads`, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({ filename: file.name, content_type: file.type || null, size: file.size }),
+    });
+    if (!opened.ok) {
+      const error = await opened.json().catch(() => null);
+      throw new Error(error?.message ?? `Failed to start upload: ${opened.status} ${opened.statusText}`);
+    }
+    const session = await opened.json();
+    const sessionUrl = `${API_BASE_URL}/api/uploads/${session.id}`;
+
+    let offset = 0;
+    let failures = 0;
+    while (offset < file.size) {
+      try {
+        const response = await fetch(sessionUrl, {
+          method: "PATCH",
+          headers: { "Upload-Offset": String(offset), "Content-Type": "application/offset+octet-stream" },
+          body: file.slice(offset, offset + chunkSize),
+        });
+        if (response.ok) {
+          offset = (await response.json()).offset;
+          failures = 0;
+          continue;
+        }
+        // a conflict means we lost track of the offset, anything else won't get better by retrying
+        if (response.status !== 409 && response.status < 500) {
+          const error = await response.json().catch(() => null);
+          throw Object.assign(new Error(error?.message ?? `Failed to upload: ${response.status} ${response.statusText}`), { fatal: true });
+        }
+      } catch (error: any) {
+        if (error?.fatal) throw error;
+      }
+
+      if (++failures > MAX_CHUNK_RETRIES) throw new Error('Upload failed after several retries');
+      await new Promise((resolve) => setTimeout(resolve, 1000 * failures));
+      const status = await fetch(sessionUrl);
+      if (!status.ok) throw new Error(`Upload session lost: ${status.status} ${status.statusText}`);
+      offset = (await status.json()).offset;
+    }
+
+    return fetch(`${sessionUrl}/complete`, { method: "POST" });
+  },
+
+  // Upload file; resolves once the server has finished processing it
   uploadFile: async (file: File): Promise<any> => {
     console.log('API Call: POST /api/upload', { fileName: file.name, fileSize: file.size });
     try {
-      const formData = new FormData();
-      formData.append("file", file);
-      
-      const response = await fetch(`${API_BASE_URL}/api/upload`, {
-        method: "POST",
-        body: formData,
-      });
+      let response: Response;
+      if (file.size > RESUMABLE_THRESHOLD) {
+        response = await api.uploadResumable(file);
+      } else {
+        const formData = new FormData();
+        formData.append("file", file);
+
+        response = await fetch(`${API_BASE_URL}/api/upload`, {
+          method: "POST",
+          body: formData,
+        });
+      }
       console.log('Response status:', response.status);
-      if (!response.ok) throw new Error(`Failed to upload file: ${response.status} ${response.statusText}`);
-      const data = await response.json();
+      const data = await response.json().catch(() => null);
       console.log('Upload response:', data);
-      return data;
+
+      // every file of the request gets a result of its own; this one only sends one
+      const result = data?.results?.[0];
+      if (!response.ok || !result) {
+        throw new Error(result?.error?.message ?? `Failed to upload file: ${response.status} ${response.statusText}`);
+      }
+      if (result.status === 'failed') throw new Error(result.error?.message ?? 'Upload failed');
+      if (result.status === 'duplicate') return { ...data, ...result, duplicate: true, metadata: result.record };
+
+      // accepted: the file is processed in the background
+      const job = await api.waitForJob(result.job_id);
+      if (job.status === 'failed') throw new Error(job.error ?? 'Processing failed');
+      const record = await api.getMetadataById(job.record_id as number);
+      return { ...data, ...result, job, record_id: job.record_id, record, metadata: record };
     } catch (error) {
       console.error('uploadFile error:', error);
       throw error;